@@ -0,0 +1,199 @@
+use std::ops::{BitAnd, BitOr, BitOrAssign, Not};
+
+use crate::MAX_BOARD_SIZE;
+
+// A set of cells on a board of up to MAX_BOARD_SIZE x MAX_BOARD_SIZE,
+// one u32 per row with bit c set if (row, c) is in the set
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+pub struct Bitboard {
+  rows: [u32; MAX_BOARD_SIZE],
+}
+
+// all the columns of a row
+const ROW_MASK: u32 = (1 << MAX_BOARD_SIZE) - 1;
+
+impl Bitboard {
+  pub fn new() -> Self {
+    Bitboard {
+      rows: [0; MAX_BOARD_SIZE],
+    }
+  }
+
+  // every cell of a board with the given side
+  pub fn full(size: usize) -> Self {
+    let mut result = Bitboard::new();
+    for row in result.rows.iter_mut().take(size) {
+      *row = (1 << size) - 1;
+    }
+    result
+  }
+
+  pub fn get(&self, row: usize, col: usize) -> bool {
+    self.rows[row] & (1 << col) != 0
+  }
+
+  pub fn set(&mut self, row: usize, col: usize) {
+    self.rows[row] |= 1 << col;
+  }
+
+  // like set, but ignores cells outside a board with the given side
+  pub fn set_checked(&mut self, row: isize, col: isize, size: usize) {
+    if row >= 0 && col >= 0 && (row as usize) < size && (col as usize) < size {
+      self.set(row as usize, col as usize)
+    }
+  }
+
+  pub fn row(&self, row: usize) -> u32 {
+    self.rows[row]
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.rows.iter().all(|r| *r == 0)
+  }
+
+  pub fn count(&self) -> u32 {
+    self.rows.iter().map(|r| r.count_ones()).sum()
+  }
+
+  // Returns true if any of the row masks, shifted to start at (row, col),
+  // overlaps the set
+  pub fn intersects(&self, row: usize, col: usize, masks: &[u32]) -> bool {
+    masks
+      .iter()
+      .enumerate()
+      .any(|(ix, mask)| self.rows[row + ix] & (mask << col) != 0)
+  }
+
+  // Adds the row masks, shifted to start at (row, col), to the set
+  pub fn insert(&mut self, row: usize, col: usize, masks: &[u32]) {
+    for (ix, mask) in masks.iter().enumerate() {
+      self.rows[row + ix] |= mask << col;
+    }
+  }
+
+  // The cells of passable connected by edges to a cell of the set,
+  // through cells of passable
+  pub fn flood_fill(&self, passable: &Bitboard) -> Bitboard {
+    let mut result = *self & *passable;
+    loop {
+      let mut next = result;
+      for row in 0..MAX_BOARD_SIZE {
+        let mut grown = result.rows[row] | (result.rows[row] << 1) | (result.rows[row] >> 1);
+        if row > 0 {
+          grown |= result.rows[row - 1];
+        }
+        if row + 1 < MAX_BOARD_SIZE {
+          grown |= result.rows[row + 1];
+        }
+        next.rows[row] = grown & passable.rows[row];
+      }
+      if next == result {
+        return result;
+      }
+      result = next;
+    }
+  }
+
+  // (row, col) of the cells in the set, in row major order
+  pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+    self.rows.iter().enumerate().flat_map(|(row, bits)| {
+      let mut bits = *bits;
+      std::iter::from_fn(move || {
+        if bits == 0 {
+          None
+        } else {
+          let col = bits.trailing_zeros() as usize;
+          bits &= bits - 1;
+          Some((row, col))
+        }
+      })
+    })
+  }
+}
+
+impl BitOr for Bitboard {
+  type Output = Bitboard;
+  fn bitor(mut self, rhs: Self) -> Self::Output {
+    self |= rhs;
+    self
+  }
+}
+
+impl BitOrAssign for Bitboard {
+  fn bitor_assign(&mut self, rhs: Self) {
+    for (l, r) in self.rows.iter_mut().zip(rhs.rows.iter()) {
+      *l |= r;
+    }
+  }
+}
+
+impl BitAnd for Bitboard {
+  type Output = Bitboard;
+  fn bitand(mut self, rhs: Self) -> Self::Output {
+    for (l, r) in self.rows.iter_mut().zip(rhs.rows.iter()) {
+      *l &= r;
+    }
+    self
+  }
+}
+
+// complement within the largest board
+impl Not for Bitboard {
+  type Output = Bitboard;
+  fn not(mut self) -> Self::Output {
+    for r in self.rows.iter_mut() {
+      *r = !*r & ROW_MASK;
+    }
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_bitboard() {
+    let mut b = Bitboard::new();
+    assert!(b.is_empty());
+    b.set(0, 0);
+    b.set(3, 19);
+    b.set_checked(-1, 4, 20);
+    b.set_checked(19, 20, 20);
+    b.set_checked(14, 3, 14);
+    assert_eq!(b.count(), 2);
+    assert_eq!(b.iter().collect::<Vec<_>>(), vec![(0, 0), (3, 19)]);
+    assert_eq!((!b).count() as usize, MAX_BOARD_SIZE * MAX_BOARD_SIZE - 2);
+    assert!((b & !b).is_empty());
+
+    // an L shape starting at (2, 18)
+    let masks = [0b1, 0b1, 0b11];
+    assert!(!b.intersects(2, 18, &masks));
+    assert!(b.intersects(2, 19, &masks));
+    b.insert(2, 18, &masks);
+    assert_eq!(b.count(), 6);
+    assert!(b.get(4, 19) && b.get(3, 18) && !b.get(2, 19));
+  }
+
+  #[test]
+  fn test_flood_fill() {
+    assert_eq!(Bitboard::full(14).count(), 196);
+    // a wall along column 3, open at row 5
+    let mut wall = Bitboard::new();
+    for row in 0..MAX_BOARD_SIZE {
+      if row != 5 {
+        wall.set(row, 3);
+      }
+    }
+    let mut start = Bitboard::new();
+    start.set(0, 0);
+    let passable = Bitboard::full(10) & !wall;
+    assert_eq!(start.flood_fill(&passable).count(), 91);
+
+    wall.set(5, 3);
+    let passable = Bitboard::full(10) & !wall;
+    assert_eq!(start.flood_fill(&passable).count(), 30);
+    // the start itself has to be passable
+    assert!(start.flood_fill(&wall).is_empty());
+  }
+}
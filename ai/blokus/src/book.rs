@@ -0,0 +1,379 @@
+use std::{
+  collections::BTreeMap,
+  io::{self, Read, Write},
+};
+
+use rand::{seq::SliceRandom, Rng};
+use rustyai::{
+  search::{
+    forest::{refcnt_forest::Node, TreeNode, TreeNodePtr},
+    Search, Uct,
+  },
+  MaMdp,
+};
+
+use crate::{Blokus, Color, HeuristicEval, Move, Piece, Scoring, State, Variant};
+
+// An opening book: for early positions, the moves a long search found
+// worth playing, weighted by their share of the search's visits.
+//
+// Positions are stored under their canonical key, with the moves as they
+// are played in the canonical image of the position, so a book entry is
+// shared by all the images of a position.
+//
+// The file format is little endian:
+//   "BLKB", format version (u8), variant name length (u8) and name, entry
+//   count (u32), then per entry canonical key (u64), move count (u8), then
+//   per move move (u32, see encode), weight (u16, 65535 for a weight of 1)
+#[derive(Clone, PartialEq, Debug)]
+pub struct Book {
+  // the name of the variant the positions are of
+  variant: String,
+  entries: BTreeMap<u64, Vec<(Move, f32)>>,
+}
+
+const MAGIC: &[u8; 4] = b"BLKB";
+// 2 added the variant
+const VERSION: u8 = 2;
+const PASS: u32 = u32::MAX;
+
+// How a book is grown from the initial position
+#[derive(Clone, Copy, Debug)]
+pub struct BookConfig {
+  // plies from the initial position to cover
+  pub depth: usize,
+  // search iterations per position
+  pub iterations: u32,
+  // the most moves kept per position. Only the positions after these
+  // moves are searched at the next ply
+  pub width: usize,
+  // moves getting a smaller share of the visits are left out, except for
+  // the most visited one
+  pub min_share: f32,
+}
+
+impl Default for BookConfig {
+  fn default() -> Self {
+    BookConfig {
+      depth: 4,
+      iterations: 20000,
+      width: 3,
+      min_share: 0.05,
+    }
+  }
+}
+
+impl Book {
+  pub fn new<V: Variant<N>, const N: usize>() -> Self {
+    Book {
+      variant: V::NAME.to_string(),
+      entries: BTreeMap::new(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  // The book moves for the position, with their weights
+  pub fn moves<const N: usize>(&self, state: &State<N>) -> Vec<(Move, f32)> {
+    let (key, sym) = state.canonical();
+    let Some(moves) = self.entries.get(&key) else {
+      return vec![];
+    };
+    let image = state.transformed(sym);
+    let inverse = sym.inverse(N);
+    moves
+      .iter()
+      .map(|(m, weight)| (image.transform_move(inverse, m), *weight))
+      .collect()
+  }
+
+  // A book move for the position, picked at random in proportion to the
+  // weights, or None if the position isn't in the book
+  pub fn choose<const N: usize>(&self, state: &State<N>, rng: &mut impl Rng) -> Option<Move> {
+    self
+      .moves(state)
+      .choose_weighted(rng, |(_, weight)| *weight)
+      .ok()
+      .map(|(m, _)| *m)
+  }
+
+  // Adds the moves for the position, replacing any it had
+  pub fn insert<const N: usize>(&mut self, state: &State<N>, moves: &[(Move, f32)]) {
+    let (key, sym) = state.canonical();
+    let moves = moves
+      .iter()
+      .map(|(m, weight)| (state.transform_move(sym, m), *weight))
+      .collect();
+    self.entries.insert(key, moves);
+  }
+
+  // Searches every position reachable through book moves, up to
+  // config.depth plies in. This is meant to be run offline, as it takes
+  // config.iterations search iterations for each position
+  pub fn generate<V: Variant<N>, const N: usize>(config: BookConfig) -> Book {
+    let game = Blokus::<V>::new(Scoring::Rank);
+    let search = Search::new(Uct(2.4), HeuristicEval::default());
+    let mut book = Book::new::<V, N>();
+    let mut positions = vec![game.initial_state()];
+    for _ in 0..config.depth {
+      let mut next = vec![];
+      for state in positions {
+        let Some(color) = state.color_to_move() else {
+          continue;
+        };
+        if !book.moves(&state).is_empty() {
+          // a transposition, or a symmetric image of a position searched
+          // already
+          continue;
+        }
+        let trees: [_; N] = std::array::from_fn(|_| Node::new());
+        for _ in 0..config.iterations {
+          search.step_mdp(&game, &state, trees.clone());
+        }
+        let mut policy: Vec<(Move, f32)> = trees[color as usize]
+          .lock()
+          .compute_policy()
+          .into_iter()
+          .map(|(m, share, _)| (*m, share))
+          .collect();
+        // nothing was searched, with no iterations
+        if policy.is_empty() {
+          continue;
+        }
+        policy.sort_by(|a, b| b.1.total_cmp(&a.1));
+        // the most visited move is always kept
+        let kept = 1 + policy[1..]
+          .iter()
+          .take_while(|(_, share)| *share >= config.min_share)
+          .count();
+        // no more than a book file can hold
+        policy.truncate(kept.min(config.width).min(u8::MAX as usize));
+        for (m, _) in policy.iter() {
+          let mut joint_action = [Move::Pass; N];
+          joint_action[color as usize] = *m;
+          let mut child = state.clone();
+          game.transition(&mut child, &joint_action);
+          next.push(child);
+        }
+        book.insert(&state, &policy);
+      }
+      positions = next;
+    }
+    book
+  }
+
+  pub fn write(&self, mut w: impl Write) -> io::Result<()> {
+    // the file gives the name and move counts a byte each, so nothing is
+    // written for a book that doesn't fit
+    let too_long = |what: &str| {
+      io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{what} too long for a book file"),
+      )
+    };
+    let name_len = u8::try_from(self.variant.len()).map_err(|_| too_long("variant name"))?;
+    if self
+      .entries
+      .values()
+      .any(|moves| moves.len() > u8::MAX as usize)
+    {
+      return Err(too_long("move list"));
+    }
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION, name_len])?;
+    w.write_all(self.variant.as_bytes())?;
+    w.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+    for (key, moves) in self.entries.iter() {
+      w.write_all(&key.to_le_bytes())?;
+      w.write_all(&[moves.len() as u8])?;
+      for (m, weight) in moves.iter() {
+        w.write_all(&encode(m).to_le_bytes())?;
+        let weight = (weight.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+        w.write_all(&weight.to_le_bytes())?;
+      }
+    }
+    Ok(())
+  }
+
+  // Reads a book of the variant V, failing on books of other variants
+  pub fn read<V: Variant<N>, const N: usize>(mut r: impl Read) -> io::Result<Book> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let mut header = [0; 6];
+    r.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+      return Err(invalid("not a Blokus opening book"));
+    }
+    if header[4] != VERSION {
+      return Err(invalid("unsupported book version"));
+    }
+    let mut variant = vec![0; header[5] as usize];
+    r.read_exact(&mut variant)?;
+    if variant != V::NAME.as_bytes() {
+      let variant = String::from_utf8_lossy(&variant);
+      return Err(invalid(&format!("a {variant} book, not {}", V::NAME)));
+    }
+    let mut u8_buf = [0; 1];
+    let mut u16_buf = [0; 2];
+    let mut u32_buf = [0; 4];
+    let mut u64_buf = [0; 8];
+    r.read_exact(&mut u32_buf)?;
+    let mut book = Book::new::<V, N>();
+    for _ in 0..u32::from_le_bytes(u32_buf) {
+      r.read_exact(&mut u64_buf)?;
+      r.read_exact(&mut u8_buf)?;
+      let mut moves = vec![];
+      for _ in 0..u8_buf[0] {
+        r.read_exact(&mut u32_buf)?;
+        r.read_exact(&mut u16_buf)?;
+        let m = decode(u32::from_le_bytes(u32_buf)).ok_or_else(|| invalid("invalid move"))?;
+        moves.push((m, u16::from_le_bytes(u16_buf) as f32 / u16::MAX as f32));
+      }
+      book.entries.insert(u64::from_le_bytes(u64_buf), moves);
+    }
+    Ok(book)
+  }
+}
+
+// A placement packs into a u32: color and piece share the top byte, then
+// orientation, row and col get a byte each
+fn encode(m: &Move) -> u32 {
+  match *m {
+    Move::Pass => PASS,
+    Move::Place {
+      color,
+      piece,
+      orientation,
+      row,
+      col,
+    } => {
+      ((color as u32) << 29)
+        | ((piece as u32) << 24)
+        | ((orientation as u32) << 16)
+        | ((row as u32) << 8)
+        | col as u32
+    }
+  }
+}
+
+fn decode(bits: u32) -> Option<Move> {
+  if bits == PASS {
+    return Some(Move::Pass);
+  }
+  let piece = Piece::try_from(((bits >> 24) & 0x1f) as u8).ok()?;
+  let orientation = (bits >> 16) as u8;
+  if orientation as usize >= piece.orientations().len() {
+    return None;
+  }
+  Some(Move::Place {
+    color: Color::try_from((bits >> 29) as u8).ok()?,
+    piece,
+    orientation,
+    row: (bits >> 8) as u8,
+    col: bits as u8,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Classic, Duo};
+
+  #[test]
+  fn test_encoding() {
+    assert_eq!(decode(encode(&Move::Pass)), Some(Move::Pass));
+    let state = State::new::<Classic>();
+    for color in Color::ALL {
+      for m in state.naive_placements(color) {
+        assert_eq!(decode(encode(&m)), Some(m));
+      }
+    }
+  }
+
+  #[test]
+  fn test_book() {
+    let config = BookConfig {
+      depth: 2,
+      iterations: 100,
+      width: 2,
+      min_share: 0.0,
+    };
+    let book = Book::generate::<Duo, 2>(config);
+    // the initial position, and the positions after its 2 book moves
+    assert!((2..=3).contains(&book.len()));
+
+    let mut file = vec![];
+    book.write(&mut file).unwrap();
+    let header = 10 + "Duo".len();
+    assert_eq!(file.len(), header + book.len() * 9 + 6 * 2 * book.len());
+    let read = Book::read::<Duo, 2>(file.as_slice()).unwrap();
+    assert_eq!(read.len(), book.len());
+    for (key, moves) in book.entries.iter() {
+      for ((m, weight), (read_m, read_weight)) in moves.iter().zip(read.entries[key].iter()) {
+        assert_eq!(m, read_m);
+        assert!((weight - read_weight).abs() < 1e-4);
+      }
+    }
+    assert!(Book::read::<Duo, 2>(&file[..file.len() - 1]).is_err());
+    assert!(Book::read::<Duo, 2>(&b"nope"[..]).is_err());
+    // a Duo book isn't one for Classic
+    let error = Book::read::<Classic, 4>(file.as_slice()).unwrap_err();
+    assert_eq!(error.to_string(), "a Duo book, not Classic");
+
+    // the book moves are legal, and so are the book moves after them or
+    // after their reflections, which share a book entry
+    let game = Blokus::<Duo>::default();
+    let state = game.initial_state();
+    let first_moves = book.moves(&state);
+    assert_eq!(first_moves.len(), 2);
+    let reflection = Duo::SYMMETRIES[1];
+    for (m, _) in first_moves {
+      for m in [m, state.transform_move(reflection, &m)] {
+        assert!(state.is_legal(&m));
+        let mut next = state.clone();
+        game.transition(&mut next, &[m, Move::Pass]);
+        let replies = book.moves(&next);
+        assert_eq!(replies.len(), 2);
+        for (reply, _) in replies {
+          assert!(next.is_legal(&reply), "{reply} after {m}");
+        }
+        assert!(book.choose(&next, &mut rand::thread_rng()).is_some());
+      }
+    }
+    assert!(book.choose(&state, &mut rand::thread_rng()).is_some());
+    assert!(Book::new::<Duo, 2>()
+      .choose(&state, &mut rand::thread_rng())
+      .is_none());
+  }
+
+  #[test]
+  fn test_book_without_search() {
+    let config = BookConfig {
+      iterations: 0,
+      ..BookConfig::default()
+    };
+    assert!(Book::generate::<Duo, 2>(config).is_empty());
+  }
+
+  #[test]
+  fn test_book_too_long() {
+    let state = State::new::<Duo>();
+    let mut book = Book::new::<Duo, 2>();
+    let moves: Vec<_> = state
+      .naive_placements(Color::Blue)
+      .into_iter()
+      .map(|m| (m, 1.0))
+      .collect();
+    assert!(moves.len() > u8::MAX as usize);
+    book.insert(&state, &moves);
+    let mut file = vec![];
+    let error = book.write(&mut file).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(file.is_empty());
+  }
+}
@@ -0,0 +1,265 @@
+use std::sync::OnceLock;
+
+use rustyai::search::eval::{BaseEval, EvaluationResult};
+
+use crate::{
+  pieces::ORIENTATION_COUNT, Bitboard, Blokus, Color, Move, Piece, Scoring, State, Variant,
+  COLOR_COUNT, MAX_BOARD_SIZE, PIECE_COUNT,
+};
+
+// A static evaluation of Blokus positions, to use in place of random
+// rollouts.
+//
+// Each color's prospects are summed up as a potential, from its open
+// corners and from its territory: the cells reachable from its open
+// corners by edges through cells it can still cover. The squares it will
+// still place are estimated as remaining * potential / (potential +
+// remaining), which is 0 without potential, and approaches the squares in
+// hand as the potential grows. The estimated scores are then turned into
+// values according to the game's scoring.
+//
+// The prior over the placements of the color to move favours large
+// pieces, more so the more pieces are left in hand
+pub struct HeuristicEval {
+  pub corner_weight: f32,
+  pub territory_weight: f32,
+  // for rank scoring, the score difference that makes a color ~73% likely
+  // to finish ahead of another
+  pub rank_scale: f32,
+  // the prior of a placement is proportional to
+  // exp(size_bias * piece size * fraction of pieces in hand)
+  pub size_bias: f32,
+}
+
+impl Default for HeuristicEval {
+  fn default() -> Self {
+    HeuristicEval {
+      corner_weight: 2.0,
+      territory_weight: 0.5,
+      rank_scale: 5.0,
+      size_bias: 1.0,
+    }
+  }
+}
+
+impl HeuristicEval {
+  // The cells color can reach from its open corners
+  pub fn territory<const N: usize>(state: &State<N>, color: Color) -> Bitboard {
+    let passable = Bitboard::full(state.size()) & !state.forbidden[color as usize];
+    state.open_corners(color).flood_fill(&passable)
+  }
+
+  // The score color is expected to finish with
+  pub fn estimated_score<const N: usize>(
+    &self,
+    scoring: Scoring,
+    state: &State<N>,
+    color: Color,
+  ) -> f32 {
+    let score = match scoring {
+      Scoring::Basic => state.basic_score(color),
+      Scoring::Advanced | Scoring::Rank => state.advanced_score(color),
+    } as f32;
+    if state.stuck[color as usize] {
+      return score;
+    }
+    let remaining = -state.basic_score(color) as f32;
+    let potential = self.corner_weight * state.open_corners(color).count() as f32
+      + self.territory_weight * HeuristicEval::territory(state, color).count() as f32;
+    score + remaining * potential / (potential + remaining)
+  }
+
+  fn values<const N: usize>(&self, scoring: Scoring, state: &State<N>) -> [f32; N] {
+    let colors: [Color; N] = std::array::from_fn(|ix| Color::ALL[ix]);
+    let scores = colors.map(|color| self.estimated_score(scoring, state, color));
+    match scoring {
+      Scoring::Basic | Scoring::Advanced => scores,
+      Scoring::Rank => scores.map(|score| {
+        let ahead: f32 = scores
+          .iter()
+          .map(|other| 1.0 / (1.0 + ((other - score) / self.rank_scale).exp()))
+          .sum();
+        // a color is half ahead of itself
+        (ahead - 0.5) / (N - 1) as f32
+      }),
+    }
+  }
+
+  fn prior<const N: usize>(&self, state: &State<N>, color: Color) -> Vec<(&'static Move, f32)> {
+    let placements = state.placements(color);
+    if placements.is_empty() {
+      return vec![(&Move::Pass, 1.0)];
+    }
+    let in_hand = state.remaining_pieces(color).len() as f32 / PIECE_COUNT as f32;
+    let weight = |m: &Move| match m {
+      Move::Place { piece, .. } => (self.size_bias * piece.size() as f32 * in_hand).exp(),
+      Move::Pass => 1.0,
+    };
+    let total: f32 = placements.iter().map(weight).sum();
+    placements
+      .iter()
+      .map(|m| (interned(m), weight(m) / total))
+      .collect()
+  }
+}
+
+impl<V, const N: usize> BaseEval<Blokus<V>, State<N>, Move, N> for HeuristicEval
+where
+  V: Variant<N>,
+{
+  fn evaluate<'a>(
+    &self,
+    problem: &Blokus<V>,
+    state: &'a mut State<N>,
+  ) -> EvaluationResult<'a, Move, N> {
+    let Some(to_move) = state.color_to_move() else {
+      // the rewards have all been given
+      return EvaluationResult {
+        values: [0.0; N],
+        policies: [(); N].map(|_| vec![]),
+      };
+    };
+    EvaluationResult {
+      values: self.values(problem.scoring(), state),
+      policies: std::array::from_fn(|ix| {
+        if ix == to_move as usize {
+          self.prior(state, to_move)
+        } else {
+          vec![(&Move::Pass, 1.0)]
+        }
+      }),
+    }
+  }
+}
+
+// The policies borrow their moves, but the placements are generated on
+// the fly, so every possible placement is kept in a table that lives as
+// long as the program
+fn interned(m: &Move) -> &'static Move {
+  static TABLE: OnceLock<(Vec<usize>, Vec<Move>)> = OnceLock::new();
+  let (offsets, moves) = TABLE.get_or_init(|| {
+    let mut offsets = vec![0];
+    for piece in Piece::ALL {
+      offsets.push(offsets.last().unwrap() + piece.orientations().len());
+    }
+    let mut moves = Vec::with_capacity(COLOR_COUNT * ORIENTATION_COUNT * MAX_BOARD_SIZE.pow(2));
+    for color in Color::ALL {
+      for piece in Piece::ALL {
+        for orientation in 0..piece.orientations().len() {
+          for row in 0..MAX_BOARD_SIZE {
+            for col in 0..MAX_BOARD_SIZE {
+              moves.push(Move::Place {
+                color,
+                piece,
+                orientation: orientation as u8,
+                row: row as u8,
+                col: col as u8,
+              });
+            }
+          }
+        }
+      }
+    }
+    (offsets, moves)
+  });
+  match m {
+    Move::Pass => &Move::Pass,
+    Move::Place {
+      color,
+      piece,
+      orientation,
+      row,
+      col,
+    } => {
+      let orientation =
+        *color as usize * ORIENTATION_COUNT + offsets[*piece as usize] + *orientation as usize;
+      &moves[(orientation * MAX_BOARD_SIZE + *row as usize) * MAX_BOARD_SIZE + *col as usize]
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rustyai::{
+    search::{
+      forest::{refcnt_forest::Node, TreeNode, TreeNodePtr},
+      Search, Uct,
+    },
+    MaMdp,
+  };
+
+  use super::*;
+  use crate::{random_game, Classic, Duo};
+
+  #[test]
+  fn test_interned() {
+    let state = State::new::<Classic>();
+    for color in Color::ALL {
+      for m in state.naive_placements(color) {
+        assert_eq!(*interned(&m), m);
+      }
+    }
+    assert_eq!(*interned(&Move::Pass), Move::Pass);
+  }
+
+  #[test]
+  fn test_initial_position() {
+    let eval = HeuristicEval::default();
+    let game = Blokus::<Duo>::new(Scoring::Rank);
+    let mut state = game.initial_state();
+    assert_eq!(HeuristicEval::territory(&state, Color::Blue).count(), 196);
+    let estimate = |color| eval.estimated_score(Scoring::Basic, &state, color);
+    assert_eq!(estimate(Color::Blue), estimate(Color::Yellow));
+    assert!(estimate(Color::Blue) > -89.0);
+    let result = eval.evaluate(&game, &mut state);
+    assert_eq!(result.values, [0.5, 0.5]);
+    assert_eq!(result.policies[1], vec![(&Move::Pass, 1.0)]);
+
+    let policy = &result.policies[0];
+    assert_eq!(policy.len(), 414);
+    let total: f32 = policy.iter().map(|(_, p)| p).sum();
+    assert!((total - 1.0).abs() < 1e-4);
+    let prior_of = |piece: Piece| {
+      policy
+        .iter()
+        .find(|(m, _)| matches!(m, Move::Place { piece: p, .. } if *p == piece))
+        .unwrap()
+        .1
+    };
+    assert!(prior_of(Piece::I5) > prior_of(Piece::O4));
+    assert!(prior_of(Piece::O4) > prior_of(Piece::I1));
+  }
+
+  #[test]
+  fn test_values_along_a_game() {
+    let eval = HeuristicEval::default();
+    for scoring in [Scoring::Basic, Scoring::Advanced, Scoring::Rank] {
+      let game = Blokus::<Classic>::new(scoring);
+      let mut state = random_game(&game, |state, _, _, _| {
+        let values = eval.evaluate(&game, &mut state.clone()).values;
+        for (other, value) in Color::ALL.iter().zip(values) {
+          match scoring {
+            Scoring::Rank => assert!((0.0..=1.0).contains(&value)),
+            // placing pieces can only improve a score
+            Scoring::Basic => assert!(value >= state.basic_score(*other) as f32),
+            Scoring::Advanced => assert!(value >= state.advanced_score(*other) as f32),
+          }
+        }
+      });
+      let result = eval.evaluate(&game, &mut state);
+      assert_eq!(result.values, [0.0; COLOR_COUNT]);
+    }
+  }
+
+  #[test]
+  fn test_uct() {
+    let game = Blokus::<Duo>::new(Scoring::Rank);
+    let state = game.initial_state();
+    let s = Search::new(Uct(2.4), HeuristicEval::default());
+    let trees = [Node::new(), Node::new()];
+    for _ in 0..200 {
+      s.step_mdp(&game, &state, [trees[0].clone(), trees[1].clone()]);
+    }
+    assert_eq!(trees[0].lock().actions().len(), 414);
+  }
+}
@@ -1 +1,634 @@
+use std::{fmt::Display, marker::PhantomData};
 
+use rustyai::{MaMdp, TranstitionResult};
+
+pub mod bitboard;
+pub mod book;
+pub mod eval;
+mod movegen;
+pub mod notation;
+pub mod pieces;
+mod scoring;
+mod setup;
+mod symmetry;
+mod teams;
+pub mod variant;
+mod zobrist;
+pub use bitboard::Bitboard;
+pub use eval::HeuristicEval;
+pub use notation::{Record, ReplayError};
+pub use pieces::{Orientation, Piece, PIECE_COUNT};
+pub use scoring::Scoring;
+pub use symmetry::Symmetry;
+pub use teams::Teams;
+pub use variant::{Classic, Duo, Variant};
+
+// The largest board any variant uses
+pub const MAX_BOARD_SIZE: usize = 20;
+// The most colors any variant uses. Variants with fewer colors use the
+// first ones in Color::ALL
+pub const COLOR_COUNT: usize = 4;
+
+// all 21 pieces still in hand
+const ALL_PIECES: u32 = (1 << PIECE_COUNT) - 1;
+
+// The colors, in turn order
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum Color {
+  Blue = 0,
+  Yellow = 1,
+  Red = 2,
+  Green = 3,
+}
+
+// A placement is a piece in one of its orientations, with the top left
+// corner of the orientation's bounding box at (row, col)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+pub enum Move {
+  // Played by every color other than the one to move, and by the color
+  // to move when it has no legal placement left
+  #[default]
+  Pass,
+  Place {
+    color: Color,
+    piece: Piece,
+    orientation: u8,
+    row: u8,
+    col: u8,
+  },
+}
+
+// Why a move can't be played
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IllegalMove {
+  GameOver,
+  // it's another color's turn
+  WrongTurn,
+  // the color has already placed the piece
+  PieceUsed,
+  InvalidOrientation,
+  // part of the piece is off the board
+  OutOfBounds,
+  // the piece covers an occupied cell
+  Overlap,
+  // the piece shares an edge with a piece of the same color
+  EdgeContact,
+  // the piece doesn't share a corner with a piece of the same color
+  NoCornerContact,
+  // the color's first piece doesn't cover its starting square
+  NotOnStart,
+  // passing while a placement is available
+  MustPlace,
+}
+
+#[derive(Clone)]
+pub struct State<const N: usize> {
+  // side of the board
+  size: usize,
+  // the square each color's first piece has to cover
+  starts: [(usize, usize); N],
+  // cells covered by each color
+  colors: [Bitboard; N],
+  // cells each color can't cover: every occupied cell, and every cell
+  // sharing an edge with a cell of the same color
+  forbidden: [Bitboard; N],
+  // cells sharing a corner with a cell of the color. Before the color's
+  // first move, this is its starting square
+  corners: [Bitboard; N],
+  // bit i is set if Piece i is still in the color's hand
+  remaining: [u32; N],
+  // the piece each color placed most recently
+  last_piece: [Option<Piece>; N],
+  // colors that passed, or placed all their pieces. A color that can't
+  // place a piece now can never place one later, so this never gets unset
+  stuck: [bool; N],
+  // If the game has ended, then color_to_move is None
+  color_to_move: Option<Color>,
+  // Zobrist key of the position, updated with every move
+  key: u64,
+  symmetries: &'static [Symmetry],
+}
+
+pub struct Blokus<V> {
+  scoring: Scoring,
+  variant: PhantomData<V>,
+}
+
+impl<V> Blokus<V> {
+  pub fn new(scoring: Scoring) -> Self {
+    Blokus {
+      scoring,
+      variant: PhantomData,
+    }
+  }
+
+  pub fn scoring(&self) -> Scoring {
+    self.scoring
+  }
+}
+
+impl<V, const N: usize> MaMdp<State<N>, Move, Move, N> for Blokus<V>
+where
+  V: Variant<N>,
+{
+  fn initial_state(&self) -> State<N> {
+    State::new::<V>()
+  }
+
+  fn actions(&self, state: &State<N>, agent: usize) -> Vec<Move> {
+    match state.color_to_move {
+      // Game over
+      None => vec![],
+      Some(color) if color as usize == agent => {
+        let result = state.placements(color);
+        if result.is_empty() {
+          vec![Move::Pass]
+        } else {
+          result
+        }
+      }
+      // other colors can only observe
+      Some(_) => vec![Move::Pass],
+    }
+  }
+
+  fn transition(
+    &self,
+    state: &mut State<N>,
+    joint_action: &[Move; N],
+  ) -> TranstitionResult<Move, N> {
+    let color = state.color_to_move.expect("transition on a finished game");
+    let agent_index = color as usize;
+    debug_assert!(
+      (0..N).all(|ix| ix == agent_index || joint_action[ix] == Move::Pass),
+      "Only the color to move can place a piece"
+    );
+    let action = joint_action[agent_index];
+    match action {
+      Move::Pass => state.set_stuck(color),
+      Move::Place {
+        color: c,
+        piece,
+        orientation,
+        row,
+        col,
+      } => {
+        debug_assert!(c == color, "Invalid color placing");
+        debug_assert!(state.is_legal(&action), "Illegal placement {action}");
+        state.place(color, piece, orientation, row as usize, col as usize);
+      }
+    }
+    state.advance_turn();
+
+    TranstitionResult {
+      rewards: if state.is_over() {
+        self.scoring.rewards(state)
+      } else {
+        [0.0; N]
+      },
+      // every color sees the move played
+      observations: [action; N],
+    }
+  }
+}
+
+impl<const N: usize> State<N> {
+  pub fn new<V: Variant<N>>() -> Self {
+    let mut corners = [Bitboard::new(); N];
+    for (ix, (row, col)) in V::STARTS.iter().enumerate() {
+      corners[ix].set(*row, *col);
+    }
+    State {
+      size: V::SIZE,
+      starts: V::STARTS,
+      colors: [Bitboard::new(); N],
+      forbidden: [Bitboard::new(); N],
+      corners,
+      remaining: [ALL_PIECES; N],
+      last_piece: [None; N],
+      stuck: [false; N],
+      color_to_move: Some(Color::ALL[0]),
+      key: zobrist::to_move_key(Some(Color::ALL[0])),
+      symmetries: V::SYMMETRIES,
+    }
+  }
+
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  // The colors in play, in turn order
+  pub fn colors(&self) -> impl Iterator<Item = Color> {
+    Color::ALL.into_iter().take(N)
+  }
+
+  pub fn start(&self, color: Color) -> (usize, usize) {
+    self.starts[color as usize]
+  }
+
+  pub fn color_to_move(&self) -> Option<Color> {
+    self.color_to_move
+  }
+
+  pub fn is_over(&self) -> bool {
+    self.color_to_move.is_none()
+  }
+
+  pub fn get(&self, row: usize, col: usize) -> Option<Color> {
+    self
+      .colors()
+      .find(|color| self.colors[*color as usize].get(row, col))
+  }
+
+  pub fn has_piece(&self, color: Color, piece: Piece) -> bool {
+    self.remaining[color as usize] & (1 << piece as u32) != 0
+  }
+
+  pub fn remaining_pieces(&self, color: Color) -> Vec<Piece> {
+    Piece::ALL
+      .into_iter()
+      .filter(|piece| self.has_piece(color, *piece))
+      .collect()
+  }
+
+  // The cells a piece of this color can be attached to
+  pub fn open_corners(&self, color: Color) -> Bitboard {
+    self.corners[color as usize] & !self.forbidden[color as usize]
+  }
+
+  // true if the color hasn't placed a piece yet
+  fn first_move(&self, color: Color) -> bool {
+    self.remaining[color as usize] == ALL_PIECES
+  }
+
+  pub fn is_legal(&self, m: &Move) -> bool {
+    self.check(m).is_ok()
+  }
+
+  // Checks if the color to move can play the move. A pass is only legal
+  // when the color has no placement left. A placement is legal if none of
+  // its cells are forbidden to the color, and at least one of them is on a
+  // corner of the color
+  pub fn check(&self, m: &Move) -> Result<(), IllegalMove> {
+    let Some(to_move) = self.color_to_move else {
+      return Err(IllegalMove::GameOver);
+    };
+    let Move::Place {
+      color,
+      piece,
+      orientation,
+      row,
+      col,
+    } = *m
+    else {
+      return if self.placements(to_move).is_empty() {
+        Ok(())
+      } else {
+        Err(IllegalMove::MustPlace)
+      };
+    };
+    if color != to_move {
+      return Err(IllegalMove::WrongTurn);
+    }
+    if !self.has_piece(color, piece) {
+      return Err(IllegalMove::PieceUsed);
+    }
+    let Some(o) = piece.orientations().get(orientation as usize) else {
+      return Err(IllegalMove::InvalidOrientation);
+    };
+    let (row, col) = (row as usize, col as usize);
+    if row + o.height as usize > self.size || col + o.width as usize > self.size {
+      return Err(IllegalMove::OutOfBounds);
+    }
+    let ix = color as usize;
+    if self.colors.iter().any(|b| b.intersects(row, col, &o.masks)) {
+      Err(IllegalMove::Overlap)
+    } else if self.forbidden[ix].intersects(row, col, &o.masks) {
+      Err(IllegalMove::EdgeContact)
+    } else if !self.corners[ix].intersects(row, col, &o.masks) {
+      if self.first_move(color) {
+        Err(IllegalMove::NotOnStart)
+      } else {
+        Err(IllegalMove::NoCornerContact)
+      }
+    } else {
+      Ok(())
+    }
+  }
+
+  fn place(&mut self, color: Color, piece: Piece, orientation: u8, row: usize, col: usize) {
+    let o = &piece.orientations()[orientation as usize];
+    let ix = color as usize;
+    self.key ^= zobrist::placement_key(color, piece, o, row, col);
+    let offset = |(r, c): &(i8, i8)| (row as isize + *r as isize, col as isize + *c as isize);
+    self.colors[ix].insert(row, col, &o.masks);
+    for forbidden in self.forbidden.iter_mut() {
+      forbidden.insert(row, col, &o.masks);
+    }
+    for cell in o.edges.iter() {
+      let (r, c) = offset(cell);
+      self.forbidden[ix].set_checked(r, c, self.size);
+    }
+    for cell in o.corners.iter() {
+      let (r, c) = offset(cell);
+      self.corners[ix].set_checked(r, c, self.size);
+    }
+    self.remaining[ix] &= !(1 << piece as u32);
+    self.last_piece[ix] = Some(piece);
+    if self.remaining[ix] == 0 {
+      self.set_stuck(color);
+    }
+  }
+
+  fn set_stuck(&mut self, color: Color) {
+    if !self.stuck[color as usize] {
+      self.stuck[color as usize] = true;
+      self.key ^= zobrist::stuck_key(color);
+    }
+  }
+
+  // Moves the turn to the next color that isn't stuck. The game ends
+  // when every color is stuck
+  fn advance_turn(&mut self) {
+    let current = self.color_to_move.unwrap() as usize;
+    self.key ^= zobrist::to_move_key(self.color_to_move);
+    self.color_to_move = (1..=N)
+      .map(|offset| (current + offset) % N)
+      .find(|ix| !self.stuck[*ix])
+      .map(|ix| Color::ALL[ix]);
+    self.key ^= zobrist::to_move_key(self.color_to_move);
+  }
+}
+
+impl Color {
+  pub const ALL: [Color; COLOR_COUNT] = [Color::Blue, Color::Yellow, Color::Red, Color::Green];
+}
+
+impl TryFrom<u8> for Color {
+  type Error = ();
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    Color::ALL.get(value as usize).copied().ok_or(())
+  }
+}
+
+impl Display for Color {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match &self {
+      Color::Blue => write!(f, "B"),
+      Color::Yellow => write!(f, "Y"),
+      Color::Red => write!(f, "R"),
+      Color::Green => write!(f, "G"),
+    }
+  }
+}
+
+impl<const N: usize> Display for State<N> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for row in 0..self.size {
+      for col in 0..self.size {
+        match self.get(row, col) {
+          Some(color) => write!(f, "{color} ")?,
+          None => write!(f, ". ")?,
+        }
+      }
+      writeln!(f)?;
+    }
+    for color in self.colors() {
+      write!(f, "{color} [")?;
+      for piece in self.remaining_pieces(color) {
+        write!(f, " {piece}")?;
+      }
+      writeln!(f, " ]")?;
+    }
+    match self.color_to_move {
+      Some(color) => writeln!(f, "{color} to move"),
+      None => writeln!(f, "Game over"),
+    }
+  }
+}
+
+impl<V> Default for Blokus<V> {
+  fn default() -> Self {
+    Blokus::new(Scoring::default())
+  }
+}
+
+// Plays a game, choose picking each move from the ply and the moves the
+// color to move has, calling f with each position before a move, the color
+// to move, the move it plays and what the move gave, and returns the final
+// position
+#[cfg(test)]
+pub(crate) fn play_game<V: Variant<N>, const N: usize>(
+  game: &Blokus<V>,
+  mut choose: impl FnMut(usize, &[Move]) -> Move,
+  mut f: impl FnMut(&State<N>, Color, Move, &TranstitionResult<Move, N>),
+) -> State<N> {
+  let mut state = game.initial_state();
+  let mut ply = 0;
+  while let Some(color) = state.color_to_move() {
+    let before = state.clone();
+    let m = choose(ply, &game.actions(&state, color as usize));
+    let mut joint_action = [Move::Pass; N];
+    joint_action[color as usize] = m;
+    let tr = game.transition(&mut state, &joint_action);
+    f(&before, color, m, &tr);
+    ply += 1;
+  }
+  state
+}
+
+// A game of random moves, see play_game
+#[cfg(test)]
+pub(crate) fn random_game<V: Variant<N>, const N: usize>(
+  game: &Blokus<V>,
+  f: impl FnMut(&State<N>, Color, Move, &TranstitionResult<Move, N>),
+) -> State<N> {
+  use rand::seq::SliceRandom;
+
+  play_game(
+    game,
+    |_, moves| *moves.choose(&mut rand::thread_rng()).unwrap(),
+    f,
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use rustyai::search::{
+    eval::RandomRolloutEval,
+    forest::{refcnt_forest::Node, TreeNode, TreeNodePtr},
+    Search, Uct,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_first_moves() {
+    let game = Blokus::<Classic>::default();
+    let state = game.initial_state();
+    let actions = game.actions(&state, Color::Blue as usize);
+    assert_eq!(actions.len(), 58);
+    for m in actions.iter() {
+      assert!(state.is_legal(m));
+    }
+    for agent in 1..COLOR_COUNT {
+      assert_eq!(game.actions(&state, agent), vec![Move::Pass]);
+    }
+  }
+
+  #[test]
+  fn test_placement_rules() {
+    let game = Blokus::<Classic>::default();
+    let mut state = game.initial_state();
+    let place = |color, piece, row, col| Move::Place {
+      color,
+      piece,
+      orientation: 0,
+      row,
+      col,
+    };
+    assert_eq!(
+      state.check(&place(Color::Blue, Piece::I1, 1, 1)),
+      Err(IllegalMove::NotOnStart)
+    );
+    assert!(state.is_legal(&place(Color::Blue, Piece::I1, 0, 0)));
+    assert_eq!(state.check(&Move::Pass), Err(IllegalMove::MustPlace));
+    assert_eq!(
+      state.check(&place(Color::Yellow, Piece::I1, 0, 19)),
+      Err(IllegalMove::WrongTurn)
+    );
+    assert_eq!(
+      state.check(&place(Color::Blue, Piece::I5, 0, 16)),
+      Err(IllegalMove::OutOfBounds)
+    );
+
+    let mut joint_action = [Move::Pass; COLOR_COUNT];
+    joint_action[0] = place(Color::Blue, Piece::O4, 0, 0);
+    game.transition(&mut state, &joint_action);
+    assert_eq!(state.color_to_move(), Some(Color::Yellow));
+    assert!(!state.has_piece(Color::Blue, Piece::O4));
+    for (color, row, col) in [
+      (Color::Yellow, 0, 19),
+      (Color::Red, 19, 19),
+      (Color::Green, 19, 0),
+    ] {
+      let mut joint_action = [Move::Pass; COLOR_COUNT];
+      joint_action[color as usize] = place(color, Piece::I1, row, col);
+      game.transition(&mut state, &joint_action);
+    }
+
+    assert_eq!(state.color_to_move(), Some(Color::Blue));
+    let check = |piece, row, col| state.check(&place(Color::Blue, piece, row, col));
+    assert_eq!(check(Piece::I1, 1, 1), Err(IllegalMove::Overlap));
+    assert_eq!(check(Piece::I1, 2, 1), Err(IllegalMove::EdgeContact));
+    assert_eq!(check(Piece::I1, 3, 3), Err(IllegalMove::NoCornerContact));
+    assert_eq!(check(Piece::I1, 2, 2), Ok(()));
+    assert_eq!(check(Piece::O4, 2, 2), Err(IllegalMove::PieceUsed));
+    let bad_orientation = Move::Place {
+      color: Color::Blue,
+      piece: Piece::X5,
+      orientation: 1,
+      row: 2,
+      col: 2,
+    };
+    assert_eq!(
+      state.check(&bad_orientation),
+      Err(IllegalMove::InvalidOrientation)
+    );
+  }
+
+  fn check_random_game<V: Variant<N>, const N: usize>() -> State<N> {
+    let game = Blokus::<V>::default();
+    let mut plies = 0;
+    let state = random_game(&game, |state, _, m, tr| {
+      for agent in 0..N {
+        assert!(!game.actions(state, agent).is_empty());
+      }
+      assert_eq!(tr.observations, [m; N]);
+      plies += 1;
+    });
+    println!("{state}");
+    // every color passes exactly once unless it places all its pieces
+    assert!(plies <= N * (PIECE_COUNT + 1));
+    for agent in 0..N {
+      assert!(game.actions(&state, agent).is_empty());
+    }
+    state
+  }
+
+  #[test]
+  fn test_random_game() {
+    check_random_game::<Classic, 4>();
+    let state = check_random_game::<Duo, 2>();
+    for row in 0..MAX_BOARD_SIZE {
+      for col in 0..MAX_BOARD_SIZE {
+        if row >= Duo::SIZE || col >= Duo::SIZE {
+          assert_eq!(state.get(row, col), None);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_duo() {
+    let game = Blokus::<Duo>::default();
+    let mut state = game.initial_state();
+    assert_eq!(state.size(), 14);
+    // the starting squares are away from the edges, so every cell of every
+    // orientation can cover them
+    let first_moves: usize = Piece::ALL
+      .iter()
+      .map(|p| p.size() * p.orientations().len())
+      .sum();
+    assert_eq!(game.actions(&state, 0).len(), first_moves);
+    assert_eq!(game.actions(&state, 1), vec![Move::Pass]);
+
+    let x = Move::Place {
+      color: Color::Blue,
+      piece: Piece::X5,
+      orientation: 0,
+      row: 3,
+      col: 3,
+    };
+    game.transition(&mut state, &[x, Move::Pass]);
+    assert_eq!(state.color_to_move(), Some(Color::Yellow));
+    assert!(game.actions(&state, 1).iter().all(|m| matches!(
+      m,
+      Move::Place {
+        color: Color::Yellow,
+        ..
+      }
+    )));
+    assert_eq!(game.actions(&state, 0), vec![Move::Pass]);
+  }
+
+  #[test]
+  fn test_uct() {
+    let game = Blokus::<Classic>::default();
+    let state = game.initial_state();
+    let s = Search::new(Uct(2.4), RandomRolloutEval::new(4));
+    let trees = [Node::new(), Node::new(), Node::new(), Node::new()];
+    for _ in 0..100 {
+      let n = [0, 1, 2, 3].map(|ix| trees[ix].clone());
+      s.step_mdp(&game, &state, n);
+    }
+    let guard = trees[0].lock();
+    assert_eq!(guard.actions().len(), 58);
+    for (m, pi, q) in guard.compute_policy() {
+      println!("policy of {m} is {pi} and q is {q}")
+    }
+  }
+
+  #[test]
+  fn test_uct_duo() {
+    let game = Blokus::<Duo>::new(Scoring::Rank);
+    let state = game.initial_state();
+    let s = Search::new(Uct(2.4), RandomRolloutEval::new(4));
+    let trees = [Node::new(), Node::new()];
+    for _ in 0..100 {
+      s.step_mdp(&game, &state, [trees[0].clone(), trees[1].clone()]);
+    }
+    assert_eq!(trees[0].lock().actions().len(), 414);
+  }
+}
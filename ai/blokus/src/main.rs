@@ -1,5 +1,413 @@
-mod lib;
+use std::{
+  fs::File,
+  io::{BufWriter, Write},
+  str::FromStr,
+};
+
+use blokus::{
+  book::{Book, BookConfig},
+  Blokus, Classic, Color, Duo, Move, Orientation, Piece, Record, Scoring, State, Variant,
+};
+use colored::{ColoredString, Colorize};
+use rustyai::{
+  search::{
+    eval::RandomRolloutEval,
+    forest::{refcnt_forest::Node, TreeNode, TreeNodePtr},
+    Search, Uct,
+  },
+  MaMdp,
+};
+use text_io::read;
+
+// Play Blokus in the terminal, against search agents or other humans.
+//
+//   blokus [classic|duo] [--bot <seat>]... [--iterations <n>] [--book <file>]
+//
+// Seats are numbered from 1 in turn order. Without --bot, every seat but
+// the first is played by the search. Bots play from the opening book while
+// the position is in it.
+//
+//   blokus [classic|duo] --make-book <file> [--depth <plies>] [--iterations <n>]
+//
+// searches the early positions and writes their best moves to an opening
+// book
+const DEFAULT_ITERATIONS: u32 = 2000;
+
+const USAGE: &str = "Usage:
+  blokus [classic|duo] [--bot <seat>]... [--iterations <n>] [--book <file>]
+  blokus [classic|duo] --make-book <file> [--depth <plies>] [--iterations <n>]";
+
+const HELP: &str = "Commands:
+  state                          show the board
+  actions                        list the legal moves of the color to move
+  move <n>                       play the n-th move of the last actions list
+  place <piece> <orientation> <row> <col>
+                                 place a piece, e.g. place L5 R1 0 0
+  pass                           pass, when no placement is left
+  pieces                         show the pieces left to the color to move
+  piece <piece>                  show the orientations of a piece
+  bot <seat> | human <seat>      hand a seat to the search, or to a human
+  iterations <n>                 search iterations per bot move
+  record                         print the game record
+  exit | quit";
+
+struct Config<const N: usize> {
+  bots: [bool; N],
+  iterations: u32,
+  book: Book,
+}
 
 fn main() {
-  println!("Hello, world!");
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let mut variant = "classic".to_string();
+  let mut bots = vec![];
+  let mut iterations = None;
+  let mut book = None;
+  let mut make_book = None;
+  let mut book_config = BookConfig::default();
+  let mut ix = 0;
+  while ix < args.len() {
+    match args[ix].as_str() {
+      "--bot" => {
+        ix += 1;
+        bots.push(value::<usize>(&args, ix, "seat number"));
+      }
+      "--iterations" => {
+        ix += 1;
+        iterations = Some(value(&args, ix, "iteration count"));
+      }
+      "--book" => {
+        ix += 1;
+        book = Some(value::<String>(&args, ix, "book file"));
+      }
+      "--make-book" => {
+        ix += 1;
+        make_book = Some(value::<String>(&args, ix, "book file"));
+      }
+      "--depth" => {
+        ix += 1;
+        book_config.depth = value(&args, ix, "ply count");
+      }
+      arg => variant = arg.to_lowercase(),
+    }
+    ix += 1;
+  }
+  let seats = match variant.as_str() {
+    "classic" => 4,
+    "duo" => 2,
+    _ => usage(&format!(
+      "Unknown variant {variant}, expected classic or duo"
+    )),
+  };
+  if let Some(seat) = bots.iter().find(|seat| !(1..=seats).contains(*seat)) {
+    usage(&format!("No seat {seat}, seats are numbered 1 to {seats}"));
+  }
+
+  if let Some(path) = make_book {
+    if let Some(iterations) = iterations {
+      book_config.iterations = iterations;
+    }
+    // fails before the long search if the book can't be written
+    let file = File::create(&path).unwrap_or_else(|e| fail(&format!("{path}: {e}")));
+    let book = match seats {
+      4 => Book::generate::<Classic, 4>(book_config),
+      _ => Book::generate::<Duo, 2>(book_config),
+    };
+    let mut w = BufWriter::new(file);
+    if let Err(e) = book.write(&mut w).and_then(|()| w.flush()) {
+      fail(&format!("{path}: {e}"));
+    }
+    println!("{} positions written to {path}", book.len());
+    return;
+  }
+
+  let iterations = iterations.unwrap_or(DEFAULT_ITERATIONS);
+  let book = book.as_deref();
+  match seats {
+    4 => play::<Classic, 4>(config(&bots, iterations, load_book::<Classic, 4>(book))),
+    _ => play::<Duo, 2>(config(&bots, iterations, load_book::<Duo, 2>(book))),
+  }
+}
+
+// The book of the variant at path, or an empty one without a path
+fn load_book<V: Variant<N>, const N: usize>(path: Option<&str>) -> Book {
+  let Some(path) = path else {
+    return Book::new::<V, N>();
+  };
+  let file = File::open(path).unwrap_or_else(|e| usage(&format!("{path}: {e}")));
+  Book::read::<V, N>(std::io::BufReader::new(file))
+    .unwrap_or_else(|e| usage(&format!("{path}: {e}")))
+}
+
+// Prints what is wrong with the arguments, and how to call the program
+fn usage(message: &str) -> ! {
+  eprintln!("{message}\n{USAGE}");
+  std::process::exit(2)
+}
+
+// Prints what went wrong, for errors that aren't the arguments' fault
+fn fail(message: &str) -> ! {
+  eprintln!("{message}");
+  std::process::exit(1)
+}
+
+// The value of the flag before ix, which is missing if the flag is last
+fn value<T: FromStr>(args: &[String], ix: usize, what: &str) -> T {
+  let Some(arg) = args.get(ix) else {
+    usage(&format!("{} needs a {what}", args[ix - 1]))
+  };
+  arg
+    .parse()
+    .unwrap_or_else(|_| usage(&format!("Invalid {what} {arg}")))
+}
+
+fn config<const N: usize>(bots: &[usize], iterations: u32, book: Book) -> Config<N> {
+  let bots = if bots.is_empty() {
+    std::array::from_fn(|ix| ix != 0)
+  } else {
+    std::array::from_fn(|ix| bots.contains(&(ix + 1)))
+  };
+  Config {
+    bots,
+    iterations,
+    book,
+  }
+}
+
+fn play<V: Variant<N>, const N: usize>(mut config: Config<N>) {
+  let game = Blokus::<V>::new(Scoring::Rank);
+  let mut state = game.initial_state();
+  let mut record = Record::new::<V, N>();
+  let mut actions = vec![];
+  println!("{HELP}");
+  // the board is shown again after every move
+  let mut moves_played = usize::MAX;
+  loop {
+    while let Some(color) = state.color_to_move() {
+      if !config.bots[color as usize] {
+        break;
+      }
+      let m = search_move(&game, &state, &config);
+      println!("{} plays {m}", paint(color, &color.notation()));
+      apply(&game, &mut state, &mut record, m);
+    }
+    if record.moves.len() != moves_played {
+      moves_played = record.moves.len();
+      println!("{}", render(&state));
+      if state.is_over() {
+        record.set_scores(&state);
+      }
+    }
+
+    let cmd: String = read!();
+    match cmd.as_str() {
+      "exit" | "quit" => std::process::exit(0),
+      "help" => println!("{HELP}"),
+      "state" => println!("{}", render(&state)),
+      "record" => println!("{record}"),
+      "actions" => {
+        actions = state
+          .color_to_move()
+          .map(|color| game.actions(&state, color as usize))
+          .unwrap_or_default();
+        for (ix, m) in actions.iter().enumerate() {
+          println!("{ix} -> {m}");
+        }
+      }
+      "move" => {
+        let n: String = read!();
+        match n.parse::<usize>().ok().and_then(|n| actions.get(n)) {
+          Some(m) => {
+            let m = *m;
+            try_move(&game, &mut state, &mut record, m);
+            actions.clear();
+          }
+          None => println!("No move {n}, list the moves with actions"),
+        }
+      }
+      "place" => {
+        let (piece, orientation, row, col): (String, String, String, String) =
+          (read!(), read!(), read!(), read!());
+        let Some(color) = state.color_to_move() else {
+          println!("The game is over");
+          continue;
+        };
+        let notation = format!("{}:{piece}/{orientation}@({row},{col})", color.notation());
+        match notation.parse() {
+          Ok(m) => try_move(&game, &mut state, &mut record, m),
+          Err(e) => println!("{e}"),
+        }
+      }
+      "pass" => try_move(&game, &mut state, &mut record, Move::Pass),
+      "pieces" => {
+        if let Some(color) = state.color_to_move() {
+          for piece in state.remaining_pieces(color) {
+            println!("{piece}\n{}", preview(piece, color));
+          }
+        }
+      }
+      "piece" => {
+        let name: String = read!();
+        match name.parse::<Piece>() {
+          Ok(piece) => println!(
+            "{}",
+            preview(piece, state.color_to_move().unwrap_or(Color::Blue))
+          ),
+          Err(e) => println!("{e}"),
+        }
+      }
+      "bot" | "human" => {
+        let seat: String = read!();
+        match seat.parse::<usize>() {
+          Ok(seat) if (1..=N).contains(&seat) => config.bots[seat - 1] = cmd == "bot",
+          _ => println!("Seats are numbered 1 to {N}"),
+        }
+      }
+      "iterations" => {
+        let n: String = read!();
+        match n.parse() {
+          Ok(n) => config.iterations = n,
+          Err(_) => println!("Invalid iteration count {n}"),
+        }
+      }
+      _ => println!("Invalid command, try help"),
+    }
+  }
+}
+
+fn try_move<V: Variant<N>, const N: usize>(
+  game: &Blokus<V>,
+  state: &mut State<N>,
+  record: &mut Record,
+  m: Move,
+) {
+  match state.check(&m) {
+    Ok(()) => apply(game, state, record, m),
+    Err(reason) => println!("Can't play {m}: {reason:?}"),
+  }
+}
+
+fn apply<V: Variant<N>, const N: usize>(
+  game: &Blokus<V>,
+  state: &mut State<N>,
+  record: &mut Record,
+  m: Move,
+) {
+  let mut joint_action = [Move::Pass; N];
+  joint_action[state.color_to_move().unwrap() as usize] = m;
+  game.transition(state, &joint_action);
+  record.moves.push(m);
+}
+
+// Plays from the book if it can, otherwise runs the search from scratch
+// and plays the most visited move
+fn search_move<V: Variant<N>, const N: usize>(
+  game: &Blokus<V>,
+  state: &State<N>,
+  config: &Config<N>,
+) -> Move {
+  let color = state.color_to_move().unwrap();
+  let actions = game.actions(state, color as usize);
+  if actions.len() == 1 {
+    return actions[0];
+  }
+  if let Some(m) = config.book.choose(state, &mut rand::thread_rng()) {
+    return m;
+  }
+  // a random rollout plays out the rest of the game
+  let s = Search::new(Uct(2.4), RandomRolloutEval::new(100));
+  let trees: [_; N] = std::array::from_fn(|_| Node::new());
+  for _ in 0..config.iterations {
+    s.step_mdp(game, state, trees.clone());
+  }
+  let guard = trees[color as usize].lock();
+  guard
+    .compute_policy()
+    .into_iter()
+    .max_by(|a, b| a.1.total_cmp(&b.1))
+    .map(|(m, _, _)| *m)
+    .unwrap_or(actions[0])
+}
+
+fn paint(color: Color, s: &str) -> ColoredString {
+  match color {
+    Color::Blue => s.truecolor(0, 90, 255),
+    Color::Yellow => s.truecolor(255, 210, 0),
+    Color::Red => s.truecolor(230, 30, 30),
+    Color::Green => s.truecolor(0, 170, 60),
+  }
+}
+
+fn cell(color: Color) -> ColoredString {
+  paint(color, "██")
+}
+
+// The board with row and column numbers, the starting squares still
+// empty marked with the color that has to cover them
+fn render<const N: usize>(state: &State<N>) -> String {
+  // column numbers are written vertically, tens above units
+  let mut result = "   ".to_string();
+  for col in 0..state.size() {
+    match col / 10 {
+      0 => result += "  ",
+      tens => result += &format!(" {tens}"),
+    }
+  }
+  result += "\n   ";
+  for col in 0..state.size() {
+    result += &format!(" {}", col % 10);
+  }
+  result += "\n";
+  for row in 0..state.size() {
+    result += &format!("{row:>2} ");
+    for col in 0..state.size() {
+      match state.get(row, col) {
+        Some(color) => result += &cell(color).to_string(),
+        None => match state.colors().find(|c| state.start(*c) == (row, col)) {
+          Some(color) => result += &paint(color, " *").to_string(),
+          None => result += " .",
+        },
+      }
+    }
+    result += "\n";
+  }
+  for color in state.colors() {
+    result += &paint(color, &color.notation()).to_string();
+    result += &format!(" {}:", state.advanced_score(color));
+    for piece in state.remaining_pieces(color) {
+      result += &format!(" {piece}");
+    }
+    result += "\n";
+  }
+  match state.color_to_move() {
+    Some(color) => result += &format!("{} to move", paint(color, &color.notation())),
+    None => result += "Game over",
+  }
+  result
+}
+
+// All the orientations of a piece side by side, each under the name used
+// to place it
+fn preview(piece: Piece, color: Color) -> String {
+  let orientations = piece.orientations();
+  let height = orientations.iter().map(|o| o.height).max().unwrap();
+  let width = |o: &Orientation| 2 * o.width.max(2) as usize + 2;
+  let mut result = String::new();
+  for o in orientations {
+    result += &format!("{:<w$}", o.name(), w = width(o));
+  }
+  for row in 0..height {
+    result += "\n";
+    for o in orientations {
+      for col in 0..o.width.max(2) {
+        if o.cells.contains(&(row as i8, col as i8)) {
+          result += &cell(color).to_string();
+        } else {
+          result += "  ";
+        }
+      }
+      result += "  ";
+    }
+  }
+  result
 }
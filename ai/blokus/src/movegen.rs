@@ -0,0 +1,179 @@
+use crate::{Color, Move, State};
+
+const EDGES: [(isize, isize); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const CORNERS: [(isize, isize); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+impl<const N: usize> State<N> {
+  // Returns all the legal placements for color.
+  // Instead of trying every orientation at every cell, this only tries
+  // placements that put an attachment cell of the orientation on one of
+  // the color's open corners, and checks them against the color's
+  // forbidden cells a row at a time
+  pub(crate) fn placements(&self, color: Color) -> Vec<Move> {
+    let forbidden = &self.forbidden[color as usize];
+    let open_corners = self.open_corners(color);
+    // The starting square of the first move need not be at the edge of the
+    // board, so any cell of the piece can cover it
+    let first_move = self.first_move(color);
+    let mut result = vec![];
+    for piece in self.remaining_pieces(color) {
+      for (orientation, o) in piece.orientations().iter().enumerate() {
+        let attachments = if first_move { &o.cells } else { &o.attachments };
+        // the same placement can touch several corners, so only check
+        // for duplicates among the placements of this orientation
+        let start = result.len();
+        for (r, c) in open_corners.iter() {
+          for (ar, ac) in attachments.iter() {
+            let (row, col) = (r as isize - *ar as isize, c as isize - *ac as isize);
+            if row < 0
+              || col < 0
+              || row as usize + o.height as usize > self.size
+              || col as usize + o.width as usize > self.size
+            {
+              continue;
+            }
+            let (row, col) = (row as usize, col as usize);
+            if forbidden.intersects(row, col, &o.masks) {
+              continue;
+            }
+            let m = Move::Place {
+              color,
+              piece,
+              orientation: orientation as u8,
+              row: row as u8,
+              col: col as u8,
+            };
+            if !result[start..].contains(&m) {
+              result.push(m);
+            }
+          }
+        }
+      }
+    }
+    result
+  }
+
+  // Reference move generator, that tries every orientation of every
+  // remaining piece at every cell, checking the cells one by one.
+  // This is far too slow for search, and is only meant for testing and
+  // benchmarking the real generator
+  pub fn naive_placements(&self, color: Color) -> Vec<Move> {
+    let mut result = vec![];
+    for piece in self.remaining_pieces(color) {
+      for (orientation, o) in piece.orientations().iter().enumerate() {
+        for row in 0..=(self.size - o.height as usize) {
+          for col in 0..=(self.size - o.width as usize) {
+            let cells: Vec<_> = o
+              .cells
+              .iter()
+              .map(|(r, c)| (row + *r as usize, col + *c as usize))
+              .collect();
+            if self.can_place(color, &cells) {
+              result.push(Move::Place {
+                color,
+                piece,
+                orientation: orientation as u8,
+                row: row as u8,
+                col: col as u8,
+              });
+            }
+          }
+        }
+      }
+    }
+    result
+  }
+
+  // A set of cells can be covered by color if
+  // 1. all of them are empty
+  // 2. none of them share an edge with a cell of the same color
+  // 3. one of them is the color's starting corner for the first move,
+  //    or shares a corner with a cell of the same color for later moves
+  fn can_place(&self, color: Color, cells: &[(usize, usize)]) -> bool {
+    let mut touches_corner = false;
+    let start = self.start(color);
+    for (r, c) in cells.iter() {
+      if self.get(*r, *c).is_some() {
+        return false;
+      }
+      for (dr, dc) in EDGES {
+        if self.color_at(*r as isize + dr, *c as isize + dc) == Some(color) {
+          return false;
+        }
+      }
+      if self.first_move(color) {
+        touches_corner = touches_corner || (*r, *c) == start;
+      } else {
+        for (dr, dc) in CORNERS {
+          touches_corner =
+            touches_corner || self.color_at(*r as isize + dr, *c as isize + dc) == Some(color);
+        }
+      }
+    }
+    touches_corner
+  }
+
+  fn color_at(&self, row: isize, col: isize) -> Option<Color> {
+    if row < 0 || col < 0 || row >= self.size as isize || col >= self.size as isize {
+      None
+    } else {
+      self.get(row as usize, col as usize)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Instant;
+
+  use crate::{random_game, Blokus, Classic, Color, Duo, State, Variant};
+
+  // plays a random game, calling f on every position before a move
+  fn each_position<V: Variant<N>, const N: usize>(mut f: impl FnMut(&State<N>, Color)) {
+    random_game(&Blokus::<V>::default(), |state, color, _, _| {
+      f(state, color)
+    });
+  }
+
+  fn check_against_naive<const N: usize>(state: &State<N>, color: Color) {
+    let mut fast = state.placements(color);
+    let mut naive = state.naive_placements(color);
+    fast.sort();
+    naive.sort();
+    assert_eq!(fast, naive, "Generators differ on\n{state}");
+    for m in fast.iter() {
+      assert!(state.is_legal(m));
+    }
+  }
+
+  #[test]
+  fn test_matches_naive_generator() {
+    for _ in 0..4 {
+      each_position::<Classic, 4>(check_against_naive);
+      each_position::<Duo, 2>(check_against_naive);
+    }
+  }
+
+  #[test]
+  #[ignore]
+  fn bench_generators() {
+    let mut positions = vec![];
+    each_position::<Classic, 4>(|state, color| positions.push((state.clone(), color)));
+
+    let start = Instant::now();
+    let fast: usize = positions.iter().map(|(s, c)| s.placements(*c).len()).sum();
+    let fast_time = start.elapsed();
+    let start = Instant::now();
+    let naive: usize = positions
+      .iter()
+      .map(|(s, c)| s.naive_placements(*c).len())
+      .sum();
+    let naive_time = start.elapsed();
+
+    assert_eq!(fast, naive);
+    println!(
+      "{} positions, {fast} moves. corner anchored: {fast_time:?}, naive: {naive_time:?}",
+      positions.len()
+    );
+  }
+}
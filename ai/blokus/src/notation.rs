@@ -0,0 +1,518 @@
+use std::{fmt::Display, str::FromStr};
+
+use rustyai::MaMdp;
+
+use crate::{Blokus, Color, IllegalMove, Move, Orientation, Piece, State, Variant};
+
+// Text notation for moves and whole games.
+//
+// A placement is written color:piece/orientation@(row,col), e.g.
+// C1:I5/R1@(0,4). Colors are numbered in turn order from C1, and the
+// orientation is the transform that produces it from the piece's base
+// cells: R0..R3 for clockwise rotations, F0..F3 for the same rotations
+// after mirroring the columns. (row, col) is the top left corner of the
+// bounding box, as in Move. A pass is written Pass.
+//
+// A game record is a header of "Key: value" lines, a blank line, then the
+// moves of the game, one per line. A pass forced by a clock, which a color
+// with placements left can't otherwise make, is written Timeout. # starts
+// a comment:
+//
+//   Variant: Duo
+//   Player C1: alice
+//   Player C2: bob
+//   Score C1: -12
+//   Score C2: 3
+//
+//   C1:X5/R0@(3,3)
+//   C2:I5/R1@(5,9)
+//   ...
+
+impl Color {
+  // C1 for the first color, C2 for the second, ...
+  pub fn notation(&self) -> String {
+    format!("C{}", *self as u8 + 1)
+  }
+
+  pub fn parse_notation(s: &str) -> Result<Color, String> {
+    s.strip_prefix('C')
+      .and_then(|n| n.parse::<u8>().ok())
+      .and_then(|n| n.checked_sub(1))
+      .and_then(|ix| Color::try_from(ix).ok())
+      .ok_or_else(|| format!("Invalid color {s:?}"))
+  }
+}
+
+impl FromStr for Piece {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Piece::ALL
+      .into_iter()
+      .find(|piece| piece.to_string() == s)
+      .ok_or_else(|| format!("Invalid piece {s:?}"))
+  }
+}
+
+impl Orientation {
+  // R0..R3 or F0..F3, after the transform producing the orientation
+  pub fn name(&self) -> String {
+    if self.transform < 4 {
+      format!("R{}", self.transform)
+    } else {
+      format!("F{}", self.transform - 4)
+    }
+  }
+}
+
+fn parse_transform(s: &str) -> Option<u8> {
+  let n = s.get(1..)?.parse::<u8>().ok().filter(|n| *n < 4)?;
+  match s.get(..1)? {
+    "R" => Some(n),
+    "F" => Some(n + 4),
+    _ => None,
+  }
+}
+
+impl Display for Move {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match &self {
+      Move::Pass => write!(f, "Pass"),
+      Move::Place {
+        color,
+        piece,
+        orientation,
+        row,
+        col,
+      } => {
+        let transform = match piece.orientations().get(*orientation as usize) {
+          Some(o) => o.name(),
+          // not a valid move, but keep it printable
+          None => format!("?{orientation}"),
+        };
+        write!(f, "{}:{piece}/{transform}@({row},{col})", color.notation())
+      }
+    }
+  }
+}
+
+impl FromStr for Move {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s == "Pass" {
+      return Ok(Move::Pass);
+    }
+    let invalid = || format!("Invalid move {s:?}");
+    let (color, rest) = s.split_once(':').ok_or_else(invalid)?;
+    let (piece, rest) = rest.split_once('/').ok_or_else(invalid)?;
+    let (transform, rest) = rest.split_once('@').ok_or_else(invalid)?;
+    let (row, col) = rest
+      .strip_prefix('(')
+      .and_then(|rest| rest.strip_suffix(')'))
+      .and_then(|rest| rest.split_once(','))
+      .ok_or_else(invalid)?;
+    let color = Color::parse_notation(color)?;
+    let piece: Piece = piece.parse()?;
+    let orientation = parse_transform(transform)
+      .and_then(|t| piece.orientation_index(t))
+      .ok_or_else(|| format!("Invalid orientation {transform:?}"))?;
+    let row = row.trim().parse().map_err(|_| invalid())?;
+    let col = col.trim().parse().map_err(|_| invalid())?;
+    Ok(Move::Place {
+      color,
+      piece,
+      orientation,
+      row,
+      col,
+    })
+  }
+}
+
+// A game, as stored or exchanged: who played which color, the moves of
+// the color to move in order (passes included), and the final advanced
+// scores if the game is over
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Record {
+  pub variant: String,
+  pub players: Vec<(Color, String)>,
+  pub scores: Vec<(Color, i32)>,
+  pub moves: Vec<Move>,
+  // the indices in moves of the passes clocks forced, in order
+  pub timeouts: Vec<usize>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ReplayError {
+  // the record is for another variant
+  WrongVariant(String),
+  // a player or score of a color the variant doesn't have
+  NoSuchColor(Color),
+  // ply counts moves from 1
+  Illegal {
+    ply: usize,
+    m: Move,
+    reason: IllegalMove,
+  },
+  WrongScore {
+    color: Color,
+    recorded: i32,
+    actual: i32,
+  },
+}
+
+impl Record {
+  pub fn new<V: Variant<N>, const N: usize>() -> Self {
+    Record {
+      variant: V::NAME.to_string(),
+      ..Default::default()
+    }
+  }
+
+  // Records the scores of a finished game
+  pub fn set_scores<const N: usize>(&mut self, state: &State<N>) {
+    self.scores = state
+      .colors()
+      .map(|color| (color, state.advanced_score(color)))
+      .collect();
+  }
+
+  // Plays the moves from the initial position, checking each of them and
+  // the recorded scores. Returns the final position
+  pub fn replay<V: Variant<N>, const N: usize>(&self) -> Result<State<N>, ReplayError> {
+    if self.variant != V::NAME {
+      return Err(ReplayError::WrongVariant(self.variant.clone()));
+    }
+    let colors = self.players.iter().map(|(color, _)| color);
+    if let Some(color) = colors
+      .chain(self.scores.iter().map(|(color, _)| color))
+      .find(|color| **color as usize >= N)
+    {
+      return Err(ReplayError::NoSuchColor(*color));
+    }
+    let game = Blokus::<V>::default();
+    let mut state = game.initial_state();
+    for (ix, m) in self.moves.iter().enumerate() {
+      match state.check(m) {
+        // the clock made the color pass
+        Err(IllegalMove::MustPlace) if self.is_timeout(ix) => {}
+        checked => checked.map_err(|reason| ReplayError::Illegal {
+          ply: ix + 1,
+          m: *m,
+          reason,
+        })?,
+      }
+      let mut joint_action = [Move::Pass; N];
+      joint_action[state.color_to_move().unwrap() as usize] = *m;
+      game.transition(&mut state, &joint_action);
+    }
+    for (color, recorded) in self.scores.iter() {
+      let actual = state.advanced_score(*color);
+      if actual != *recorded {
+        return Err(ReplayError::WrongScore {
+          color: *color,
+          recorded: *recorded,
+          actual,
+        });
+      }
+    }
+    Ok(state)
+  }
+
+  // Whether the move at ix is a pass the clock forced
+  fn is_timeout(&self, ix: usize) -> bool {
+    self.moves[ix] == Move::Pass && self.timeouts.contains(&ix)
+  }
+}
+
+impl Display for Record {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "Variant: {}", self.variant)?;
+    for (color, name) in self.players.iter() {
+      writeln!(f, "Player {}: {name}", color.notation())?;
+    }
+    for (color, score) in self.scores.iter() {
+      writeln!(f, "Score {}: {score}", color.notation())?;
+    }
+    writeln!(f)?;
+    for (ix, m) in self.moves.iter().enumerate() {
+      if self.is_timeout(ix) {
+        writeln!(f, "Timeout")?;
+      } else {
+        writeln!(f, "{m}")?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for Record {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut record = Record::default();
+    let mut in_header = true;
+    for (ix, line) in s.lines().enumerate() {
+      let line = line.split('#').next().unwrap().trim();
+      let with_line = |e: String| format!("line {}: {e}", ix + 1);
+      if line.is_empty() {
+        in_header = in_header && record.variant.is_empty();
+        continue;
+      }
+      if !in_header {
+        if line == "Timeout" {
+          record.timeouts.push(record.moves.len());
+          record.moves.push(Move::Pass);
+        } else {
+          record.moves.push(line.parse().map_err(with_line)?);
+        }
+        continue;
+      }
+      let (key, value) = line
+        .split_once(':')
+        .ok_or_else(|| with_line(format!("Invalid header {line:?}")))?;
+      let value = value.trim();
+      match key.split_once(' ') {
+        None if key == "Variant" => record.variant = value.to_string(),
+        Some(("Player", color)) => {
+          let color = Color::parse_notation(color).map_err(with_line)?;
+          record.players.push((color, value.to_string()));
+        }
+        Some(("Score", color)) => {
+          let color = Color::parse_notation(color).map_err(with_line)?;
+          let score = value
+            .parse()
+            .map_err(|_| with_line(format!("Invalid score {value:?}")))?;
+          record.scores.push((color, score));
+        }
+        _ => return Err(with_line(format!("Unknown header {key:?}"))),
+      }
+    }
+    if record.variant.is_empty() {
+      return Err("Missing variant".to_string());
+    }
+    Ok(record)
+  }
+}
+
+impl Display for ReplayError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ReplayError::WrongVariant(variant) => write!(f, "Record is for variant {variant}"),
+      ReplayError::NoSuchColor(color) => write!(f, "There is no color {}", color.notation()),
+      ReplayError::Illegal { ply, m, reason } => {
+        write!(f, "Illegal move {m} at ply {ply}: {reason:?}")
+      }
+      ReplayError::WrongScore {
+        color,
+        recorded,
+        actual,
+      } => write!(
+        f,
+        "{} scored {actual}, but the record says {recorded}",
+        color.notation()
+      ),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rustyai::KeyableState;
+
+  use super::*;
+  use crate::{random_game, Classic, Duo};
+
+  fn random_record<V: Variant<N>, const N: usize>() -> Record {
+    let game = Blokus::<V>::default();
+    let mut record = Record::new::<V, N>();
+    let state = random_game(&game, |_, _, m, _| record.moves.push(m));
+    for color in state.colors() {
+      record
+        .players
+        .push((color, format!("player {}", color as u8)));
+    }
+    record.set_scores(&state);
+    record
+  }
+
+  #[test]
+  fn test_move_notation() {
+    let m: Move = "C1:I5/R1@(0,4)".parse().unwrap();
+    assert_eq!(
+      m,
+      Move::Place {
+        color: Color::Blue,
+        piece: Piece::I5,
+        orientation: 1,
+        row: 0,
+        col: 4,
+      }
+    );
+    assert_eq!(m.to_string(), "C1:I5/R1@(0,4)");
+    assert_eq!("Pass".parse::<Move>(), Ok(Move::Pass));
+
+    // every transform parses, and prints as the first transform giving the
+    // same orientation
+    let x: Move = "C4:X5/F3@(1,2)".parse().unwrap();
+    assert_eq!(x.to_string(), "C4:X5/R0@(1,2)");
+    let i: Move = "C2:I2/R2@(0,0)".parse().unwrap();
+    assert_eq!(i.to_string(), "C2:I2/R0@(0,0)");
+
+    for piece in Piece::ALL {
+      for (ix, o) in piece.orientations().iter().enumerate() {
+        let m = Move::Place {
+          color: Color::Red,
+          piece,
+          orientation: ix as u8,
+          row: 3,
+          col: 17,
+        };
+        assert_eq!(piece.orientation_index(o.transform), Some(ix as u8));
+        assert_eq!(m.to_string().parse::<Move>(), Ok(m));
+      }
+    }
+
+    for bad in [
+      "",
+      "C0:I1/R0@(0,0)",
+      "C1:Q1/R0@(0,0)",
+      "C1:I1/R4@(0,0)",
+      "C1:I1/R0@0,0",
+    ] {
+      assert!(bad.parse::<Move>().is_err(), "{bad}");
+    }
+  }
+
+  #[test]
+  fn test_record_round_trip() {
+    let record = random_record::<Duo, 2>();
+    let text = record.to_string();
+    assert_eq!(text.parse::<Record>(), Ok(record.clone()));
+    let state = record.replay::<Duo, 2>().ok().unwrap();
+    assert!(state.is_over());
+
+    let record = random_record::<Classic, 4>();
+    assert_eq!(record.to_string().parse::<Record>(), Ok(record.clone()));
+    assert!(record.replay::<Classic, 4>().is_ok());
+    assert_eq!(
+      record.replay::<Duo, 2>().err(),
+      Some(ReplayError::WrongVariant("Classic".to_string()))
+    );
+  }
+
+  #[test]
+  fn test_parse_record() {
+    let text = "
+      # a short game
+      Variant: Duo
+      Player C1: alice
+      Player C2: bob
+
+      C1:X5/R0@(3,3)  # center
+      C2:I5/R1@(5,9)
+    ";
+    let record: Record = text.parse().unwrap();
+    assert_eq!(record.variant, "Duo");
+    assert_eq!(
+      record.players,
+      vec![
+        (Color::Blue, "alice".to_string()),
+        (Color::Yellow, "bob".to_string())
+      ]
+    );
+    assert_eq!(record.moves.len(), 2);
+    let state = record.replay::<Duo, 2>().ok().unwrap();
+    assert_eq!(state.get(4, 4), Some(Color::Blue));
+    assert_eq!(state.get(9, 9), Some(Color::Yellow));
+
+    assert!("Variant: Duo\nPlayer C5: x\n".parse::<Record>().is_err());
+    assert!("Player C1: x\n".parse::<Record>().is_err());
+    assert!("Variant: Duo\n\nC1:X5\n".parse::<Record>().is_err());
+  }
+
+  #[test]
+  fn test_illegal_moves() {
+    let mut record = random_record::<Duo, 2>();
+    // Blue plays the same move twice
+    record.moves.insert(2, record.moves[0]);
+    assert_eq!(
+      record.replay::<Duo, 2>().err(),
+      Some(ReplayError::Illegal {
+        ply: 3,
+        m: record.moves[0],
+        reason: IllegalMove::PieceUsed,
+      })
+    );
+
+    let mut record = random_record::<Duo, 2>();
+    record.moves.swap(0, 1);
+    assert!(matches!(
+      record.replay::<Duo, 2>(),
+      Err(ReplayError::Illegal {
+        ply: 1,
+        reason: IllegalMove::WrongTurn,
+        ..
+      })
+    ));
+
+    let mut record = random_record::<Duo, 2>();
+    record.scores[1].1 += 1;
+    assert!(matches!(
+      record.replay::<Duo, 2>(),
+      Err(ReplayError::WrongScore {
+        color: Color::Yellow,
+        ..
+      })
+    ));
+  }
+
+  #[test]
+  fn test_timeouts() {
+    let game = Blokus::<Duo>::default();
+    let mut state = game.initial_state();
+    let mut record = Record::new::<Duo, 2>();
+    let first = game.actions(&state, 0)[0];
+    game.transition(&mut state, &[first, Move::Pass]);
+    // yellow runs out of time, and blue plays on alone
+    game.transition(&mut state, &[Move::Pass; 2]);
+    let second = game.actions(&state, 0)[0];
+    game.transition(&mut state, &[second, Move::Pass]);
+    record.moves = vec![first, Move::Pass, second];
+    record.timeouts = vec![1];
+    record.set_scores(&state);
+
+    let text = record.to_string();
+    assert!(text.contains("\nTimeout\n"), "{text}");
+    let parsed: Record = text.parse().unwrap();
+    assert_eq!(parsed, record);
+    let replayed = parsed.replay::<Duo, 2>().ok().unwrap();
+    assert_eq!(replayed.color_to_move(), Some(Color::Blue));
+    assert_eq!(replayed.key(), state.key());
+
+    // a pass the clock didn't force is as illegal as ever
+    record.timeouts.clear();
+    assert!(matches!(
+      record.replay::<Duo, 2>(),
+      Err(ReplayError::Illegal {
+        ply: 2,
+        reason: IllegalMove::MustPlace,
+        ..
+      })
+    ));
+  }
+
+  #[test]
+  fn test_colors_outside_variant() {
+    for text in [
+      "Variant: Duo\nScore C3: 0\n\n",
+      "Variant: Duo\nPlayer C4: x\n\n",
+    ] {
+      let record: Record = text.parse().unwrap();
+      let error = record.replay::<Duo, 2>().err().unwrap();
+      assert!(matches!(error, ReplayError::NoSuchColor(_)), "{error}");
+    }
+    let record: Record = "Variant: Classic\nScore C3: 0\n\n".parse().unwrap();
+    assert!(matches!(
+      record.replay::<Classic, 4>(),
+      Err(ReplayError::WrongScore { .. })
+    ));
+  }
+}
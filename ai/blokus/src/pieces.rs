@@ -0,0 +1,310 @@
+use std::{fmt::Display, sync::OnceLock};
+
+pub const PIECE_COUNT: usize = 21;
+// Number of distinct fixed orientations over all the pieces
+pub const ORIENTATION_COUNT: usize = 91;
+
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum Piece {
+  I1 = 0,
+  I2,
+  I3,
+  V3,
+  I4,
+  L4,
+  T4,
+  O4,
+  Z4,
+  F5,
+  I5,
+  L5,
+  N5,
+  P5,
+  T5,
+  U5,
+  V5,
+  W5,
+  X5,
+  Y5,
+  Z5,
+}
+
+// A piece in one fixed rotation/reflection. All offsets are (row, col),
+// relative to the top left corner of the orientation's bounding box
+pub struct Orientation {
+  // index of the first of the 8 transforms that produces this orientation.
+  // 0..4 are clockwise rotations by 90 degrees, 4..8 are the same rotations
+  // applied after mirroring the columns
+  pub transform: u8,
+  pub height: u8,
+  pub width: u8,
+  pub cells: Vec<(i8, i8)>,
+  // one bitmask per row of the bounding box, with bit c set if (row, c)
+  // is a cell of the piece
+  pub masks: Vec<u32>,
+  // cells of the piece that have a free diagonal neighbour. One of these
+  // has to sit on a corner of the same color when the piece is placed
+  pub attachments: Vec<(i8, i8)>,
+  // cells outside the piece that share an edge with it, and so can't be
+  // covered by the same color once the piece is placed
+  pub edges: Vec<(i8, i8)>,
+  // cells outside the piece that share only a corner with it. These are
+  // the corners the piece opens up for its color
+  pub corners: Vec<(i8, i8)>,
+}
+
+const EDGES: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const DIAGONALS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+impl Piece {
+  pub const ALL: [Piece; PIECE_COUNT] = [
+    Piece::I1,
+    Piece::I2,
+    Piece::I3,
+    Piece::V3,
+    Piece::I4,
+    Piece::L4,
+    Piece::T4,
+    Piece::O4,
+    Piece::Z4,
+    Piece::F5,
+    Piece::I5,
+    Piece::L5,
+    Piece::N5,
+    Piece::P5,
+    Piece::T5,
+    Piece::U5,
+    Piece::V5,
+    Piece::W5,
+    Piece::X5,
+    Piece::Y5,
+    Piece::Z5,
+  ];
+
+  // (row, col) of the cells of the piece in its base orientation
+  pub fn cells(&self) -> &'static [(i8, i8)] {
+    match self {
+      Piece::I1 => &[(0, 0)],
+      Piece::I2 => &[(0, 0), (0, 1)],
+      Piece::I3 => &[(0, 0), (0, 1), (0, 2)],
+      Piece::V3 => &[(0, 0), (1, 0), (1, 1)],
+      Piece::I4 => &[(0, 0), (0, 1), (0, 2), (0, 3)],
+      Piece::L4 => &[(0, 0), (1, 0), (2, 0), (2, 1)],
+      Piece::T4 => &[(0, 0), (0, 1), (0, 2), (1, 1)],
+      Piece::O4 => &[(0, 0), (0, 1), (1, 0), (1, 1)],
+      Piece::Z4 => &[(0, 0), (0, 1), (1, 1), (1, 2)],
+      Piece::F5 => &[(0, 1), (0, 2), (1, 0), (1, 1), (2, 1)],
+      Piece::I5 => &[(0, 0), (0, 1), (0, 2), (0, 3), (0, 4)],
+      Piece::L5 => &[(0, 0), (1, 0), (2, 0), (3, 0), (3, 1)],
+      Piece::N5 => &[(0, 0), (0, 1), (1, 1), (1, 2), (1, 3)],
+      Piece::P5 => &[(0, 0), (0, 1), (1, 0), (1, 1), (2, 0)],
+      Piece::T5 => &[(0, 0), (0, 1), (0, 2), (1, 1), (2, 1)],
+      Piece::U5 => &[(0, 0), (0, 2), (1, 0), (1, 1), (1, 2)],
+      Piece::V5 => &[(0, 0), (1, 0), (2, 0), (2, 1), (2, 2)],
+      Piece::W5 => &[(0, 0), (1, 0), (1, 1), (2, 1), (2, 2)],
+      Piece::X5 => &[(0, 1), (1, 0), (1, 1), (1, 2), (2, 1)],
+      Piece::Y5 => &[(0, 1), (1, 0), (1, 1), (2, 1), (3, 1)],
+      Piece::Z5 => &[(0, 0), (0, 1), (1, 1), (2, 1), (2, 2)],
+    }
+  }
+
+  pub fn size(&self) -> usize {
+    self.cells().len()
+  }
+
+  // All the distinct rotations and reflections of the piece. These are
+  // computed once, on first use
+  pub fn orientations(&self) -> &'static [Orientation] {
+    static TABLE: OnceLock<Vec<Vec<Orientation>>> = OnceLock::new();
+    &TABLE.get_or_init(|| {
+      Piece::ALL
+        .iter()
+        .map(|p| p.compute_orientations())
+        .collect()
+    })[*self as usize]
+  }
+
+  // Index in orientations() of the orientation produced by transform, for
+  // any of the 8 transforms
+  pub fn orientation_index(&self, transform: u8) -> Option<u8> {
+    if transform >= 8 {
+      return None;
+    }
+    let cells = normalise(
+      self
+        .cells()
+        .iter()
+        .map(|cell| apply(transform, *cell))
+        .collect(),
+    );
+    self
+      .orientations()
+      .iter()
+      .position(|o| o.cells == cells)
+      .map(|ix| ix as u8)
+  }
+
+  fn compute_orientations(&self) -> Vec<Orientation> {
+    let mut result: Vec<Orientation> = vec![];
+    for transform in 0..8 {
+      let cells = normalise(
+        self
+          .cells()
+          .iter()
+          .map(|cell| apply(transform, *cell))
+          .collect(),
+      );
+      if result.iter().all(|o| o.cells != cells) {
+        result.push(Orientation::new(transform, cells));
+      }
+    }
+    result
+  }
+}
+
+// maps a cell through one of the 8 symmetries of the square
+fn apply(transform: u8, (r, c): (i8, i8)) -> (i8, i8) {
+  let (mut r, mut c) = if transform >= 4 { (r, -c) } else { (r, c) };
+  for _ in 0..(transform % 4) {
+    (r, c) = (c, -r);
+  }
+  (r, c)
+}
+
+// translates the cells so that their bounding box starts at (0, 0), and sorts them
+fn normalise(mut cells: Vec<(i8, i8)>) -> Vec<(i8, i8)> {
+  let min_r = cells.iter().map(|(r, _)| *r).min().unwrap();
+  let min_c = cells.iter().map(|(_, c)| *c).min().unwrap();
+  for (r, c) in cells.iter_mut() {
+    *r -= min_r;
+    *c -= min_c;
+  }
+  cells.sort();
+  cells
+}
+
+impl Orientation {
+  fn new(transform: u8, cells: Vec<(i8, i8)>) -> Self {
+    let shift = |(r, c): &(i8, i8), (dr, dc): &(i8, i8)| (r + dr, c + dc);
+    let mut edges = vec![];
+    for cell in cells.iter() {
+      for e in EDGES.iter() {
+        let n = shift(cell, e);
+        if !cells.contains(&n) && !edges.contains(&n) {
+          edges.push(n);
+        }
+      }
+    }
+    let mut corners = vec![];
+    let mut attachments = vec![];
+    for cell in cells.iter() {
+      for d in DIAGONALS.iter() {
+        let n = shift(cell, d);
+        if !cells.contains(&n) && !edges.contains(&n) {
+          if !corners.contains(&n) {
+            corners.push(n);
+          }
+          if !attachments.contains(cell) {
+            attachments.push(*cell);
+          }
+        }
+      }
+    }
+    edges.sort();
+    corners.sort();
+    attachments.sort();
+    let height = cells.iter().map(|(r, _)| *r).max().unwrap() as u8 + 1;
+    let mut masks = vec![0; height as usize];
+    for (r, c) in cells.iter() {
+      masks[*r as usize] |= 1 << c;
+    }
+    Orientation {
+      transform,
+      height,
+      width: cells.iter().map(|(_, c)| *c).max().unwrap() as u8 + 1,
+      cells,
+      masks,
+      attachments,
+      edges,
+      corners,
+    }
+  }
+}
+
+impl TryFrom<u8> for Piece {
+  type Error = ();
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    Piece::ALL.get(value as usize).copied().ok_or(())
+  }
+}
+
+impl Display for Piece {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{self:?}")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_orientation_counts() {
+    let counts: Vec<_> = Piece::ALL.iter().map(|p| p.orientations().len()).collect();
+    assert_eq!(
+      counts,
+      vec![1, 2, 2, 4, 2, 8, 4, 1, 4, 8, 2, 8, 8, 8, 4, 4, 4, 4, 1, 8, 4]
+    );
+    assert_eq!(counts.iter().sum::<usize>(), ORIENTATION_COUNT);
+    assert_eq!(Piece::ALL.iter().map(|p| p.size()).sum::<usize>(), 89);
+  }
+
+  #[test]
+  fn test_orientations_are_distinct() {
+    for piece in Piece::ALL {
+      let orientations = piece.orientations();
+      assert_eq!(orientations[0].transform, 0);
+      for (ix, o) in orientations.iter().enumerate() {
+        assert_eq!(o.cells.len(), piece.size());
+        assert!(o.cells.iter().any(|(r, _)| *r == 0));
+        assert!(o.cells.iter().any(|(_, c)| *c == 0));
+        for other in orientations[(ix + 1)..].iter() {
+          assert!(o.cells != other.cells, "{piece} has duplicate orientations");
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_attachment_cells() {
+    let monomino = &Piece::I1.orientations()[0];
+    assert_eq!(monomino.attachments, vec![(0, 0)]);
+    assert_eq!(monomino.edges, vec![(-1, 0), (0, -1), (0, 1), (1, 0)]);
+    assert_eq!(monomino.corners, vec![(-1, -1), (-1, 1), (1, -1), (1, 1)]);
+
+    // the middle cell of the I3 can't touch a corner
+    let i3 = &Piece::I3.orientations()[0];
+    assert_eq!(i3.attachments, vec![(0, 0), (0, 2)]);
+    assert_eq!(i3.corners.len(), 4);
+    assert_eq!(i3.edges.len(), 8);
+
+    // the X has 4 arms that attach, and 8 corners
+    let x = &Piece::X5.orientations()[0];
+    assert_eq!(x.attachments, vec![(0, 1), (1, 0), (1, 2), (2, 1)]);
+    assert_eq!(x.corners.len(), 8);
+    assert_eq!((x.height, x.width), (3, 3));
+    assert_eq!(x.masks, vec![0b010, 0b111, 0b010]);
+
+    for piece in Piece::ALL {
+      for o in piece.orientations() {
+        for cell in o.edges.iter().chain(o.corners.iter()) {
+          assert!(!o.cells.contains(cell));
+        }
+        for cell in o.corners.iter() {
+          assert!(!o.edges.contains(cell));
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,135 @@
+use crate::{Color, Piece, State};
+
+// How the final position is turned into rewards. Rewards are only given
+// on the transition that ends the game
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Scoring {
+  // -1 for every square left in hand
+  #[default]
+  Basic,
+  // Basic, plus 15 for placing every piece, and 5 more if the last piece
+  // placed was the monomino
+  Advanced,
+  // The fraction of opponents with a lower advanced score, with ties
+  // counting half. The winner gets 1, and the last placed color gets 0
+  Rank,
+}
+
+const ALL_PLACED_BONUS: i32 = 15;
+const MONOMINO_LAST_BONUS: i32 = 5;
+
+impl<const N: usize> State<N> {
+  pub fn basic_score(&self, color: Color) -> i32 {
+    -(self
+      .remaining_pieces(color)
+      .iter()
+      .map(|piece| piece.size() as i32)
+      .sum::<i32>())
+  }
+
+  pub fn advanced_score(&self, color: Color) -> i32 {
+    let mut score = self.basic_score(color);
+    if self.remaining_pieces(color).is_empty() {
+      score += ALL_PLACED_BONUS;
+      if self.last_piece[color as usize] == Some(Piece::I1) {
+        score += MONOMINO_LAST_BONUS;
+      }
+    }
+    score
+  }
+}
+
+impl Scoring {
+  pub fn rewards<const N: usize>(&self, state: &State<N>) -> [f32; N] {
+    let colors: [Color; N] = std::array::from_fn(|ix| Color::ALL[ix]);
+    match self {
+      Scoring::Basic => colors.map(|color| state.basic_score(color) as f32),
+      Scoring::Advanced => colors.map(|color| state.advanced_score(color) as f32),
+      Scoring::Rank => {
+        let scores = colors.map(|color| state.advanced_score(color));
+        scores.map(|score| {
+          let beaten: f32 = scores
+            .iter()
+            .map(|other| match score.cmp(other) {
+              std::cmp::Ordering::Greater => 1.0,
+              std::cmp::Ordering::Equal => 0.5,
+              std::cmp::Ordering::Less => 0.0,
+            })
+            .sum();
+          // a color ties with itself
+          (beaten - 0.5) / (N - 1) as f32
+        })
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{random_game, Blokus, Classic, Duo, COLOR_COUNT};
+
+  fn place(state: &mut State<COLOR_COUNT>, color: Color, piece: Piece) {
+    state.place(color, piece, 0, 0, 0);
+  }
+
+  #[test]
+  fn test_scores() {
+    let mut state = State::new::<Classic>();
+    assert_eq!(state.basic_score(Color::Blue), -89);
+    assert_eq!(state.advanced_score(Color::Blue), -89);
+
+    // only the pieces in hand matter for scoring, so put them all on the
+    // same cells
+    for piece in Piece::ALL.iter().rev() {
+      place(&mut state, Color::Blue, *piece);
+    }
+    for piece in Piece::ALL {
+      place(&mut state, Color::Yellow, piece);
+    }
+    for piece in Piece::ALL.iter().skip(1) {
+      place(&mut state, Color::Red, *piece);
+    }
+    assert_eq!(state.basic_score(Color::Blue), 0);
+    assert_eq!(state.advanced_score(Color::Blue), 20);
+    assert_eq!(state.advanced_score(Color::Yellow), 15);
+    assert_eq!(state.advanced_score(Color::Red), -1);
+    assert_eq!(state.advanced_score(Color::Green), -89);
+
+    assert_eq!(Scoring::Basic.rewards(&state), [0.0, 0.0, -1.0, -89.0]);
+    assert_eq!(Scoring::Advanced.rewards(&state), [20.0, 15.0, -1.0, -89.0]);
+    assert_eq!(
+      Scoring::Rank.rewards(&state),
+      [1.0, 2.0 / 3.0, 1.0 / 3.0, 0.0]
+    );
+
+    place(&mut state, Color::Green, Piece::I1);
+    place(&mut state, Color::Red, Piece::I1);
+    assert_eq!(
+      Scoring::Rank.rewards(&state),
+      [2.5 / 3.0, 1.0 / 3.0, 2.5 / 3.0, 0.0]
+    );
+  }
+
+  #[test]
+  fn test_rewards_at_game_end() {
+    for scoring in [Scoring::Basic, Scoring::Advanced, Scoring::Rank] {
+      let game = Blokus::<Classic>::new(scoring);
+      let mut rewards = vec![];
+      let state = random_game(&game, |_, _, _, tr| rewards.push(tr.rewards));
+      // only the last move is rewarded
+      let last = rewards.pop().unwrap();
+      assert!(rewards.iter().all(|r| *r == [0.0; COLOR_COUNT]));
+      assert_eq!(last, scoring.rewards(&state));
+    }
+  }
+
+  #[test]
+  fn test_two_color_rank() {
+    let mut state = State::new::<Duo>();
+    assert_eq!(Scoring::Rank.rewards(&state), [0.5, 0.5]);
+    state.place(Color::Blue, Piece::I5, 0, 0, 0);
+    assert_eq!(Scoring::Rank.rewards(&state), [1.0, 0.0]);
+    assert_eq!(Scoring::Basic.rewards(&state), [-84.0, -89.0]);
+  }
+}
@@ -0,0 +1,307 @@
+use crate::{zobrist, Color, Move, Piece, State, Variant};
+
+impl<const N: usize> State<N> {
+  // Sets up the position shown by a board: cells[row][col] is the color
+  // covering the cell, if any.
+  // Pieces of the same color never share an edge, so every group of cells
+  // of a color connected by edges is one piece, which tells which pieces
+  // are still in hand. What the board doesn't show is which colors have
+  // passed and which piece each color placed last. Colors without a piece
+  // or a placement left are marked stuck, as they can't move again, except
+  // the color to move, which passes, and the last piece of every color is
+  // left unknown.
+  // The pieces of every color have to be placeable by the rules, in some
+  // order, from its starting square. There is no color to move only once
+  // no color can move
+  pub fn from_cells<V: Variant<N>>(
+    cells: &[Vec<Option<Color>>],
+    color_to_move: Option<Color>,
+  ) -> Result<Self, String> {
+    let mut state = State::new::<V>();
+    if cells.len() != state.size || cells.iter().any(|row| row.len() != state.size) {
+      return Err(format!("The board has to be {0}x{0}", state.size));
+    }
+    if let Some(color) = color_to_move {
+      if color as usize >= N {
+        return Err(format!("There is no color {}", color.notation()));
+      }
+    }
+    let mut pieces: [Vec<Move>; N] = std::array::from_fn(|_| vec![]);
+    let mut seen = vec![vec![false; state.size]; state.size];
+    for row in 0..state.size {
+      for col in 0..state.size {
+        let Some(color) = cells[row][col] else {
+          continue;
+        };
+        if seen[row][col] {
+          continue;
+        }
+        if color as usize >= N {
+          return Err(format!("There is no color {}", color.notation()));
+        }
+        let piece_cells = state.component(cells, &mut seen, color, (row, col));
+        let m = state.piece_covering(color, &piece_cells)?;
+        pieces[color as usize].push(m);
+      }
+    }
+    for (color, pieces) in state.colors().zip(pieces) {
+      state.place_in_order(color, pieces)?;
+    }
+    for color in state.colors() {
+      // the color to move still has its turn, if only to pass
+      if Some(color) != color_to_move && state.placements(color).is_empty() {
+        state.set_stuck(color);
+      }
+    }
+    if color_to_move.is_none() {
+      if let Some(color) = state.colors().find(|color| !state.stuck[*color as usize]) {
+        return Err(format!(
+          "The game isn't over, {} can move",
+          color.notation()
+        ));
+      }
+    }
+    state.key ^= zobrist::to_move_key(state.color_to_move);
+    state.color_to_move = color_to_move;
+    state.key ^= zobrist::to_move_key(state.color_to_move);
+    Ok(state)
+  }
+
+  // The cells of color connected to start by edges
+  fn component(
+    &self,
+    cells: &[Vec<Option<Color>>],
+    seen: &mut [Vec<bool>],
+    color: Color,
+    start: (usize, usize),
+  ) -> Vec<(usize, usize)> {
+    let mut result = vec![start];
+    seen[start.0][start.1] = true;
+    let mut ix = 0;
+    while ix < result.len() {
+      let (row, col) = result[ix];
+      let neighbours = [
+        (row.wrapping_sub(1), col),
+        (row + 1, col),
+        (row, col.wrapping_sub(1)),
+        (row, col + 1),
+      ];
+      for (r, c) in neighbours {
+        if r < self.size && c < self.size && !seen[r][c] && cells[r][c] == Some(color) {
+          seen[r][c] = true;
+          result.push((r, c));
+        }
+      }
+      ix += 1;
+    }
+    result
+  }
+
+  // The placement of the piece covering exactly the cells
+  fn piece_covering(&self, color: Color, cells: &[(usize, usize)]) -> Result<Move, String> {
+    let row = cells.iter().map(|(r, _)| *r).min().unwrap();
+    let col = cells.iter().map(|(_, c)| *c).min().unwrap();
+    let mut shape: Vec<_> = cells
+      .iter()
+      .map(|(r, c)| ((r - row) as i8, (c - col) as i8))
+      .collect();
+    shape.sort();
+    let not_a_piece = || {
+      format!(
+        "The {} cells at ({row},{col}) aren't a piece",
+        color.notation()
+      )
+    };
+    let (piece, orientation) = Piece::ALL
+      .into_iter()
+      .filter(|piece| piece.size() == shape.len())
+      .find_map(|piece| {
+        piece
+          .orientations()
+          .iter()
+          .position(|o| o.cells == shape)
+          .map(|orientation| (piece, orientation as u8))
+      })
+      .ok_or_else(not_a_piece)?;
+    Ok(Move::Place {
+      color,
+      piece,
+      orientation,
+      row: row as u8,
+      col: col as u8,
+    })
+  }
+
+  // Places the pieces of color one at a time, each where the rules allow it
+  // then. Placing a piece never makes another illegal unless they share an
+  // edge, which no order allows, so any legal piece will do at every step
+  fn place_in_order(&mut self, color: Color, mut pieces: Vec<Move>) -> Result<(), String> {
+    for (ix, m) in pieces.iter().enumerate() {
+      let Move::Place { piece, .. } = m else {
+        continue;
+      };
+      if pieces[..ix]
+        .iter()
+        .any(|other| matches!(other, Move::Place { piece: p, .. } if p == piece))
+      {
+        return Err(format!("{} has placed {piece} twice", color.notation()));
+      }
+    }
+    let mut placed = 0;
+    while !pieces.is_empty() {
+      let legal = self.placements(color);
+      let Some(ix) = pieces.iter().position(|m| legal.contains(m)) else {
+        return Err(if placed == 0 {
+          let (row, col) = self.start(color);
+          format!("No {} piece covers ({row},{col})", color.notation())
+        } else {
+          format!(
+            "The {} pieces aren't all joined corner to corner",
+            color.notation()
+          )
+        });
+      };
+      let Move::Place {
+        piece,
+        orientation,
+        row,
+        col,
+        ..
+      } = pieces.swap_remove(ix)
+      else {
+        unreachable!()
+      };
+      self.place(color, piece, orientation, row as usize, col as usize);
+      placed += 1;
+    }
+    // a piece placed from a board isn't known to be the last one
+    self.last_piece[color as usize] = None;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rustyai::{KeyableState, MaMdp};
+
+  use super::*;
+  use crate::{play_game, Blokus, Classic, Duo};
+
+  fn cells<const N: usize>(state: &State<N>) -> Vec<Vec<Option<Color>>> {
+    (0..state.size())
+      .map(|row| (0..state.size()).map(|col| state.get(row, col)).collect())
+      .collect()
+  }
+
+  // the state read back from the cells of state plays the same
+  fn check_copy<V: Variant<N>, const N: usize>(state: &State<N>) {
+    let copy = State::from_cells::<V>(&cells(state), state.color_to_move()).unwrap();
+    assert_eq!(copy.to_string(), state.to_string());
+    for color in state.colors() {
+      assert_eq!(copy.remaining_pieces(color), state.remaining_pieces(color));
+      let mut moves = state.placements(color);
+      moves.sort();
+      let mut copy_moves = copy.placements(color);
+      copy_moves.sort();
+      assert_eq!(moves, copy_moves);
+    }
+    assert_eq!(copy.key(), copy.compute_key());
+  }
+
+  fn check_round_trip<V: Variant<N>, const N: usize>() {
+    let game = Blokus::<V>::default();
+    // the same spread of moves every run, so a failing board can be found again
+    let state = play_game(
+      &game,
+      |ply, moves| moves[(ply * 7919) % moves.len()],
+      |state, _, _, _| check_copy::<V, N>(state),
+    );
+    check_copy::<V, N>(&state);
+  }
+
+  #[test]
+  fn test_round_trip() {
+    check_round_trip::<Classic, 4>();
+    check_round_trip::<Duo, 2>();
+  }
+
+  #[test]
+  fn test_invalid_boards() {
+    let mut board = vec![vec![None; 14]; 14];
+    assert!(State::from_cells::<Duo>(&board, Some(Color::Blue)).is_ok());
+    assert!(State::from_cells::<Classic>(&board, Some(Color::Blue)).is_err());
+    assert!(State::from_cells::<Duo>(&board, Some(Color::Red)).is_err());
+
+    // a 2x3 block is no piece
+    for row in board[4..6].iter_mut() {
+      row[4..7].fill(Some(Color::Blue));
+    }
+    assert!(State::from_cells::<Duo>(&board, Some(Color::Blue)).is_err());
+
+    // neither are two monominoes
+    let mut board = vec![vec![None; 14]; 14];
+    board[4][4] = Some(Color::Blue);
+    board[5][5] = Some(Color::Blue);
+    assert!(State::from_cells::<Duo>(&board, Some(Color::Yellow)).is_err());
+    board[5][5] = Some(Color::Green);
+    assert!(State::from_cells::<Duo>(&board, Some(Color::Yellow)).is_err());
+
+    // no color is to move only once none can
+    let board = vec![vec![None; 14]; 14];
+    let error = State::from_cells::<Duo>(&board, None).err().unwrap();
+    assert_eq!(error, "The game isn't over, C1 can move");
+    // and a color to move that can't place still has to pass
+    let game = Blokus::<Duo>::default();
+    let over = play_game(&game, |_, moves| moves[0], |_, _, _, _| ());
+    let mut state = State::from_cells::<Duo>(&cells(&over), Some(Color::Blue)).unwrap();
+    assert_eq!(game.actions(&state, 0), [Move::Pass]);
+    game.transition(&mut state, &[Move::Pass; 2]);
+    assert!(state.is_over());
+  }
+
+  #[test]
+  fn test_unplayable_boards() {
+    let from_cells =
+      |board: &Vec<Vec<Option<Color>>>| State::from_cells::<Duo>(board, Some(Color::Blue));
+    // blue covers its starting square with a domino, then a monomino off its
+    // corner
+    let mut board = vec![vec![None; 14]; 14];
+    board[4][4] = Some(Color::Blue);
+    board[4][5] = Some(Color::Blue);
+    board[5][6] = Some(Color::Blue);
+    assert!(from_cells(&board).is_ok());
+
+    // a piece away from the starting square
+    let mut moved = vec![vec![None; 14]; 14];
+    moved[0][0] = Some(Color::Blue);
+    let error = from_cells(&moved).err().unwrap();
+    assert_eq!(error, "No C1 piece covers (4,4)");
+
+    // pieces sharing an edge read as one shape, which is no piece
+    let mut touching = board.clone();
+    touching[3][5..10].fill(Some(Color::Blue));
+    assert!(from_cells(&touching).is_err());
+
+    // a piece touching no corner of the others
+    let mut apart = board.clone();
+    apart[8][8] = Some(Color::Blue);
+    apart[8][9] = Some(Color::Blue);
+    apart[8][10] = Some(Color::Blue);
+    let error = from_cells(&apart).err().unwrap();
+    assert_eq!(error, "The C1 pieces aren't all joined corner to corner");
+
+    // nor does one touching a corner of another color only
+    let mut other = board.clone();
+    other[9][9] = Some(Color::Yellow);
+    assert!(from_cells(&other).is_ok());
+    other[10][10] = Some(Color::Blue);
+    assert!(from_cells(&other).is_err());
+    assert_eq!(
+      from_cells(&board)
+        .unwrap()
+        .remaining_pieces(Color::Blue)
+        .len(),
+      19
+    );
+  }
+}
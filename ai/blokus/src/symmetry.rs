@@ -0,0 +1,224 @@
+use crate::{Bitboard, Color, Move, State};
+
+// A symmetry of a variant: a symmetry of the square board, together with
+// the relabelling of the colors that maps each color's starting square to
+// the starting square of its new color. Cycling the colors keeps the turn
+// order, so a position and its image play out the same way, up to the
+// relabelling
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct Symmetry {
+  // as for piece orientations: 0..4 rotate the board clockwise by 90
+  // degrees, 4..8 mirror the columns before rotating
+  pub transform: u8,
+  // color i becomes color (i + color_shift) % N
+  pub color_shift: u8,
+}
+
+impl Symmetry {
+  pub const IDENTITY: Symmetry = Symmetry {
+    transform: 0,
+    color_shift: 0,
+  };
+
+  // The symmetry undoing this one, for a variant with the given number of
+  // colors
+  pub fn inverse(&self, colors: usize) -> Symmetry {
+    Symmetry {
+      // reflections are their own inverse
+      transform: if self.transform < 4 {
+        (4 - self.transform) % 4
+      } else {
+        self.transform
+      },
+      color_shift: ((colors - self.color_shift as usize) % colors) as u8,
+    }
+  }
+
+  // The image of (row, col) on a board with the given side
+  pub fn cell(&self, size: usize, (row, col): (usize, usize)) -> (usize, usize) {
+    let (mut row, mut col) = if self.transform >= 4 {
+      (row, size - 1 - col)
+    } else {
+      (row, col)
+    };
+    for _ in 0..(self.transform % 4) {
+      (row, col) = (col, size - 1 - row);
+    }
+    (row, col)
+  }
+}
+
+impl<const N: usize> State<N> {
+  // The symmetries of the variant, the identity first
+  pub fn symmetries(&self) -> &'static [Symmetry] {
+    self.symmetries
+  }
+
+  pub fn relabel(&self, sym: Symmetry, color: Color) -> Color {
+    Color::ALL[(color as usize + sym.color_shift as usize) % N]
+  }
+
+  // The image of the position under sym
+  pub fn transformed(&self, sym: Symmetry) -> State<N> {
+    let map = |board: &Bitboard| {
+      let mut result = Bitboard::new();
+      for cell in board.iter() {
+        let (row, col) = sym.cell(self.size, cell);
+        result.set(row, col);
+      }
+      result
+    };
+    let mut result = self.clone();
+    for color in self.colors() {
+      let (from, to) = (color as usize, self.relabel(sym, color) as usize);
+      result.colors[to] = map(&self.colors[from]);
+      result.forbidden[to] = map(&self.forbidden[from]);
+      result.corners[to] = map(&self.corners[from]);
+      result.remaining[to] = self.remaining[from];
+      result.last_piece[to] = self.last_piece[from];
+      result.stuck[to] = self.stuck[from];
+    }
+    result.color_to_move = self.color_to_move.map(|color| self.relabel(sym, color));
+    result.key = result.compute_key();
+    result
+  }
+
+  // The image of a move played in this position, to play in the
+  // transformed position
+  pub fn transform_move(&self, sym: Symmetry, m: &Move) -> Move {
+    let Move::Place {
+      color,
+      piece,
+      orientation,
+      row,
+      col,
+    } = *m
+    else {
+      return Move::Pass;
+    };
+    let cells: Vec<_> = piece.orientations()[orientation as usize]
+      .cells
+      .iter()
+      .map(|(r, c)| {
+        sym.cell(
+          self.size,
+          (row as usize + *r as usize, col as usize + *c as usize),
+        )
+      })
+      .collect();
+    let row = cells.iter().map(|(r, _)| *r).min().unwrap();
+    let col = cells.iter().map(|(_, c)| *c).min().unwrap();
+    let mut shape: Vec<_> = cells
+      .iter()
+      .map(|(r, c)| ((r - row) as i8, (c - col) as i8))
+      .collect();
+    shape.sort();
+    let orientation = piece
+      .orientations()
+      .iter()
+      .position(|o| o.cells == shape)
+      .unwrap();
+    Move::Place {
+      color: self.relabel(sym, color),
+      piece,
+      orientation: orientation as u8,
+      row: row as u8,
+      col: col as u8,
+    }
+  }
+
+  // The smallest key over the images of the position, and the symmetry
+  // giving it. Positions with the same canonical key are the same up to
+  // symmetry, and moves for the canonical position can be brought back with
+  // transform_move and the inverse symmetry
+  pub fn canonical(&self) -> (u64, Symmetry) {
+    self
+      .symmetries
+      .iter()
+      .map(|sym| {
+        if *sym == Symmetry::IDENTITY {
+          (self.key, *sym)
+        } else {
+          (self.transformed(*sym).key, *sym)
+        }
+      })
+      .min_by_key(|(key, _)| *key)
+      .unwrap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rustyai::{KeyableState, MaMdp};
+
+  use super::*;
+  use crate::{random_game, Blokus, Classic, Duo, Piece, Variant};
+
+  fn check_symmetries<V: Variant<N>, const N: usize>() {
+    let game = Blokus::<V>::default();
+    let state = game.initial_state();
+    assert_eq!(state.symmetries()[0], Symmetry::IDENTITY);
+    for (color, start) in state.colors().zip(V::STARTS) {
+      for sym in state.symmetries() {
+        let image = state.start(state.relabel(*sym, color));
+        assert_eq!(sym.cell(V::SIZE, start), image);
+      }
+    }
+    random_game(&game, |state, color, _, _| {
+      let canonical = state.canonical().0;
+      let mut moves = state.placements(color);
+      moves.sort();
+      for sym in state.symmetries() {
+        let image = state.transformed(*sym);
+        assert_eq!(image.canonical().0, canonical);
+        let back = image.transformed(sym.inverse(N));
+        assert_eq!(back.key(), state.key());
+        assert_eq!(back.to_string(), state.to_string());
+
+        let mut mapped: Vec<_> = moves
+          .iter()
+          .map(|m| state.transform_move(*sym, m))
+          .collect();
+        mapped.sort();
+        let mut image_moves = image.placements(image.color_to_move().unwrap());
+        image_moves.sort();
+        assert_eq!(mapped, image_moves);
+      }
+    });
+  }
+
+  #[test]
+  fn test_symmetries() {
+    check_symmetries::<Classic, 4>();
+    check_symmetries::<Duo, 2>();
+  }
+
+  #[test]
+  fn test_duo_reflection() {
+    let game = Blokus::<Duo>::default();
+    let m = Move::Place {
+      color: Color::Blue,
+      piece: Piece::L4,
+      orientation: 0,
+      row: 2,
+      col: 4,
+    };
+    let mut a = game.initial_state();
+    let mut b = game.initial_state();
+    let reflected = b.transform_move(Duo::SYMMETRIES[1], &m);
+    assert_eq!(
+      reflected,
+      Move::Place {
+        color: Color::Blue,
+        piece: Piece::L4,
+        orientation: Piece::L4.orientation_index(7).unwrap(),
+        row: 4,
+        col: 2,
+      }
+    );
+    game.transition(&mut a, &[m, Move::Pass]);
+    game.transition(&mut b, &[reflected, Move::Pass]);
+    assert_ne!(a.key(), b.key());
+    assert_eq!(a.canonical().0, b.canonical().0);
+  }
+}
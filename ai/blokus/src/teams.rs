@@ -0,0 +1,176 @@
+use rustyai::{MaMdp, TranstitionResult};
+
+use crate::{Blokus, Classic, Color, Move, Scoring, State, COLOR_COUNT};
+
+// Classic Blokus for two players, where each player controls two opposite
+// colors: agent 0 plays Blue and Red, agent 1 plays Yellow and Green.
+// A player's reward is the combined score of its colors, or for rank
+// scoring, 1 for the player with the higher combined advanced score
+pub struct Teams {
+  game: Blokus<Classic>,
+}
+
+impl Teams {
+  pub fn new(scoring: Scoring) -> Self {
+    Teams {
+      game: Blokus::new(scoring),
+    }
+  }
+
+  // The agent playing the color
+  pub fn owner(color: Color) -> usize {
+    color as usize % 2
+  }
+
+  fn rewards(&self, state: &State<COLOR_COUNT>) -> [f32; 2] {
+    let mut result = [0.0; 2];
+    match self.game.scoring() {
+      Scoring::Rank => {
+        let mut scores = [0; 2];
+        for color in Color::ALL {
+          scores[Teams::owner(color)] += state.advanced_score(color);
+        }
+        result[0] = match scores[0].cmp(&scores[1]) {
+          std::cmp::Ordering::Greater => 1.0,
+          std::cmp::Ordering::Equal => 0.5,
+          std::cmp::Ordering::Less => 0.0,
+        };
+        result[1] = 1.0 - result[0];
+      }
+      scoring => {
+        for (color, reward) in Color::ALL.iter().zip(scoring.rewards(state)) {
+          result[Teams::owner(*color)] += reward;
+        }
+      }
+    }
+    result
+  }
+}
+
+impl Default for Teams {
+  fn default() -> Self {
+    Teams::new(Scoring::default())
+  }
+}
+
+impl MaMdp<State<COLOR_COUNT>, Move, Move, 2> for Teams {
+  fn initial_state(&self) -> State<COLOR_COUNT> {
+    self.game.initial_state()
+  }
+
+  fn actions(&self, state: &State<COLOR_COUNT>, agent: usize) -> Vec<Move> {
+    match state.color_to_move() {
+      // Game over
+      None => vec![],
+      Some(color) if Teams::owner(color) == agent => self.game.actions(state, color as usize),
+      Some(_) => vec![Move::Pass],
+    }
+  }
+
+  fn transition(
+    &self,
+    state: &mut State<COLOR_COUNT>,
+    joint_action: &[Move; 2],
+  ) -> TranstitionResult<Move, 2> {
+    let color = state
+      .color_to_move()
+      .expect("transition on a finished game");
+    let agent = Teams::owner(color);
+    debug_assert!(
+      joint_action[1 - agent] == Move::Pass,
+      "Only the player owning the color to move can place a piece"
+    );
+    let mut colors_action = [Move::Pass; COLOR_COUNT];
+    colors_action[color as usize] = joint_action[agent];
+    self.game.transition(state, &colors_action);
+    TranstitionResult {
+      rewards: if state.is_over() {
+        self.rewards(state)
+      } else {
+        [0.0; 2]
+      },
+      observations: [joint_action[agent]; 2],
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::seq::SliceRandom;
+  use rustyai::search::{
+    eval::RandomRolloutEval,
+    forest::{refcnt_forest::Node, TreeNode, TreeNodePtr},
+    Search, Uct,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_turns() {
+    let game = Teams::default();
+    let mut state = game.initial_state();
+    for color in Color::ALL {
+      assert_eq!(state.color_to_move(), Some(color));
+      let agent = Teams::owner(color);
+      let actions = game.actions(&state, agent);
+      assert_eq!(actions.len(), 58);
+      assert_eq!(game.actions(&state, 1 - agent), vec![Move::Pass]);
+      let mut joint_action = [Move::Pass; 2];
+      joint_action[agent] = actions[0];
+      let tr = game.transition(&mut state, &joint_action);
+      assert_eq!(tr.observations, [actions[0]; 2]);
+    }
+    assert_eq!(state.color_to_move(), Some(Color::Blue));
+  }
+
+  #[test]
+  fn test_team_rewards() {
+    for scoring in [Scoring::Basic, Scoring::Advanced, Scoring::Rank] {
+      let game = Teams::new(scoring);
+      let mut state = game.initial_state();
+      let mut total = [0.0; 2];
+      while let Some(color) = state.color_to_move() {
+        let agent = Teams::owner(color);
+        let actions = game.actions(&state, agent);
+        let mut joint_action = [Move::Pass; 2];
+        joint_action[agent] = *actions.choose(&mut rand::thread_rng()).unwrap();
+        let tr = game.transition(&mut state, &joint_action);
+        total[0] += tr.rewards[0];
+        total[1] += tr.rewards[1];
+      }
+      let team_score = |agent| -> i32 {
+        Color::ALL
+          .iter()
+          .filter(|color| Teams::owner(**color) == agent)
+          .map(|color| state.advanced_score(*color))
+          .sum()
+      };
+      match scoring {
+        Scoring::Basic => {
+          let basic = |color| state.basic_score(color) as f32;
+          assert_eq!(total[0], basic(Color::Blue) + basic(Color::Red));
+          assert_eq!(total[1], basic(Color::Yellow) + basic(Color::Green));
+        }
+        Scoring::Advanced => {
+          assert_eq!(total, [team_score(0) as f32, team_score(1) as f32]);
+        }
+        Scoring::Rank => {
+          assert_eq!(total[0] + total[1], 1.0);
+          assert_eq!(total[0] > total[1], team_score(0) > team_score(1));
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_uct() {
+    let game = Teams::new(Scoring::Rank);
+    let state = game.initial_state();
+    let s = Search::new(Uct(2.4), RandomRolloutEval::new(4));
+    let trees = [Node::new(), Node::new()];
+    for _ in 0..100 {
+      s.step_mdp(&game, &state, [trees[0].clone(), trees[1].clone()]);
+    }
+    assert_eq!(trees[0].lock().actions().len(), 58);
+  }
+}
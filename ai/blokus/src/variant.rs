@@ -0,0 +1,48 @@
+use crate::Symmetry;
+
+// The board layout of a Blokus game for N colors. The engine, and so the
+// search, only depends on the variant through these constants
+pub trait Variant<const N: usize> {
+  // used to identify the variant in game records
+  const NAME: &'static str;
+  // side of the square board, at most MAX_BOARD_SIZE
+  const SIZE: usize;
+  // (row, col) of the square each color's first piece has to cover
+  const STARTS: [(usize, usize); N];
+  // the symmetries of the layout, starting with the identity
+  const SYMMETRIES: &'static [Symmetry];
+}
+
+const fn symmetry(transform: u8, color_shift: u8) -> Symmetry {
+  Symmetry {
+    transform,
+    color_shift,
+  }
+}
+
+// The original game: 4 colors on a 20x20 board, starting from the corners
+pub struct Classic;
+
+impl Variant<4> for Classic {
+  const NAME: &'static str = "Classic";
+  const SIZE: usize = 20;
+  const STARTS: [(usize, usize); 4] = [(0, 0), (0, 19), (19, 19), (19, 0)];
+  // each quarter turn takes every corner to the next color's
+  const SYMMETRIES: &'static [Symmetry] = &[
+    symmetry(0, 0),
+    symmetry(1, 1),
+    symmetry(2, 2),
+    symmetry(3, 3),
+  ];
+}
+
+// Blokus Duo: 2 colors on a 14x14 board, starting near the middle
+pub struct Duo;
+
+impl Variant<2> for Duo {
+  const NAME: &'static str = "Duo";
+  const SIZE: usize = 14;
+  const STARTS: [(usize, usize); 2] = [(4, 4), (9, 9)];
+  // the reflection in the diagonal through both starting squares
+  const SYMMETRIES: &'static [Symmetry] = &[symmetry(0, 0), symmetry(7, 0)];
+}
@@ -0,0 +1,176 @@
+use rustyai::KeyableState;
+
+use crate::{Color, Orientation, Piece, State, COLOR_COUNT, MAX_BOARD_SIZE, PIECE_COUNT};
+
+// Zobrist keys. A position's key is the xor of the keys of
+// - every covered cell, for the color covering it
+// - every piece placed, for the color that placed it
+// - every stuck color
+// - the color to move
+// The keys are generated at compile time from a fixed seed, so keys stay
+// the same across builds and can be stored, e.g. in an opening book.
+// Which piece was placed last isn't part of the key, so two finished
+// games differing only in the monomino bonus share a key
+struct Keys {
+  cells: [[u64; MAX_BOARD_SIZE * MAX_BOARD_SIZE]; COLOR_COUNT],
+  pieces: [[u64; PIECE_COUNT]; COLOR_COUNT],
+  stuck: [u64; COLOR_COUNT],
+  to_move: [u64; COLOR_COUNT],
+}
+
+static KEYS: Keys = Keys::generate();
+
+// splitmix64
+const fn next(seed: &mut u64) -> u64 {
+  *seed = seed.wrapping_add(0x9e3779b97f4a7c15);
+  let mut z = *seed;
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+  z ^ (z >> 31)
+}
+
+impl Keys {
+  const fn generate() -> Keys {
+    let mut seed = 0x426c6f6b7573;
+    let mut keys = Keys {
+      cells: [[0; MAX_BOARD_SIZE * MAX_BOARD_SIZE]; COLOR_COUNT],
+      pieces: [[0; PIECE_COUNT]; COLOR_COUNT],
+      stuck: [0; COLOR_COUNT],
+      to_move: [0; COLOR_COUNT],
+    };
+    let mut color = 0;
+    while color < COLOR_COUNT {
+      let mut cell = 0;
+      while cell < MAX_BOARD_SIZE * MAX_BOARD_SIZE {
+        keys.cells[color][cell] = next(&mut seed);
+        cell += 1;
+      }
+      let mut piece = 0;
+      while piece < PIECE_COUNT {
+        keys.pieces[color][piece] = next(&mut seed);
+        piece += 1;
+      }
+      keys.stuck[color] = next(&mut seed);
+      keys.to_move[color] = next(&mut seed);
+      color += 1;
+    }
+    keys
+  }
+}
+
+pub(crate) fn cell_key(color: Color, row: usize, col: usize) -> u64 {
+  KEYS.cells[color as usize][row * MAX_BOARD_SIZE + col]
+}
+
+pub(crate) fn stuck_key(color: Color) -> u64 {
+  KEYS.stuck[color as usize]
+}
+
+pub(crate) fn to_move_key(color: Option<Color>) -> u64 {
+  color.map_or(0, |color| KEYS.to_move[color as usize])
+}
+
+// The change in key from color placing the piece in orientation o at
+// (row, col)
+pub(crate) fn placement_key(
+  color: Color,
+  piece: Piece,
+  o: &Orientation,
+  row: usize,
+  col: usize,
+) -> u64 {
+  o.cells.iter().fold(
+    KEYS.pieces[color as usize][piece as usize],
+    |key, (r, c)| key ^ cell_key(color, row + *r as usize, col + *c as usize),
+  )
+}
+
+impl<const N: usize> State<N> {
+  // The key computed from scratch, rather than kept up to date move by move
+  pub(crate) fn compute_key(&self) -> u64 {
+    let mut key = to_move_key(self.color_to_move);
+    for color in self.colors() {
+      let ix = color as usize;
+      for (row, col) in self.colors[ix].iter() {
+        key ^= cell_key(color, row, col);
+      }
+      for piece in Piece::ALL {
+        if !self.has_piece(color, piece) {
+          key ^= KEYS.pieces[ix][piece as usize];
+        }
+      }
+      if self.stuck[ix] {
+        key ^= stuck_key(color);
+      }
+    }
+    key
+  }
+}
+
+impl<const N: usize> KeyableState<u64> for State<N> {
+  fn key(&self) -> u64 {
+    self.key
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rustyai::MaMdp;
+
+  use super::*;
+  use crate::{random_game, Blokus, Classic, Duo, Move, Variant};
+
+  fn check_keys<V: Variant<N>, const N: usize>() {
+    let mut keys = vec![];
+    let state = random_game(&Blokus::<V>::default(), |state, _, _, _| {
+      assert_eq!(state.key(), state.compute_key());
+      keys.push(state.key());
+    });
+    assert_eq!(state.key(), state.compute_key());
+    keys.push(state.key());
+    // every position of a game is different
+    let count = keys.len();
+    keys.sort();
+    keys.dedup();
+    assert_eq!(keys.len(), count);
+  }
+
+  #[test]
+  fn test_incremental_keys() {
+    check_keys::<Classic, 4>();
+    check_keys::<Duo, 2>();
+  }
+
+  #[test]
+  fn test_transpositions() {
+    let game = Blokus::<Duo>::default();
+    let place = |color, piece: Piece, row, col| {
+      let mut joint_action = [Move::Pass; 2];
+      joint_action[color as usize] = Move::Place {
+        color,
+        piece,
+        orientation: 0,
+        row,
+        col,
+      };
+      joint_action
+    };
+    // the same pieces on the same cells, in a different order
+    let mut a = game.initial_state();
+    let mut b = game.initial_state();
+    for state in [&mut a, &mut b] {
+      game.transition(state, &place(Color::Blue, Piece::I1, 4, 4));
+      game.transition(state, &place(Color::Yellow, Piece::I1, 9, 9));
+    }
+    game.transition(&mut a, &place(Color::Blue, Piece::I2, 5, 5));
+    game.transition(&mut b, &place(Color::Blue, Piece::O4, 2, 5));
+    assert_ne!(a.key(), b.key());
+    for state in [&mut a, &mut b] {
+      game.transition(state, &place(Color::Yellow, Piece::I2, 10, 10));
+    }
+    game.transition(&mut a, &place(Color::Blue, Piece::O4, 2, 5));
+    game.transition(&mut b, &place(Color::Blue, Piece::I2, 5, 5));
+    assert_eq!(a.to_string(), b.to_string());
+    assert_eq!(a.key(), b.key());
+  }
+}
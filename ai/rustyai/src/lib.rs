@@ -3,6 +3,7 @@ mod traits;
 pub use traits::{
   mdp::MaMdp,
   pomdp::{BlockMaPomdp, MaPomdp, SampleResult, TranstitionResult},
+  KeyableState,
 };
 
 #[cfg(test)]
@@ -1,9 +1,74 @@
 // Code generated by jtd-codegen for TypeScript v0.2.1
 
+/**
+ * What happens to a color out of time: it passes for the rest of the game,
+ * or also forfeits
+ */
+export enum GameClockOnTimeout {
+  Forfeit = "FORFEIT",
+  Pass = "PASS",
+}
+
+export type GameClockTimeControl = GameClockTimeControlFischer | GameClockTimeControlPerMove;
+
+/**
+ * Each color has initial_ms for the game, and gains increment_ms with each
+ * of its moves
+ */
+export interface GameClockTimeControlFischer {
+  kind: "FISCHER";
+  increment_ms: number;
+  initial_ms: number;
+}
+
+export interface GameClockTimeControlPerMove {
+  kind: "PER_MOVE";
+  limit_ms: number;
+}
+
+export interface GameClock {
+  /**
+   * What happens to a color out of time: it passes for the rest of the game,
+   * or also forfeits
+   */
+  on_timeout: GameClockOnTimeout;
+  time_control: GameClockTimeControl;
+}
+
+/**
+ * What the game did to the rating of the player, once it ended. Bots keep
+ * theirs
+ */
+export interface GamePlayerRatingChange {
+  delta: number;
+  rating: number;
+}
+
 export interface GamePlayer {
   color: Color;
   score: number;
   user: User;
+
+  /**
+   * The difficulty of the search playing this color, for bots
+   */
+  bot?: Difficulty;
+
+  /**
+   * Set when the color ran out of time in a game whose clock forfeits
+   */
+  forfeited?: boolean;
+
+  /**
+   * The time the color has left, in games with a clock that have started
+   */
+  remaining_ms?: number;
+
+  /**
+   * What the game did to the rating of the player, once it ended. Bots keep
+   * theirs
+   */
+  rating_change?: GamePlayerRatingChange;
 }
 
 export enum GameStatus {
@@ -12,27 +77,34 @@ export enum GameStatus {
   WaitingForPlayers = "WAITING_FOR_PLAYERS",
 }
 
+export enum GameVariant {
+  Classic = "CLASSIC",
+  Duo = "DUO",
+}
+
 export interface Game {
   id: string;
+  moves: Move[];
   players: GamePlayer[];
+
+  /**
+   * Bumped whenever the schema changes in a way older clients can't read
+   */
+  schema_version: number;
   status: GameStatus;
+  variant: GameVariant;
   board?: Board;
-}
-
-export interface BoardRemainingTiles {
-  color: Color;
-  tiles: Tile[];
-}
+  clock?: GameClock;
 
-export interface BoardScore {
-  color: Color;
-  score: number;
+  /**
+   * The code to join a private game with, only sent to its creator
+   */
+  invite_code?: string;
 }
 
 export interface Board {
+  colors: ColorState[];
   over: boolean;
-  remaining_tiles: BoardRemainingTiles;
-  score: BoardScore;
   table: (Color | null)[][];
   color_to_move?: Color;
 }
@@ -44,13 +116,82 @@ export enum Color {
   C4 = "C4",
 }
 
-export enum Tile {
-  T1 = "T1",
-  T2 = "T2",
-  T3 = "T3",
+export interface ColorState {
+  color: Color;
+  remaining_pieces: Piece[];
+  score: number;
+}
+
+export enum Difficulty {
+  Easy = "EASY",
+  Hard = "HARD",
+  Medium = "MEDIUM",
+}
+
+export type Move = MovePass | MovePlace;
+
+export interface MovePass {
+  kind: "PASS";
+  color: Color;
+}
+
+/**
+ * The top left corner of the bounding box of the placed piece
+ */
+export interface MovePlaceAnchor {
+  col: number;
+  row: number;
+}
+
+export interface MovePlace {
+  kind: "PLACE";
+
+  /**
+   * The top left corner of the bounding box of the placed piece
+   */
+  anchor: MovePlaceAnchor;
+  color: Color;
+  orientation: Orientation;
+  piece: Piece;
+}
+
+/**
+ * R0..R3 rotate the piece clockwise by 90 degrees, F0..F3 mirror its
+ * columns before rotating
+ */
+export enum Orientation {
+  F0 = "F0",
+  F1 = "F1",
+  F2 = "F2",
+  F3 = "F3",
+  R0 = "R0",
+  R1 = "R1",
+  R2 = "R2",
+  R3 = "R3",
+}
+
+export enum Piece {
+  F5 = "F5",
+  I1 = "I1",
+  I2 = "I2",
+  I3 = "I3",
+  I4 = "I4",
+  I5 = "I5",
+  L4 = "L4",
+  L5 = "L5",
+  N5 = "N5",
+  O4 = "O4",
+  P5 = "P5",
   T4 = "T4",
   T5 = "T5",
-  T6 = "T6",
+  U5 = "U5",
+  V3 = "V3",
+  V5 = "V5",
+  W5 = "W5",
+  X5 = "X5",
+  Y5 = "Y5",
+  Z4 = "Z4",
+  Z5 = "Z5",
 }
 
 export interface User {
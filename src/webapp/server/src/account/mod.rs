@@ -0,0 +1,394 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+  async_trait,
+  extract::{rejection::JsonRejection, FromRef, FromRequestParts, State},
+  http::{
+    header::{AUTHORIZATION, COOKIE, SET_COOKIE},
+    request::Parts,
+    HeaderMap, StatusCode,
+  },
+  response::{IntoResponse, Response},
+  routing::{get, post},
+  Json, Router,
+};
+use rand::{Rng, RngCore};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{
+  api::ApiError,
+  store::{Store, StoreError},
+};
+
+mod password;
+
+const SESSION_COOKIE: &str = "session";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct User {
+  pub id: String,
+  pub name: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Account {
+  pub user: User,
+  // the password hash, see password.rs. Guests have no password, so they
+  // can't log in again once their session is gone
+  pub password: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+  NameTaken,
+  InvalidName,
+  WeakPassword,
+  BadCredentials,
+  // no valid session came with the request
+  Unauthenticated,
+  Store(StoreError),
+}
+
+impl From<StoreError> for AccountError {
+  fn from(error: StoreError) -> Self {
+    AccountError::Store(error)
+  }
+}
+
+impl From<AccountError> for ApiError {
+  fn from(error: AccountError) -> Self {
+    let (status, code, message) = match error {
+      AccountError::NameTaken => (
+        StatusCode::CONFLICT,
+        "NAME_TAKEN",
+        "The name is taken".to_string(),
+      ),
+      AccountError::InvalidName => (
+        StatusCode::BAD_REQUEST,
+        "INVALID_NAME",
+        "Names have 3 to 24 letters, digits, '-' or '_', and don't start with Guest".to_string(),
+      ),
+      AccountError::WeakPassword => (
+        StatusCode::BAD_REQUEST,
+        "WEAK_PASSWORD",
+        "Passwords have at least 8 characters".to_string(),
+      ),
+      AccountError::BadCredentials => (
+        StatusCode::UNAUTHORIZED,
+        "BAD_CREDENTIALS",
+        "Wrong name or password".to_string(),
+      ),
+      AccountError::Unauthenticated => (
+        StatusCode::UNAUTHORIZED,
+        "UNAUTHENTICATED",
+        "Log in first".to_string(),
+      ),
+      AccountError::Store(error) => (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "STORAGE",
+        error.to_string(),
+      ),
+    };
+    ApiError {
+      status,
+      code,
+      message,
+    }
+  }
+}
+
+fn valid_name(name: &str) -> bool {
+  (3..=24).contains(&name.len())
+    && name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    && !name.to_ascii_lowercase().starts_with("guest")
+}
+
+// Sessions are stored under the hash of their token, so the store never
+// holds a token that could be used as is
+fn token_key(token: &str) -> String {
+  hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// The accounts and sessions of the server
+pub struct Accounts {
+  store: Arc<dyn Store>,
+  // registrations are made one at a time, so two can't take the same name
+  lock: Mutex<()>,
+}
+
+impl Accounts {
+  pub fn new(store: Arc<dyn Store>) -> Self {
+    Accounts {
+      store,
+      lock: Mutex::new(()),
+    }
+  }
+
+  fn create(&self, name: String, password: Option<String>) -> Result<Account, AccountError> {
+    let _guard = self.lock.lock().unwrap();
+    if self.store.account_by_name(&name)?.is_some() {
+      return Err(AccountError::NameTaken);
+    }
+    let account = Account {
+      user: User {
+        id: uuid::Uuid::new_v4().to_string(),
+        name,
+      },
+      password,
+    };
+    self.store.insert_account(&account)?;
+    Ok(account)
+  }
+
+  pub async fn register(&self, name: &str, password: &str) -> Result<User, AccountError> {
+    if !valid_name(name) {
+      return Err(AccountError::InvalidName);
+    }
+    if password.chars().count() < 8 {
+      return Err(AccountError::WeakPassword);
+    }
+    let password = password.to_string();
+    let hash = blocking(move || password::hash(&password)).await;
+    Ok(self.create(name.to_string(), Some(hash))?.user)
+  }
+
+  // A new account with a generated name and no password
+  pub fn guest(&self) -> Result<User, AccountError> {
+    loop {
+      let name = format!("Guest{:06}", rand::thread_rng().gen_range(0..1_000_000));
+      match self.create(name, None) {
+        Err(AccountError::NameTaken) => continue,
+        result => return result.map(|account| account.user),
+      }
+    }
+  }
+
+  pub async fn login(&self, name: &str, password: &str) -> Result<User, AccountError> {
+    let account = self.store.account_by_name(name)?;
+    let Some(Account {
+      user,
+      password: Some(hash),
+    }) = account
+    else {
+      return Err(AccountError::BadCredentials);
+    };
+    let password = password.to_string();
+    match blocking(move || password::verify(&password, &hash)).await {
+      true => Ok(user),
+      false => Err(AccountError::BadCredentials),
+    }
+  }
+
+  // A new session for the user, returning its token
+  pub fn start_session(&self, user: &User) -> Result<String, AccountError> {
+    let mut bytes = [0; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    self.store.insert_session(&token_key(&token), &user.id)?;
+    Ok(token)
+  }
+
+  pub fn end_session(&self, token: &str) -> Result<(), AccountError> {
+    Ok(self.store.delete_session(&token_key(token))?)
+  }
+
+  pub fn session_user(&self, token: &str) -> Result<User, AccountError> {
+    let account = match self.store.session(&token_key(token))? {
+      Some(user_id) => self.store.account(&user_id)?,
+      None => None,
+    };
+    account
+      .map(|account| account.user)
+      .ok_or(AccountError::Unauthenticated)
+  }
+}
+
+// Runs f on the blocking pool, for the hashing of passwords
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+  match tokio::task::spawn_blocking(f).await {
+    Ok(result) => result,
+    Err(error) => std::panic::resume_unwind(error.into_panic()),
+  }
+}
+
+// The session token of a request: a bearer token, or else the session
+// cookie
+fn session_token(headers: &HeaderMap) -> Option<&str> {
+  let bearer = headers
+    .get(AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "));
+  let cookie = || {
+    headers
+      .get_all(COOKIE)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(';'))
+      .filter_map(|cookie| cookie.trim().split_once('='))
+      .find(|(name, _)| *name == SESSION_COOKIE)
+      .map(|(_, token)| token)
+  };
+  bearer.or_else(cookie)
+}
+
+// The logged in user. Handlers taking a User refuse requests without a
+// valid session, and ones taking an Option<User> serve anyone
+#[async_trait]
+impl<S> FromRequestParts<S> for User
+where
+  Arc<Accounts>: FromRef<S>,
+  S: Send + Sync,
+{
+  type Rejection = ApiError;
+
+  async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    let token = session_token(&parts.headers).ok_or(AccountError::Unauthenticated)?;
+    Ok(Arc::<Accounts>::from_ref(state).session_user(token)?)
+  }
+}
+
+pub fn router<S>() -> Router<S>
+where
+  Arc<Accounts>: FromRef<S>,
+  S: Send + Sync + Clone + 'static,
+{
+  Router::new()
+    .route("/accounts/register", post(register))
+    .route("/accounts/login", post(login))
+    .route("/accounts/guest", post(guest))
+    .route("/accounts/logout", post(logout))
+    .route("/accounts/me", get(me))
+}
+
+#[derive(Deserialize)]
+pub struct Credentials {
+  pub name: String,
+  pub password: String,
+}
+
+fn user_json(user: &User) -> serde_json::Value {
+  json!({ "id": user.id, "name": user.name })
+}
+
+// Answers with the token both in the body, for bearer use, and as the
+// session cookie, for browsers
+fn session_response(
+  accounts: &Accounts,
+  status: StatusCode,
+  user: &User,
+) -> Result<Response, ApiError> {
+  let token = accounts.start_session(user)?;
+  let cookie = format!("{SESSION_COOKIE}={token}; Path=/; HttpOnly; SameSite=Lax");
+  let body = json!({ "token": token, "user": user_json(user) });
+  Ok((status, [(SET_COOKIE, cookie)], Json(body)).into_response())
+}
+
+async fn register(
+  State(accounts): State<Arc<Accounts>>,
+  credentials: Result<Json<Credentials>, JsonRejection>,
+) -> Result<Response, ApiError> {
+  let Json(credentials) = credentials?;
+  let user = accounts
+    .register(&credentials.name, &credentials.password)
+    .await?;
+  session_response(&accounts, StatusCode::CREATED, &user)
+}
+
+async fn login(
+  State(accounts): State<Arc<Accounts>>,
+  credentials: Result<Json<Credentials>, JsonRejection>,
+) -> Result<Response, ApiError> {
+  let Json(credentials) = credentials?;
+  let user = accounts
+    .login(&credentials.name, &credentials.password)
+    .await?;
+  session_response(&accounts, StatusCode::OK, &user)
+}
+
+async fn guest(State(accounts): State<Arc<Accounts>>) -> Result<Response, ApiError> {
+  let user = accounts.guest()?;
+  session_response(&accounts, StatusCode::CREATED, &user)
+}
+
+async fn logout(
+  State(accounts): State<Arc<Accounts>>,
+  headers: HeaderMap,
+) -> Result<Response, ApiError> {
+  let token = session_token(&headers).ok_or(AccountError::Unauthenticated)?;
+  accounts.end_session(token)?;
+  let cookie = format!("{SESSION_COOKIE}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0");
+  Ok((StatusCode::NO_CONTENT, [(SET_COOKIE, cookie)]).into_response())
+}
+
+async fn me(user: User) -> Json<serde_json::Value> {
+  Json(user_json(&user))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::store::MemoryStore;
+
+  fn accounts() -> Accounts {
+    Accounts::new(Arc::new(MemoryStore::new()))
+  }
+
+  #[tokio::test]
+  async fn test_register_and_login() {
+    let accounts = accounts();
+    let user = accounts.register("alice", "password1").await.unwrap();
+    assert!(matches!(
+      accounts.register("alice", "password2").await,
+      Err(AccountError::NameTaken)
+    ));
+    assert!(matches!(
+      accounts.register("bob", "short").await,
+      Err(AccountError::WeakPassword)
+    ));
+    for name in ["al", "a b c", "Guest123", &"x".repeat(25)] {
+      assert!(matches!(
+        accounts.register(name, "password1").await,
+        Err(AccountError::InvalidName)
+      ));
+    }
+    assert_eq!(accounts.login("alice", "password1").await.unwrap(), user);
+    assert!(matches!(
+      accounts.login("alice", "password2").await,
+      Err(AccountError::BadCredentials)
+    ));
+    assert!(matches!(
+      accounts.login("carol", "password1").await,
+      Err(AccountError::BadCredentials)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_sessions() {
+    let accounts = accounts();
+    let guest = accounts.guest().unwrap();
+    assert!(guest.name.starts_with("Guest"));
+    assert_ne!(accounts.guest().unwrap().id, guest.id);
+    // guests can't log in
+    assert!(accounts.login(&guest.name, "").await.is_err());
+
+    let token = accounts.start_session(&guest).unwrap();
+    assert_eq!(accounts.session_user(&token).unwrap(), guest);
+    assert!(accounts.session_user("nope").is_err());
+    accounts.end_session(&token).unwrap();
+    assert!(accounts.session_user(&token).is_err());
+  }
+
+  #[test]
+  fn test_session_token() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(session_token(&headers), None);
+    headers.insert(COOKIE, "theme=dark; session=abc".parse().unwrap());
+    assert_eq!(session_token(&headers), Some("abc"));
+    headers.insert(AUTHORIZATION, "Bearer xyz".parse().unwrap());
+    assert_eq!(session_token(&headers), Some("xyz"));
+  }
+}
@@ -0,0 +1,77 @@
+use rand::RngCore;
+use sha2::Sha256;
+
+// Passwords are stored as "pbkdf2-sha256$<iterations>$<salt>$<hash>", with
+// salt and hash in hex, so the iteration count can be raised later without
+// invalidating stored hashes. Hashing takes long on purpose, so it's best
+// kept off the async runtime
+const SCHEME: &str = "pbkdf2-sha256";
+// far fewer in tests, which hash many passwords without optimisation
+const ITERATIONS: u32 = if cfg!(test) { 1_000 } else { 100_000 };
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+pub fn hash(password: &str) -> String {
+  let mut salt = [0; SALT_LEN];
+  rand::thread_rng().fill_bytes(&mut salt);
+  let mut hash = [0; HASH_LEN];
+  pbkdf2(password.as_bytes(), &salt, ITERATIONS, &mut hash);
+  format!(
+    "{SCHEME}${ITERATIONS}${}${}",
+    hex::encode(salt),
+    hex::encode(hash)
+  )
+}
+
+pub fn verify(password: &str, stored: &str) -> bool {
+  let parts: Vec<_> = stored.split('$').collect();
+  let [SCHEME, iterations, salt, expected] = parts[..] else {
+    return false;
+  };
+  let (Ok(iterations), Ok(salt), Ok(expected)) =
+    (iterations.parse(), hex::decode(salt), hex::decode(expected))
+  else {
+    return false;
+  };
+  let mut hash = vec![0; expected.len()];
+  pbkdf2(password.as_bytes(), &salt, iterations, &mut hash);
+  // compares every byte, so the time taken doesn't tell how many matched
+  !expected.is_empty()
+    && hash
+      .iter()
+      .zip(&expected)
+      .fold(0, |acc, (a, b)| acc | (a ^ b))
+      == 0
+}
+
+fn pbkdf2(password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) {
+  pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, out);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_pbkdf2() {
+    // RFC 7914, section 11
+    let mut out = [0; 64];
+    pbkdf2(b"passwd", b"salt", 1, &mut out);
+    assert_eq!(
+      hex::encode(out),
+      "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+       49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+    );
+  }
+
+  #[test]
+  fn test_verify() {
+    let stored = hash("correct horse");
+    assert!(verify("correct horse", &stored));
+    assert!(!verify("correct horse ", &stored));
+    assert_ne!(hash("correct horse"), stored);
+    assert!(!verify("correct horse", "md5$1$00$00"));
+    assert!(!verify("correct horse", "garbage"));
+    assert!(!verify("correct horse", "pbkdf2-sha256$1$00$"));
+  }
+}
@@ -0,0 +1,350 @@
+use std::{
+  collections::{HashMap, VecDeque},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use axum::{
+  extract::{rejection::JsonRejection, FromRef, State},
+  http::StatusCode,
+  routing::post,
+  Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::Semaphore;
+
+use crate::{
+  account::{Accounts, User},
+  api::ApiError,
+  board,
+  bots::{self, Analysis, BotConfig, Budget, Eval},
+  game,
+  games::Games,
+  history,
+  position::Position,
+};
+
+pub struct AnalysisConfig {
+  // searches run at once. Each takes a blocking thread, which bots share,
+  // so this keeps analysis from crowding out the bots of live games
+  pub workers: usize,
+  // requests waiting for a worker, beyond which requests are refused
+  pub queue: usize,
+  // requests each user may make in a minute
+  pub per_minute: usize,
+  // the most a request may ask for
+  pub max_iterations: u32,
+  pub max_time: Duration,
+}
+
+impl Default for AnalysisConfig {
+  fn default() -> Self {
+    AnalysisConfig {
+      workers: 1,
+      queue: 8,
+      per_minute: 10,
+      max_iterations: 20_000,
+      max_time: Duration::from_secs(5),
+    }
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AnalysisError {
+  // the queue is full
+  Busy,
+  RateLimited,
+  // the search failed on its thread
+  Failed,
+}
+
+// Runs the analyses users ask for, a few at a time
+pub struct Analyst {
+  config: AnalysisConfig,
+  workers: Semaphore,
+  // requests queued or running
+  pending: AtomicUsize,
+  // when each user's recent requests were made, oldest first
+  recent: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+// Counts a request as pending for as long as it lives, so requests given up
+// on while queued leave the queue
+struct Pending<'a>(&'a AtomicUsize);
+
+impl Drop for Pending<'_> {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+impl Analyst {
+  pub fn new(config: AnalysisConfig) -> Self {
+    Analyst {
+      workers: Semaphore::new(config.workers),
+      config,
+      pending: AtomicUsize::new(0),
+      recent: Mutex::new(HashMap::new()),
+    }
+  }
+
+  // Counts the request against the user's rate
+  fn admit(&self, user_id: &str) -> Result<(), AnalysisError> {
+    let mut recent = self.recent.lock().unwrap();
+    let now = Instant::now();
+    let requests = recent.entry(user_id.to_string()).or_default();
+    while requests
+      .front()
+      .is_some_and(|made| now.duration_since(*made) >= RATE_WINDOW)
+    {
+      requests.pop_front();
+    }
+    if requests.len() >= self.config.per_minute {
+      return Err(AnalysisError::RateLimited);
+    }
+    requests.push_back(now);
+    Ok(())
+  }
+
+  // The budget asked for, within the limits
+  fn budget(&self, iterations: Option<u32>, time: Option<Duration>) -> Budget {
+    match (iterations, time) {
+      (_, Some(time)) => Budget::Time(time.min(self.config.max_time)),
+      (Some(iterations), None) => Budget::Iterations(iterations.min(self.config.max_iterations)),
+      (None, None) => Budget::Iterations(self.config.max_iterations / 10),
+    }
+  }
+
+  // Analyzes the position, which can't be finished, for the user, once a
+  // worker is free
+  pub async fn analyze(
+    &self,
+    user_id: &str,
+    position: Position,
+    budget: Budget,
+  ) -> Result<Analysis, AnalysisError> {
+    let limit = self.config.workers + self.config.queue;
+    self
+      .pending
+      .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+        (pending < limit).then_some(pending + 1)
+      })
+      .map_err(|_| AnalysisError::Busy)?;
+    let _pending = Pending(&self.pending);
+    self.admit(user_id)?;
+    let _worker = self.workers.acquire().await.unwrap();
+    let config = BotConfig {
+      exploration: 2.4,
+      eval: Eval::Heuristic,
+      budget,
+    };
+    let search = tokio::task::spawn_blocking(move || bots::analyze(&position, &config));
+    search.await.map_err(|_| AnalysisError::Failed)
+  }
+}
+
+pub fn router<S>() -> Router<S>
+where
+  Arc<Accounts>: FromRef<S>,
+  Arc<Games>: FromRef<S>,
+  Arc<Analyst>: FromRef<S>,
+  S: Send + Sync + Clone + 'static,
+{
+  Router::new().route("/analysis", post(analyze))
+}
+
+// The position is either a board of a variant, or that of a game after ply
+// moves, its last position without ply. The budget is in iterations or
+// time, time taking precedence
+#[derive(Deserialize)]
+pub struct AnalysisRequest {
+  pub variant: Option<game::GameVariant>,
+  pub board: Option<game::Board>,
+  pub game_id: Option<String>,
+  pub ply: Option<usize>,
+  pub iterations: Option<u32>,
+  pub time_ms: Option<u32>,
+  // how many of the moves to send, the most visited
+  pub top: Option<usize>,
+}
+
+const DEFAULT_TOP: usize = 10;
+
+fn bad_request(message: &str) -> ApiError {
+  ApiError {
+    status: StatusCode::BAD_REQUEST,
+    code: "BAD_REQUEST",
+    message: message.to_string(),
+  }
+}
+
+impl From<AnalysisError> for ApiError {
+  fn from(error: AnalysisError) -> Self {
+    let (status, code, message) = match error {
+      AnalysisError::Busy => (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "BUSY",
+        "Too many analyses are queued, try again later",
+      ),
+      AnalysisError::RateLimited => (
+        StatusCode::TOO_MANY_REQUESTS,
+        "RATE_LIMITED",
+        "Too many analyses asked for, try again in a minute",
+      ),
+      AnalysisError::Failed => (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "ANALYSIS_FAILED",
+        "The analysis failed",
+      ),
+    };
+    ApiError {
+      status,
+      code,
+      message: message.to_string(),
+    }
+  }
+}
+
+fn position(games: &Games, request: &AnalysisRequest) -> Result<Position, ApiError> {
+  match (&request.board, &request.game_id) {
+    (Some(board), None) => {
+      let variant = request
+        .variant
+        .as_ref()
+        .ok_or_else(|| bad_request("A board needs its variant"))?;
+      Position::from_board(variant.into(), board).map_err(|message| bad_request(&message))
+    }
+    (None, Some(id)) => {
+      let game = games.get(id)?;
+      let ply = request.ply.unwrap_or(game.moves.len());
+      game
+        .position_at(ply)
+        .ok_or_else(|| history::no_such_ply(&game))
+    }
+    _ => Err(bad_request("Give either a board or a game id")),
+  }
+}
+
+fn analysis_json(analysis: &Analysis, top: usize) -> Value {
+  let moves: Vec<Value> = analysis
+    .moves
+    .iter()
+    .take(top)
+    .map(|(m, share, value)| {
+      json!({
+        "move": board::to_move(analysis.color, m),
+        "visit_share": share,
+        "value": value,
+      })
+    })
+    .collect();
+  let line: Vec<game::Move> = analysis
+    .principal_variation
+    .iter()
+    .map(|(color, m)| board::to_move(*color, m))
+    .collect();
+  json!({
+    "color_to_move": game::Color::from(analysis.color),
+    "iterations": analysis.iterations,
+    "moves": moves,
+    "principal_variation": line,
+  })
+}
+
+async fn analyze(
+  State(games): State<Arc<Games>>,
+  State(analyst): State<Arc<Analyst>>,
+  user: User,
+  request: Result<Json<AnalysisRequest>, JsonRejection>,
+) -> Result<Json<Value>, ApiError> {
+  let Json(request) = request?;
+  let position = position(&games, &request)?;
+  if position.color_to_move().is_none() {
+    return Err(ApiError {
+      status: StatusCode::UNPROCESSABLE_ENTITY,
+      code: "GAME_OVER",
+      message: "The game is over".to_string(),
+    });
+  }
+  let time = request.time_ms.map(|ms| Duration::from_millis(ms as u64));
+  let budget = analyst.budget(request.iterations, time);
+  let analysis = analyst.analyze(&user.id, position, budget).await?;
+  Ok(Json(analysis_json(
+    &analysis,
+    request.top.unwrap_or(DEFAULT_TOP),
+  )))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::position::VariantKind;
+
+  #[test]
+  fn test_budget() {
+    let analyst = Analyst::new(AnalysisConfig::default());
+    let budget = analyst.budget(Some(1_000_000), None);
+    assert!(matches!(budget, Budget::Iterations(20_000)));
+    let budget = analyst.budget(Some(10), Some(Duration::from_secs(60)));
+    assert!(matches!(budget, Budget::Time(time) if time == Duration::from_secs(5)));
+    assert!(matches!(
+      analyst.budget(None, None),
+      Budget::Iterations(2_000)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_rate_limit() {
+    let analyst = Analyst::new(AnalysisConfig {
+      per_minute: 2,
+      ..AnalysisConfig::default()
+    });
+    let position = || Position::new(VariantKind::Duo);
+    for _ in 0..2 {
+      let analysis = analyst.analyze("a", position(), Budget::Iterations(5));
+      assert_eq!(analysis.await.unwrap().iterations, 5);
+    }
+    let refused = analyst
+      .analyze("a", position(), Budget::Iterations(5))
+      .await;
+    assert_eq!(refused.err(), Some(AnalysisError::RateLimited));
+    // the limit is per user
+    assert!(analyst
+      .analyze("b", position(), Budget::Iterations(5))
+      .await
+      .is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_queue() {
+    let analyst = Arc::new(Analyst::new(AnalysisConfig {
+      workers: 1,
+      queue: 1,
+      ..AnalysisConfig::default()
+    }));
+    let budget = Budget::Time(Duration::from_millis(300));
+    let running: Vec<_> = ["a", "b"]
+      .map(|user| {
+        let analyst = analyst.clone();
+        tokio::spawn(async move {
+          let position = Position::new(VariantKind::Duo);
+          analyst.analyze(user, position, budget).await
+        })
+      })
+      .into();
+    // one runs and one waits, which fills the queue
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let refused = analyst.analyze("c", Position::new(VariantKind::Duo), budget);
+    assert_eq!(refused.await.err(), Some(AnalysisError::Busy));
+    for analysis in running {
+      assert!(analysis.await.unwrap().is_ok());
+    }
+    assert_eq!(analyst.pending.load(Ordering::SeqCst), 0);
+  }
+}
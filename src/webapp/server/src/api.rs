@@ -0,0 +1,848 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{
+    rejection::{JsonRejection, QueryRejection},
+    FromRef, Path, State,
+  },
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  routing::{get, post},
+  Json, Router,
+};
+use blokus::IllegalMove;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+  account::{self, Accounts, User},
+  analysis::{self, AnalysisConfig, Analyst},
+  bots::Difficulty,
+  clock::Clock,
+  game,
+  games::{GameError, Games, Setup, Status},
+  history, live,
+  lobby::{self, LobbyConfig, Matchmaker},
+  position::VariantKind,
+  ratings,
+  store::Store,
+};
+
+// What the handlers share. Each handler takes the parts it needs
+#[derive(Clone)]
+pub struct AppState {
+  pub games: Arc<Games>,
+  pub accounts: Arc<Accounts>,
+  pub matchmaker: Arc<Matchmaker>,
+  pub analyst: Arc<Analyst>,
+}
+
+impl AppState {
+  pub fn new(store: Arc<dyn Store>) -> Self {
+    AppState::with_lobby(store, LobbyConfig::default())
+  }
+
+  pub fn with_lobby(store: Arc<dyn Store>, lobby: LobbyConfig) -> Self {
+    let games = Arc::new(Games::new(store.clone()));
+    AppState {
+      games: games.clone(),
+      accounts: Arc::new(Accounts::new(store)),
+      matchmaker: Arc::new(Matchmaker::new(games, lobby)),
+      analyst: Arc::new(Analyst::new(AnalysisConfig::default())),
+    }
+  }
+}
+
+impl FromRef<AppState> for Arc<Games> {
+  fn from_ref(state: &AppState) -> Self {
+    state.games.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<Accounts> {
+  fn from_ref(state: &AppState) -> Self {
+    state.accounts.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<Matchmaker> {
+  fn from_ref(state: &AppState) -> Self {
+    state.matchmaker.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<Analyst> {
+  fn from_ref(state: &AppState) -> Self {
+    state.analyst.clone()
+  }
+}
+
+// Reading games is open to anyone, changing them takes a session
+pub fn router(state: AppState) -> Router {
+  Router::new()
+    .route("/games", get(list_games).post(create_game))
+    .route("/games/:id", get(get_game))
+    .route("/games/:id/join", post(join_game))
+    .route("/games/:id/moves", post(play_move))
+    .route("/games/:id/ws", get(live::game_socket))
+    .merge(account::router())
+    .merge(lobby::router())
+    .merge(ratings::router())
+    .merge(analysis::router())
+    .merge(history::router())
+    .with_state(state)
+}
+
+// Errors are sent as {"error": CODE, "message": text}, with CODE meant for
+// programs and text for people
+#[derive(Debug)]
+pub struct ApiError {
+  pub status: StatusCode,
+  pub code: &'static str,
+  pub message: String,
+}
+
+impl IntoResponse for ApiError {
+  fn into_response(self) -> Response {
+    let body = json!({ "error": self.code, "message": self.message });
+    (self.status, Json(body)).into_response()
+  }
+}
+
+impl From<GameError> for ApiError {
+  fn from(error: GameError) -> Self {
+    let (status, code, message) = match error {
+      GameError::NotFound => (
+        StatusCode::NOT_FOUND,
+        "NOT_FOUND",
+        "No such game".to_string(),
+      ),
+      GameError::WrongStatus(status) => (
+        StatusCode::CONFLICT,
+        "WRONG_STATUS",
+        match status {
+          Status::WaitingForPlayers => "The game hasn't started",
+          Status::Ongoing => "The game has started",
+          Status::Ended => "The game has ended",
+        }
+        .to_string(),
+      ),
+      GameError::AlreadySeated => (
+        StatusCode::CONFLICT,
+        "ALREADY_SEATED",
+        "You already play in this game".to_string(),
+      ),
+      GameError::NotSeated => (
+        StatusCode::FORBIDDEN,
+        "NOT_SEATED",
+        "You don't play in this game".to_string(),
+      ),
+      GameError::NotYourColor => (
+        StatusCode::FORBIDDEN,
+        "NOT_YOUR_COLOR",
+        "You don't play this color".to_string(),
+      ),
+      GameError::Private => (
+        StatusCode::FORBIDDEN,
+        "PRIVATE",
+        "The game is private, join it with its invite code".to_string(),
+      ),
+      GameError::OutOfTime => (
+        StatusCode::CONFLICT,
+        "OUT_OF_TIME",
+        "Your time has run out".to_string(),
+      ),
+      GameError::Illegal(reason) => {
+        let (code, message) = illegal_move(reason);
+        (StatusCode::UNPROCESSABLE_ENTITY, code, message.to_string())
+      }
+      GameError::Store(error) => (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "STORAGE",
+        error.to_string(),
+      ),
+    };
+    ApiError {
+      status,
+      code,
+      message,
+    }
+  }
+}
+
+fn illegal_move(reason: IllegalMove) -> (&'static str, &'static str) {
+  match reason {
+    IllegalMove::GameOver => ("GAME_OVER", "The game is over"),
+    IllegalMove::WrongTurn => ("WRONG_TURN", "It's another color's turn"),
+    IllegalMove::PieceUsed => ("PIECE_USED", "The piece has been placed already"),
+    IllegalMove::InvalidOrientation => ("INVALID_ORIENTATION", "The piece has no such orientation"),
+    IllegalMove::OutOfBounds => ("OUT_OF_BOUNDS", "The piece doesn't fit on the board"),
+    IllegalMove::Overlap => ("OVERLAP", "The piece covers an occupied square"),
+    IllegalMove::EdgeContact => (
+      "EDGE_CONTACT",
+      "The piece shares an edge with a piece of its color",
+    ),
+    IllegalMove::NoCornerContact => (
+      "NO_CORNER_CONTACT",
+      "The piece doesn't touch a corner of a piece of its color",
+    ),
+    IllegalMove::NotOnStart => (
+      "NOT_ON_START",
+      "The first piece has to cover the starting square",
+    ),
+    IllegalMove::MustPlace => ("MUST_PLACE", "A piece can still be placed"),
+  }
+}
+
+impl From<QueryRejection> for ApiError {
+  fn from(rejection: QueryRejection) -> Self {
+    ApiError {
+      status: rejection.status(),
+      code: "BAD_REQUEST",
+      message: rejection.body_text(),
+    }
+  }
+}
+
+impl From<JsonRejection> for ApiError {
+  fn from(rejection: JsonRejection) -> Self {
+    ApiError {
+      status: rejection.status(),
+      code: "BAD_REQUEST",
+      message: rejection.body_text(),
+    }
+  }
+}
+
+#[derive(Deserialize)]
+pub struct CreateGame {
+  pub variant: game::GameVariant,
+  // the difficulty of the bot for each color, in turn order, or null for
+  // colors left to players
+  #[serde(default)]
+  pub bots: Vec<Option<game::Difficulty>>,
+  // private games are left out of the lobby, and joined with the invite
+  // code sent back
+  #[serde(default)]
+  pub private: bool,
+  // games without one are untimed
+  #[serde(default)]
+  pub clock: Option<game::GameClock>,
+}
+
+async fn create_game(
+  State(games): State<Arc<Games>>,
+  _user: User,
+  request: Result<Json<CreateGame>, JsonRejection>,
+) -> Result<(StatusCode, Json<game::Game>), ApiError> {
+  let Json(request) = request?;
+  let variant = VariantKind::from(&request.variant);
+  if request.bots.len() > variant.colors() {
+    return Err(ApiError {
+      status: StatusCode::BAD_REQUEST,
+      code: "BAD_REQUEST",
+      message: format!("A {:?} game has {} colors", variant, variant.colors()),
+    });
+  }
+  let setup = Setup {
+    bots: request
+      .bots
+      .iter()
+      .map(|d| d.as_ref().map(Difficulty::from))
+      .collect(),
+    players: vec![],
+    private: request.private,
+    clock: request.clock.as_ref().map(Clock::from),
+  };
+  let game = games.create(variant, setup)?;
+  let mut json = game.to_json();
+  json.inviteCode = game.invite.map(Box::new);
+  Ok((StatusCode::CREATED, Json(json)))
+}
+
+async fn list_games(State(games): State<Arc<Games>>) -> Result<Json<Vec<game::Game>>, ApiError> {
+  let list = games.list()?.iter().map(|game| game.to_json()).collect();
+  Ok(Json(list))
+}
+
+async fn get_game(
+  State(games): State<Arc<Games>>,
+  Path(id): Path<String>,
+) -> Result<Json<game::Game>, ApiError> {
+  Ok(Json(games.get(&id)?.to_json()))
+}
+
+async fn join_game(
+  State(games): State<Arc<Games>>,
+  Path(id): Path<String>,
+  user: User,
+) -> Result<Json<game::Game>, ApiError> {
+  Ok(Json(games.join(&id, user)?.to_json()))
+}
+
+async fn play_move(
+  State(games): State<Arc<Games>>,
+  Path(id): Path<String>,
+  user: User,
+  m: Result<Json<game::Move>, JsonRejection>,
+) -> Result<Json<game::Game>, ApiError> {
+  let Json(m) = m?;
+  Ok(Json(games.play(&id, &user.id, &m)?.to_json()))
+}
+
+#[cfg(test)]
+mod tests {
+  use axum::{
+    body::Body,
+    http::{header::AUTHORIZATION, Method, Request},
+  };
+  use serde_json::Value;
+  use tower::ServiceExt;
+
+  use super::*;
+  use crate::store::MemoryStore;
+
+  fn app() -> Router {
+    router(AppState::new(Arc::new(MemoryStore::new())))
+  }
+
+  async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+  ) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+      .method(method)
+      .uri(uri)
+      .header("content-type", "application/json");
+    if let Some(token) = token {
+      request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+    }
+    let request = request
+      .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+      .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json = if bytes.is_empty() {
+      Value::Null
+    } else {
+      serde_json::from_slice(&bytes).unwrap()
+    };
+    (status, json)
+  }
+
+  // Registers the user, returning a session token
+  async fn register(app: &Router, name: &str) -> String {
+    let body = json!({ "name": name, "password": "password" });
+    let (status, session) = send(app, Method::POST, "/accounts/register", None, Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    session["token"].as_str().unwrap().to_string()
+  }
+
+  async fn create(app: &Router, token: &str, variant: &str) -> (StatusCode, Value) {
+    let body = json!({ "variant": variant });
+    send(app, Method::POST, "/games", Some(token), Some(body)).await
+  }
+
+  async fn join(app: &Router, game: &str, token: &str) -> (StatusCode, Value) {
+    let uri = format!("/games/{game}/join");
+    send(app, Method::POST, &uri, Some(token), None).await
+  }
+
+  #[tokio::test]
+  async fn test_game_lifecycle() {
+    let app = app();
+    let alice = register(&app, "alice").await;
+    let bob = register(&app, "bob").await;
+    let carol = register(&app, "carol").await;
+    let (status, list) = send(&app, Method::GET, "/games", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(list, json!([]));
+
+    let (status, game) = create(&app, &alice, "DUO").await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(game["status"], "WAITING_FOR_PLAYERS");
+    assert_eq!(game["variant"], "DUO");
+    assert_eq!(game["schema_version"], 1);
+    assert_eq!(game["players"], json!([]));
+    assert_eq!(game["moves"], json!([]));
+    let id = game["id"].as_str().unwrap().to_string();
+
+    let (status, game) = join(&app, &id, &alice).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(game["players"][0]["color"], "C1");
+    assert_eq!(game["players"][0]["user"]["name"], "alice");
+    assert_eq!(game["status"], "WAITING_FOR_PLAYERS");
+    let (status, error) = join(&app, &id, &alice).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error["error"], "ALREADY_SEATED");
+
+    let (status, game) = join(&app, &id, &bob).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(game["players"][1]["user"]["name"], "bob");
+    assert_eq!(game["status"], "ONGOING");
+    assert_eq!(game["board"]["table"].as_array().unwrap().len(), 14);
+    let (status, error) = join(&app, &id, &carol).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error["error"], "WRONG_STATUS");
+
+    let uri = format!("/games/{id}");
+    let (status, fetched) = send(&app, Method::GET, &uri, None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetched, game);
+    let (_, list) = send(&app, Method::GET, "/games", None, None).await;
+    assert_eq!(list, json!([game]));
+  }
+
+  #[tokio::test]
+  async fn test_errors() {
+    let app = app();
+    let token = register(&app, "alice").await;
+    let (status, error) = send(&app, Method::GET, "/games/nope", None, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error["error"], "NOT_FOUND");
+    let (status, _) = join(&app, "nope", &token).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, error) = create(&app, &token, "HEX").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(error["error"], "BAD_REQUEST");
+    let (_, game) = create(&app, &token, "CLASSIC").await;
+    let id = game["id"].as_str().unwrap();
+    for name in ["alice2", "bob", "carol"] {
+      let token = register(&app, name).await;
+      let (_, game) = join(&app, id, &token).await;
+      assert_eq!(game["status"], "WAITING_FOR_PLAYERS");
+    }
+    let (_, game) = join(&app, id, &token).await;
+    assert_eq!(game["status"], "ONGOING");
+    assert_eq!(game["board"]["colors"].as_array().unwrap().len(), 4);
+  }
+
+  #[tokio::test]
+  async fn test_create_with_bots() {
+    let app = app();
+    let token = register(&app, "alice").await;
+    let body = json!({ "variant": "CLASSIC", "bots": [null, "EASY", null, "HARD"] });
+    let (status, game) = send(&app, Method::POST, "/games", Some(&token), Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(game["status"], "WAITING_FOR_PLAYERS");
+    assert_eq!(game["players"][0]["color"], "C2");
+    assert_eq!(game["players"][0]["bot"], "EASY");
+    assert_eq!(game["players"][1]["color"], "C4");
+    assert_eq!(game["players"][1]["bot"], "HARD");
+
+    let id = game["id"].as_str().unwrap();
+    let (_, game) = join(&app, id, &token).await;
+    assert_eq!(game["players"][0]["color"], "C1");
+    assert_eq!(game["players"][0]["user"]["name"], "alice");
+
+    let body = json!({ "variant": "DUO", "bots": ["EASY", "EASY", "EASY"] });
+    let (status, error) = send(&app, Method::POST, "/games", Some(&token), Some(body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"], "BAD_REQUEST");
+  }
+
+  #[tokio::test]
+  async fn test_create_with_clock() {
+    let app = app();
+    let alice = register(&app, "alice").await;
+    let bob = register(&app, "bob").await;
+    let clock = json!({
+      "time_control": { "kind": "FISCHER", "initial_ms": 60_000, "increment_ms": 1_000 },
+      "on_timeout": "FORFEIT",
+    });
+    let body = json!({ "variant": "DUO", "clock": clock });
+    let (status, game) = send(&app, Method::POST, "/games", Some(&alice), Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(game["clock"], clock);
+    let id = game["id"].as_str().unwrap();
+    join(&app, id, &alice).await;
+    let (_, game) = join(&app, id, &bob).await;
+    // the clock starts with the game
+    for player in game["players"].as_array().unwrap() {
+      let remaining = player["remaining_ms"].as_u64().unwrap();
+      assert!(remaining > 50_000 && remaining <= 60_000, "{remaining}");
+      assert!(player.get("forfeited").is_none());
+    }
+
+    let (_, untimed) = create(&app, &alice, "DUO").await;
+    assert!(untimed.get("clock").is_none());
+  }
+
+  #[tokio::test]
+  async fn test_analysis() {
+    let app = app();
+    let a = register(&app, "alice").await;
+    let b = register(&app, "bob").await;
+    let (_, game) = create(&app, &a, "DUO").await;
+    let id = game["id"].as_str().unwrap().to_string();
+    join(&app, &id, &a).await;
+    join(&app, &id, &b).await;
+    let (_, game) = play(&app, &id, &a, place("C1", "I2", "R0", 4, 4)).await;
+
+    let body = json!({ "game_id": id, "ply": 0, "iterations": 50, "top": 3 });
+    let (status, analysis) = send(&app, Method::POST, "/analysis", Some(&a), Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(analysis["color_to_move"], "C1");
+    assert_eq!(analysis["iterations"], 50);
+    assert_eq!(analysis["moves"].as_array().unwrap().len(), 3);
+    let best = &analysis["moves"][0];
+    assert!(best["visit_share"].as_f64().unwrap() > 0.0);
+    assert!(best["value"].is_number());
+    assert_eq!(analysis["principal_variation"][0], best["move"]);
+
+    // the game as it stands, from its id or its board
+    let body = json!({ "game_id": id, "iterations": 20 });
+    let (_, analysis) = send(&app, Method::POST, "/analysis", Some(&a), Some(body)).await;
+    assert_eq!(analysis["color_to_move"], "C2");
+    let body = json!({ "variant": "DUO", "board": game["board"], "iterations": 20 });
+    let (_, analysis) = send(&app, Method::POST, "/analysis", Some(&b), Some(body)).await;
+    assert_eq!(analysis["color_to_move"], "C2");
+
+    let body = json!({ "game_id": id, "ply": 2 });
+    let (status, error) = send(&app, Method::POST, "/analysis", Some(&a), Some(body)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error["error"], "NO_SUCH_PLY");
+    let body = json!({ "board": game["board"] });
+    let (status, _) = send(&app, Method::POST, "/analysis", Some(&a), Some(body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body = json!({ "game_id": id });
+    let (status, _) = send(&app, Method::POST, "/analysis", None, Some(body)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+  }
+
+  #[tokio::test]
+  async fn test_history() {
+    let app = app();
+    let a = register(&app, "alice").await;
+    let b = register(&app, "bob").await;
+    let (_, game) = create(&app, &a, "DUO").await;
+    let id = game["id"].as_str().unwrap().to_string();
+    join(&app, &id, &a).await;
+    join(&app, &id, &b).await;
+    play(&app, &id, &a, place("C1", "I2", "R0", 4, 4)).await;
+
+    let uri = format!("/games/{id}/history");
+    let (status, history) = send(&app, Method::GET, &uri, None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(history["variant"], "DUO");
+    assert!(history["started_at"].is_u64());
+    let moves = history["moves"].as_array().unwrap();
+    assert_eq!(moves.len(), 1);
+    assert_eq!(moves[0]["ply"], 1);
+    assert_eq!(moves[0]["move"], place("C1", "I2", "R0", 4, 4));
+    assert!(moves[0]["played_at"].is_u64());
+    assert_eq!(moves[0]["timeout"], false);
+    assert_eq!(
+      moves[0]["scores"][0],
+      json!({ "color": "C1", "score": -87 })
+    );
+
+    let (status, board) = send(&app, Method::GET, &format!("/games/{id}/at/0"), None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(board["table"][4][5], Value::Null);
+    assert_eq!(board["color_to_move"], "C1");
+    let (_, board) = send(&app, Method::GET, &format!("/games/{id}/at/1"), None, None).await;
+    assert_eq!(board["table"][4][5], "C1");
+    assert_eq!(board["color_to_move"], "C2");
+    let (status, error) = send(&app, Method::GET, &format!("/games/{id}/at/2"), None, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error["error"], "NO_SUCH_PLY");
+
+    let request = Request::builder()
+      .uri(format!("/games/{id}/record"))
+      .body(Body::empty())
+      .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let record: blokus::Record = std::str::from_utf8(&bytes).unwrap().parse().unwrap();
+    assert_eq!(record.variant, "Duo");
+    assert_eq!(record.players[1].1, "bob");
+    assert_eq!(record.moves.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_leaderboard() {
+    let app = app();
+    let (status, board) = send(&app, Method::GET, "/leaderboard", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    // bots are listed before anyone has played
+    let bots: Vec<_> = board
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|e| &e["bot"])
+      .collect();
+    assert_eq!(bots, ["HARD", "MEDIUM", "EASY"]);
+    assert_eq!(board[0]["rank"], 1);
+    assert_eq!(board[0]["rating"], 1900);
+
+    let uri = "/leaderboard?variant=DUO&limit=2";
+    let (_, board) = send(&app, Method::GET, uri, None, None).await;
+    assert_eq!(board.as_array().unwrap().len(), 2);
+    let uri = "/leaderboard?variant=TRIO";
+    let (status, error) = send(&app, Method::GET, uri, None, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"], "BAD_REQUEST");
+  }
+
+  #[tokio::test]
+  async fn test_lobby() {
+    let app = app();
+    let alice = register(&app, "alice").await;
+    let bob = register(&app, "bob").await;
+    let (_, open) = create(&app, &alice, "CLASSIC").await;
+    let body = json!({ "variant": "DUO", "private": true });
+    let (_, private) = send(&app, Method::POST, "/games", Some(&alice), Some(body)).await;
+    let code = private["invite_code"].as_str().unwrap();
+    assert_eq!(code.len(), 6);
+    let id = open["id"].as_str().unwrap();
+    join(&app, id, &bob).await;
+
+    let (status, lobby) = send(&app, Method::GET, "/lobby", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(lobby.as_array().unwrap().len(), 1);
+    assert_eq!(lobby[0]["game"]["id"], id);
+    assert_eq!(lobby[0]["open_seats"], json!(["C2", "C3", "C4"]));
+
+    // the invite code is only for the creator to share
+    let uri = format!("/games/{}", private["id"].as_str().unwrap());
+    let (_, fetched) = send(&app, Method::GET, &uri, None, None).await;
+    assert!(fetched.get("invite_code").is_none());
+    let (status, error) = join(&app, private["id"].as_str().unwrap(), &bob).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error["error"], "PRIVATE");
+    let uri = format!("/invites/{}/join", code.to_lowercase());
+    let (status, game) = send(&app, Method::POST, &uri, Some(&bob), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(game["players"][0]["user"]["name"], "bob");
+    let (status, _) = send(&app, Method::POST, "/invites/NOPE/join", Some(&bob), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+  }
+
+  #[tokio::test]
+  async fn test_matchmaking() {
+    let app = app();
+    let alice = register(&app, "alice").await;
+    let bob = register(&app, "bob").await;
+    let (_, ticket) = send(&app, Method::GET, "/matchmaking", Some(&alice), None).await;
+    assert_eq!(ticket["status"], "NOT_QUEUED");
+    let body = Some(json!({ "variant": "DUO" }));
+    let (status, ticket) = send(
+      &app,
+      Method::POST,
+      "/matchmaking",
+      Some(&alice),
+      body.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ticket["status"], "QUEUED");
+    assert_eq!(ticket["variant"], "DUO");
+    assert_eq!(ticket["waiting"], 1);
+
+    let (_, ticket) = send(&app, Method::POST, "/matchmaking", Some(&bob), body).await;
+    assert_eq!(ticket["status"], "MATCHED");
+    let (_, other) = send(&app, Method::GET, "/matchmaking", Some(&alice), None).await;
+    assert_eq!(other, ticket);
+    let uri = format!("/games/{}", ticket["game_id"].as_str().unwrap());
+    let (_, game) = send(&app, Method::GET, &uri, None, None).await;
+    assert_eq!(game["status"], "ONGOING");
+
+    let (status, _) = send(&app, Method::DELETE, "/matchmaking", Some(&alice), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, ticket) = send(&app, Method::GET, "/matchmaking", Some(&alice), None).await;
+    assert_eq!(ticket["status"], "NOT_QUEUED");
+    let (status, _) = send(&app, Method::GET, "/matchmaking", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+  }
+
+  #[tokio::test]
+  async fn test_sessions_required() {
+    let app = app();
+    let body = Some(json!({ "variant": "DUO" }));
+    let (status, error) = send(&app, Method::POST, "/games", None, body.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error["error"], "UNAUTHENTICATED");
+    let (status, _) = send(&app, Method::POST, "/games", Some("forged"), body).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let token = register(&app, "alice").await;
+    let (_, game) = create(&app, &token, "DUO").await;
+    let id = game["id"].as_str().unwrap();
+    let (status, _) = send(&app, Method::POST, &format!("/games/{id}/join"), None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let uri = format!("/games/{id}/moves");
+    let m = json!({ "kind": "PASS", "color": "C1" });
+    let (status, _) = send(&app, Method::POST, &uri, None, Some(m)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+  }
+
+  #[tokio::test]
+  async fn test_accounts() {
+    let app = app();
+    let token = register(&app, "alice").await;
+    let (status, me) = send(&app, Method::GET, "/accounts/me", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["name"], "alice");
+
+    let body = json!({ "name": "alice", "password": "password" });
+    let (status, error) = send(
+      &app,
+      Method::POST,
+      "/accounts/register",
+      None,
+      Some(body.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error["error"], "NAME_TAKEN");
+    let (status, session) = send(&app, Method::POST, "/accounts/login", None, Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(session["user"], me);
+    let other = session["token"].as_str().unwrap();
+    assert_ne!(other, token);
+
+    let wrong = json!({ "name": "alice", "password": "wrong password" });
+    let (status, error) = send(&app, Method::POST, "/accounts/login", None, Some(wrong)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error["error"], "BAD_CREDENTIALS");
+
+    // logging out ends only that session
+    let (status, _) = send(&app, Method::POST, "/accounts/logout", Some(&token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, Method::GET, "/accounts/me", Some(&token), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, Method::GET, "/accounts/me", Some(other), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, guest) = send(&app, Method::POST, "/accounts/guest", None, None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert!(guest["user"]["name"].as_str().unwrap().starts_with("Guest"));
+  }
+
+  #[tokio::test]
+  async fn test_session_cookie() {
+    let app = app();
+    let request = Request::builder()
+      .method(Method::POST)
+      .uri("/accounts/guest")
+      .body(Body::empty())
+      .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let cookie = response.headers()["set-cookie"].to_str().unwrap();
+    assert!(cookie.contains("HttpOnly"));
+    let session = cookie.split(';').next().unwrap().to_string();
+
+    let request = Request::builder()
+      .uri("/accounts/me")
+      .header("cookie", session)
+      .body(Body::empty())
+      .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+  }
+
+  async fn play(app: &Router, game: &str, token: &str, m: Value) -> (StatusCode, Value) {
+    let uri = format!("/games/{game}/moves");
+    send(app, Method::POST, &uri, Some(token), Some(m)).await
+  }
+
+  fn place(color: &str, piece: &str, orientation: &str, row: u8, col: u8) -> Value {
+    json!({
+      "kind": "PLACE",
+      "color": color,
+      "piece": piece,
+      "orientation": orientation,
+      "anchor": { "row": row, "col": col },
+    })
+  }
+
+  #[tokio::test]
+  async fn test_moves() {
+    let app = app();
+    let a = register(&app, "alice").await;
+    let b = register(&app, "bob").await;
+    let c = register(&app, "carol").await;
+    let (_, game) = create(&app, &a, "DUO").await;
+    let id = game["id"].as_str().unwrap().to_string();
+    join(&app, &id, &a).await;
+    let (status, error) = play(&app, &id, &a, place("C1", "I2", "R0", 4, 4)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error["error"], "WRONG_STATUS");
+    join(&app, &id, &b).await;
+
+    let (status, error) = play(&app, &id, &b, place("C1", "I2", "R0", 4, 4)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error["error"], "NOT_YOUR_COLOR");
+    let (status, error) = play(&app, &id, &c, place("C1", "I2", "R0", 4, 4)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error["error"], "NOT_SEATED");
+    let (status, error) = play(&app, &id, &b, place("C2", "I2", "R0", 9, 9)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(error["error"], "WRONG_TURN");
+    let (_, error) = play(&app, &id, &a, place("C1", "I2", "R0", 0, 0)).await;
+    assert_eq!(error["error"], "NOT_ON_START");
+    let (_, error) = play(&app, &id, &a, place("C1", "I5", "R0", 4, 10)).await;
+    assert_eq!(error["error"], "OUT_OF_BOUNDS");
+    let (_, error) = play(&app, &id, &a, json!({ "kind": "PASS", "color": "C1" })).await;
+    assert_eq!(error["error"], "MUST_PLACE");
+    let (status, error) = play(&app, &id, &a, json!({ "kind": "JUMP" })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(error["error"], "BAD_REQUEST");
+
+    // I2 across (4,4) and (4,5)
+    let (status, game) = play(&app, &id, &a, place("C1", "I2", "R0", 4, 4)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(game["moves"], json!([place("C1", "I2", "R0", 4, 4)]));
+    assert_eq!(game["board"]["table"][4][5], "C1");
+    assert_eq!(game["board"]["colors"][0]["score"], -87);
+    assert_eq!(game["players"][0]["score"], -87);
+    assert_eq!(game["board"]["color_to_move"], "C2");
+
+    let (_, game) = play(&app, &id, &b, place("C2", "I2", "R1", 8, 9)).await;
+    assert_eq!(game["board"]["table"][9][9], "C2");
+    let (_, error) = play(&app, &id, &a, place("C1", "I1", "R0", 4, 5)).await;
+    assert_eq!(error["error"], "OVERLAP");
+    let (_, error) = play(&app, &id, &a, place("C1", "I1", "R0", 4, 6)).await;
+    assert_eq!(error["error"], "EDGE_CONTACT");
+    let (_, error) = play(&app, &id, &a, place("C1", "I1", "R0", 7, 7)).await;
+    assert_eq!(error["error"], "NO_CORNER_CONTACT");
+    let (_, error) = play(&app, &id, &a, place("C1", "I2", "R0", 5, 6)).await;
+    assert_eq!(error["error"], "PIECE_USED");
+    let (status, game) = play(&app, &id, &a, place("C1", "I1", "R0", 5, 6)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(game["moves"].as_array().unwrap().len(), 3);
+    let uri = format!("/games/{id}");
+    let (_, fetched) = send(&app, Method::GET, &uri, None, None).await;
+    assert_eq!(fetched, game);
+  }
+
+  #[tokio::test]
+  async fn test_color_outside_variant() {
+    let app = app();
+    let a = register(&app, "alice").await;
+    let b = register(&app, "bob").await;
+    let (_, game) = create(&app, &a, "DUO").await;
+    let id = game["id"].as_str().unwrap().to_string();
+    join(&app, &id, &a).await;
+    join(&app, &id, &b).await;
+    // Duo has no third color
+    for m in [
+      json!({ "kind": "PASS", "color": "C3" }),
+      place("C4", "I1", "R0", 4, 4),
+    ] {
+      let (status, error) = play(&app, &id, &a, m).await;
+      assert_eq!(status, StatusCode::FORBIDDEN);
+      assert_eq!(error["error"], "NOT_YOUR_COLOR");
+    }
+    // and the game plays on
+    let (status, _) = play(&app, &id, &a, place("C1", "I2", "R0", 4, 4)).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+}
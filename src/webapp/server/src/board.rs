@@ -0,0 +1,287 @@
+use blokus::{State, Variant};
+
+use crate::game::{
+  Board, Color, ColorState, Move, MovePass, MovePlace, MovePlaceAnchor, Orientation, Piece,
+};
+
+// Conversions between the positions and moves of the blokus engine and the
+// ones sent to clients
+
+// Both enums list the same names, so each converts to the other variant by
+// variant
+macro_rules! convert_enum {
+  ($engine:ty, $schema:ty, $($name:ident),*) => {
+    impl From<$engine> for $schema {
+      fn from(value: $engine) -> Self {
+        match value {
+          $(<$engine>::$name => <$schema>::$name,)*
+        }
+      }
+    }
+
+    impl From<&$schema> for $engine {
+      fn from(value: &$schema) -> Self {
+        match value {
+          $(<$schema>::$name => <$engine>::$name,)*
+        }
+      }
+    }
+  };
+}
+
+convert_enum!(
+  blokus::Piece,
+  Piece,
+  I1,
+  I2,
+  I3,
+  V3,
+  I4,
+  L4,
+  T4,
+  O4,
+  Z4,
+  F5,
+  I5,
+  L5,
+  N5,
+  P5,
+  T5,
+  U5,
+  V5,
+  W5,
+  X5,
+  Y5,
+  Z5
+);
+
+impl From<blokus::Color> for Color {
+  fn from(color: blokus::Color) -> Self {
+    match color {
+      blokus::Color::Blue => Color::C1,
+      blokus::Color::Yellow => Color::C2,
+      blokus::Color::Red => Color::C3,
+      blokus::Color::Green => Color::C4,
+    }
+  }
+}
+
+impl From<&Color> for blokus::Color {
+  fn from(color: &Color) -> Self {
+    match color {
+      Color::C1 => blokus::Color::Blue,
+      Color::C2 => blokus::Color::Yellow,
+      Color::C3 => blokus::Color::Red,
+      Color::C4 => blokus::Color::Green,
+    }
+  }
+}
+
+// The orientations are named after the transforms producing them
+fn orientation_name(transform: u8) -> Orientation {
+  match transform {
+    0 => Orientation::R0,
+    1 => Orientation::R1,
+    2 => Orientation::R2,
+    3 => Orientation::R3,
+    4 => Orientation::F0,
+    5 => Orientation::F1,
+    6 => Orientation::F2,
+    _ => Orientation::F3,
+  }
+}
+
+fn transform(orientation: &Orientation) -> u8 {
+  match orientation {
+    Orientation::R0 => 0,
+    Orientation::R1 => 1,
+    Orientation::R2 => 2,
+    Orientation::R3 => 3,
+    Orientation::F0 => 4,
+    Orientation::F1 => 5,
+    Orientation::F2 => 6,
+    Orientation::F3 => 7,
+  }
+}
+
+// color is the color that moved, as engine passes don't carry one
+pub fn to_move(color: blokus::Color, m: &blokus::Move) -> Move {
+  match *m {
+    blokus::Move::Pass => Move::Pass(MovePass {
+      color: color.into(),
+    }),
+    blokus::Move::Place {
+      color,
+      piece,
+      orientation,
+      row,
+      col,
+    } => {
+      let transform = piece.orientations()[orientation as usize].transform;
+      Move::Place(MovePlace {
+        anchor: MovePlaceAnchor { col, row },
+        color: color.into(),
+        orientation: orientation_name(transform),
+        piece: piece.into(),
+      })
+    }
+  }
+}
+
+// The engine move, and the color that made it
+pub fn from_move(m: &Move) -> (blokus::Color, blokus::Move) {
+  match m {
+    Move::Pass(pass) => ((&pass.color).into(), blokus::Move::Pass),
+    Move::Place(place) => {
+      let piece = blokus::Piece::from(&place.piece);
+      let color = (&place.color).into();
+      let m = blokus::Move::Place {
+        color,
+        piece,
+        // every transform gives one of the piece's orientations
+        orientation: piece
+          .orientation_index(transform(&place.orientation))
+          .unwrap(),
+        row: place.anchor.row,
+        col: place.anchor.col,
+      };
+      (color, m)
+    }
+  }
+}
+
+pub fn to_board<const N: usize>(state: &State<N>) -> Board {
+  let table = (0..state.size())
+    .map(|row| {
+      (0..state.size())
+        .map(|col| state.get(row, col).map(|color| Box::new(color.into())))
+        .collect()
+    })
+    .collect();
+  Board {
+    colors: state
+      .colors()
+      .map(|color| ColorState {
+        color: color.into(),
+        remainingPieces: state
+          .remaining_pieces(color)
+          .into_iter()
+          .map(Piece::from)
+          .collect(),
+        score: state.advanced_score(color) as i8,
+      })
+      .collect(),
+    over: state.is_over(),
+    table,
+    colorToMove: state.color_to_move().map(|color| Box::new(color.into())),
+  }
+}
+
+// Sets up the position shown on the board, see State::from_cells for what
+// can't be recovered from it
+pub fn from_board<V: Variant<N>, const N: usize>(board: &Board) -> Result<State<N>, String> {
+  let cells: Vec<Vec<_>> = board
+    .table
+    .iter()
+    .map(|row| {
+      row
+        .iter()
+        .map(|cell| cell.as_deref().map(blokus::Color::from))
+        .collect()
+    })
+    .collect();
+  let color_to_move = match (&board.colorToMove, board.over) {
+    (None, true) => None,
+    (Some(color), false) => Some(blokus::Color::from(color.as_ref())),
+    (Some(_), true) => return Err("A finished game has no color to move".to_string()),
+    (None, false) => return Err("An ongoing game needs a color to move".to_string()),
+  };
+  State::from_cells::<V>(&cells, color_to_move)
+}
+
+#[cfg(test)]
+mod tests {
+  use blokus::{Blokus, Duo, Move};
+  use rustyai::MaMdp;
+
+  use super::*;
+  use crate::position::{play_out, Position, VariantKind};
+
+  // the board read back shows the same position, with the same moves
+  fn check_board(variant: VariantKind, position: &Position) {
+    let json = serde_json::to_value(position.board()).unwrap();
+    let parsed: Board = serde_json::from_value(json.clone()).unwrap();
+    let copy = Position::from_board(variant, &parsed).unwrap();
+    assert_eq!(serde_json::to_value(copy.board()).unwrap(), json);
+    let mut moves = position.legal_moves();
+    moves.sort();
+    let mut copy_moves = copy.legal_moves();
+    copy_moves.sort();
+    assert_eq!(copy_moves, moves);
+  }
+
+  fn check_round_trip(variant: VariantKind) {
+    let mut position = Position::new(variant);
+    play_out(&mut position, |position, color, m| {
+      check_board(variant, position);
+      let json = serde_json::to_value(to_move(color, &m)).unwrap();
+      let parsed = serde_json::from_value(json).unwrap();
+      assert_eq!(from_move(&parsed), (color, m));
+    });
+    check_board(variant, &position);
+    assert!(position.board().over);
+  }
+
+  #[test]
+  fn test_round_trip() {
+    check_round_trip(VariantKind::Classic);
+    check_round_trip(VariantKind::Duo);
+  }
+
+  #[test]
+  fn test_json() {
+    let mut state = State::new::<Duo>();
+    let game = Blokus::<Duo>::default();
+    let m = Move::Place {
+      color: blokus::Color::Blue,
+      piece: blokus::Piece::I2,
+      orientation: 0,
+      row: 4,
+      col: 4,
+    };
+    game.transition(&mut state, &[m, Move::Pass]);
+    let json = serde_json::to_value(to_board(&state)).unwrap();
+    assert_eq!(json["over"], false);
+    assert_eq!(json["color_to_move"], "C2");
+    assert_eq!(json["colors"][0]["score"], -87);
+    assert_eq!(json["colors"][0]["remaining_pieces"][0], "I1");
+    assert_eq!(json["colors"][0]["remaining_pieces"][1], "I3");
+    assert_eq!(json["colors"][1]["score"], -89);
+    assert_eq!(json["table"].as_array().unwrap().len(), 14);
+    assert_eq!(json["table"][4][4], "C1");
+    assert_eq!(json["table"][4][5], "C1");
+    assert!(json["table"][5][5].is_null());
+
+    let json = serde_json::to_value(to_move(blokus::Color::Blue, &m)).unwrap();
+    assert_eq!(
+      json,
+      serde_json::json!({
+        "kind": "PLACE",
+        "anchor": {"row": 4, "col": 4},
+        "color": "C1",
+        "orientation": "R0",
+        "piece": "I2",
+      })
+    );
+    let json = serde_json::to_value(to_move(blokus::Color::Yellow, &Move::Pass)).unwrap();
+    assert_eq!(json, serde_json::json!({"kind": "PASS", "color": "C2"}));
+
+    let json = serde_json::to_value(to_board(&state)).unwrap();
+    let mut board: Board = serde_json::from_value(json).unwrap();
+    board.over = true;
+    assert!(from_board::<Duo, 2>(&board).is_err());
+    board.table.pop();
+    board.over = false;
+    assert!(from_board::<Duo, 2>(&board).is_err());
+  }
+}
@@ -0,0 +1,502 @@
+use std::{
+  cell::RefCell,
+  rc::Rc,
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+use blokus::{Blokus, Classic, Color, Duo, HeuristicEval, Move, Scoring, State, Variant};
+use rustyai::{
+  search::{
+    eval::{BaseEval, RandomRolloutEval},
+    forest::{refcnt_forest::Node, TreeNode, TreeNodePtr},
+    Search, Uct,
+  },
+  MaMdp,
+};
+use tokio::sync::Semaphore;
+
+use crate::{
+  account::User,
+  game,
+  games::{GameRecord, Games, Status},
+  position::Position,
+};
+
+// Seats played by the search. Each difficulty is played by its own
+// account, so bots show up in games like any other player
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Difficulty {
+  Easy,
+  Medium,
+  Hard,
+}
+
+impl From<&game::Difficulty> for Difficulty {
+  fn from(difficulty: &game::Difficulty) -> Self {
+    match difficulty {
+      game::Difficulty::Easy => Difficulty::Easy,
+      game::Difficulty::Medium => Difficulty::Medium,
+      game::Difficulty::Hard => Difficulty::Hard,
+    }
+  }
+}
+
+impl From<Difficulty> for game::Difficulty {
+  fn from(difficulty: Difficulty) -> Self {
+    match difficulty {
+      Difficulty::Easy => game::Difficulty::Easy,
+      Difficulty::Medium => game::Difficulty::Medium,
+      Difficulty::Hard => game::Difficulty::Hard,
+    }
+  }
+}
+
+// How the search values the leaves it reaches
+#[derive(Clone, Copy, Debug)]
+pub enum Eval {
+  // plays random moves for up to that many plies
+  Rollout(u32),
+  Heuristic,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Budget {
+  Iterations(u32),
+  Time(Duration),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BotConfig {
+  // the exploration constant of UCT, the tree policy
+  pub exploration: f32,
+  pub eval: Eval,
+  pub budget: Budget,
+}
+
+// What the server lets bots use, whatever their difficulty
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BotLimits {
+  // searches run at once, each on a blocking thread
+  pub workers: usize,
+  // the longest a search given time may take
+  pub max_time: Duration,
+}
+
+impl Default for BotLimits {
+  fn default() -> Self {
+    BotLimits {
+      workers: 4,
+      max_time: Duration::from_secs(10),
+    }
+  }
+}
+
+impl BotConfig {
+  fn limited(self, limits: &BotLimits) -> BotConfig {
+    let budget = match self.budget {
+      Budget::Time(time) => Budget::Time(time.min(limits.max_time)),
+      budget => budget,
+    };
+    BotConfig { budget, ..self }
+  }
+}
+
+const USER_PREFIX: &str = "bot-";
+
+impl Difficulty {
+  pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard];
+
+  pub fn config(self) -> BotConfig {
+    match self {
+      Difficulty::Easy => BotConfig {
+        exploration: 2.4,
+        eval: Eval::Rollout(20),
+        budget: Budget::Iterations(200),
+      },
+      Difficulty::Medium => BotConfig {
+        exploration: 2.4,
+        eval: Eval::Heuristic,
+        budget: Budget::Iterations(2_000),
+      },
+      Difficulty::Hard => BotConfig {
+        exploration: 2.4,
+        eval: Eval::Heuristic,
+        budget: Budget::Time(Duration::from_secs(3)),
+      },
+    }
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      Difficulty::Easy => "easy",
+      Difficulty::Medium => "medium",
+      Difficulty::Hard => "hard",
+    }
+  }
+
+  // The account of the bot. Its name has a space, which registered names
+  // can't have
+  pub fn user(self) -> User {
+    User {
+      id: format!("{USER_PREFIX}{}", self.name()),
+      name: format!("Bot ({})", self.name()),
+    }
+  }
+
+  // The difficulty of the bot with that user id, if it is one
+  pub fn of(user_id: &str) -> Option<Difficulty> {
+    let name = user_id.strip_prefix(USER_PREFIX)?;
+    Difficulty::ALL.into_iter().find(|d| d.name() == name)
+  }
+}
+
+// What the search makes of a position
+pub struct Analysis {
+  pub color: Color,
+  pub iterations: u32,
+  // the moves of the color to move, with their share of the visits and
+  // their value to it, most visited first
+  pub moves: Vec<(Move, f32, f32)>,
+  // the most visited line of play from the position, as far as the search
+  // went down it
+  pub principal_variation: Vec<(Color, Move)>,
+}
+
+type Tree = Rc<RefCell<Node<Move, Move>>>;
+
+// Every color descends its tree by the move played, whoever played it
+fn principal_variation<V: Variant<N>, const N: usize>(
+  game: &Blokus<V>,
+  state: &State<N>,
+  mut trees: [Tree; N],
+) -> Vec<(Color, Move)> {
+  let mut state = state.clone();
+  let mut line = vec![];
+  while let Some(color) = state.color_to_move() {
+    // the first of the most visited, as in Analysis::moves
+    let best = trees[color as usize]
+      .lock()
+      .actions()
+      .iter()
+      .rev()
+      .filter(|(_, info)| info.select_count > 0)
+      .max_by_key(|(_, info)| info.select_count)
+      .map(|(m, _)| *m);
+    let Some(m) = best else {
+      break;
+    };
+    line.push((color, m));
+    for tree in &mut trees {
+      let child = tree.lock().children().get(&m).cloned();
+      let Some(child) = child else {
+        return line;
+      };
+      *tree = child;
+    }
+    let mut joint_action = [Move::Pass; N];
+    joint_action[color as usize] = m;
+    game.transition(&mut state, &joint_action);
+  }
+  line
+}
+
+fn run<V, E, const N: usize>(state: &State<N>, search: Search<Uct, E>, budget: Budget) -> Analysis
+where
+  V: Variant<N>,
+  E: BaseEval<Blokus<V>, State<N>, Move, N>,
+{
+  let game = Blokus::<V>::new(Scoring::Rank);
+  let color = state.color_to_move().expect("a search in a finished game");
+  let trees: [Tree; N] = std::array::from_fn(|_| Node::new());
+  let start = Instant::now();
+  let mut iterations = 0;
+  // at least one iteration, so the root has its moves
+  loop {
+    search.step_mdp(&game, state, trees.clone());
+    iterations += 1;
+    let done = match budget {
+      Budget::Iterations(limit) => iterations >= limit,
+      Budget::Time(limit) => start.elapsed() >= limit,
+    };
+    if done {
+      break;
+    }
+  }
+  let mut moves: Vec<(Move, f32, f32)> = trees[color as usize]
+    .lock()
+    .compute_policy()
+    .into_iter()
+    .map(|(m, share, value)| (*m, share, value))
+    .collect();
+  moves.sort_by(|a, b| b.1.total_cmp(&a.1));
+  Analysis {
+    color,
+    iterations,
+    moves,
+    principal_variation: principal_variation(&game, state, trees),
+  }
+}
+
+fn search<V: Variant<N>, const N: usize>(state: &State<N>, config: &BotConfig) -> Analysis {
+  let tree_policy = Uct(config.exploration);
+  match config.eval {
+    Eval::Rollout(horizon) => {
+      let eval = RandomRolloutEval::<Blokus<V>, State<N>, (), Move>::new(horizon);
+      run::<V, _, N>(state, Search::new(tree_policy, eval), config.budget)
+    }
+    Eval::Heuristic => {
+      let eval = HeuristicEval::default();
+      run::<V, _, N>(state, Search::new(tree_policy, eval), config.budget)
+    }
+  }
+}
+
+// Searches the position, which can't be finished, to the budget of config,
+// so it's meant for a blocking thread
+pub fn analyze(position: &Position, config: &BotConfig) -> Analysis {
+  match position {
+    Position::Classic(state) => search::<Classic, 4>(state, config),
+    Position::Duo(state) => search::<Duo, 2>(state, config),
+  }
+}
+
+// The move of the color to move, the most visited by the search
+pub fn choose_move(position: &Position, config: &BotConfig) -> Move {
+  let moves = position.legal_moves();
+  if moves.len() == 1 {
+    return moves[0];
+  }
+  let analysis = analyze(position, config);
+  analysis.moves.first().map_or(moves[0], |(m, _, _)| *m)
+}
+
+impl GameRecord {
+  // The color to move and its difficulty, when a bot is to move
+  pub fn bot_to_move(&self) -> Option<(Color, Difficulty)> {
+    if self.status != Status::Ongoing {
+      return None;
+    }
+    let color = self.position.color_to_move()?;
+    let user = self.seats[color as usize].as_ref()?;
+    Some((color, Difficulty::of(&user.id)?))
+  }
+}
+
+// The bots of the server, see spawn
+pub struct Bots {
+  workers: Arc<Semaphore>,
+  limits: BotLimits,
+}
+
+impl Bots {
+  // Waits for the moves being searched to be played, and stops bots from
+  // moving again
+  pub async fn finish(&self) {
+    let workers = self.limits.workers as u32;
+    if let Ok(permits) = self.workers.acquire_many(workers).await {
+      permits.forget();
+    }
+    self.workers.close();
+  }
+}
+
+// Plays the bot moves of the games, as games tell they're due. Searches
+// run on the blocking pool, a few at a time, and their moves are played
+// like those of players, so they are stored and sent to subscribers the
+// same way
+pub fn spawn(games: Arc<Games>, limits: BotLimits) -> Bots {
+  let workers = Arc::new(Semaphore::new(limits.workers));
+  let mut turns = games.bot_turns();
+  // games left waiting on a bot when the server last stopped
+  match games.ongoing() {
+    Ok(list) => list
+      .iter()
+      .filter(|game| game.bot_to_move().is_some())
+      .for_each(|game| games.notify_bot(game)),
+    Err(error) => eprintln!("Bot moves of stored games not resumed: {error:?}"),
+  }
+  let bots = Bots {
+    workers: workers.clone(),
+    limits,
+  };
+  tokio::spawn(async move {
+    while let Some(id) = turns.recv().await {
+      tokio::spawn(play(games.clone(), workers.clone(), limits, id));
+    }
+  });
+  bots
+}
+
+async fn play(games: Arc<Games>, workers: Arc<Semaphore>, limits: BotLimits, id: String) {
+  // closed once the server stops
+  let Ok(_worker) = workers.acquire().await else {
+    return;
+  };
+  let Ok(game) = games.get(&id) else {
+    return;
+  };
+  let Some((color, difficulty)) = game.bot_to_move() else {
+    return;
+  };
+  let position = game.position;
+  let config = difficulty.config().limited(&limits);
+  let search = tokio::task::spawn_blocking(move || choose_move(&position, &config));
+  let Ok(m) = search.await else {
+    return eprintln!("The bot search of game {id} failed");
+  };
+  // the game may have moved on while the bot was thinking
+  if let Err(error) = games.play_as(&id, &difficulty.user().id, color, m) {
+    eprintln!("The bot move {m} of game {id} was refused: {error:?}");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::{games::Setup, position::VariantKind, store::MemoryStore};
+
+  #[test]
+  fn test_users() {
+    for difficulty in Difficulty::ALL {
+      assert_eq!(Difficulty::of(&difficulty.user().id), Some(difficulty));
+    }
+    assert_eq!(Difficulty::of("bot-impossible"), None);
+    assert_eq!(Difficulty::of("easy"), None);
+  }
+
+  #[test]
+  fn test_choose_move() {
+    let mut position = Position::new(VariantKind::Duo);
+    let config = BotConfig {
+      exploration: 2.4,
+      eval: Eval::Rollout(4),
+      budget: Budget::Iterations(50),
+    };
+    for _ in 0..4 {
+      let m = choose_move(&position, &config);
+      assert!(position.check(&m).is_ok());
+      position.play(m);
+    }
+    let config = BotConfig {
+      eval: Eval::Heuristic,
+      budget: Budget::Time(Duration::from_millis(10)),
+      ..config
+    };
+    assert!(position.check(&choose_move(&position, &config)).is_ok());
+  }
+
+  #[test]
+  fn test_analyze() {
+    let config = BotConfig {
+      exploration: 2.4,
+      eval: Eval::Heuristic,
+      budget: Budget::Iterations(300),
+    };
+    let analysis = analyze(&Position::new(VariantKind::Duo), &config);
+    assert_eq!(analysis.color, Color::Blue);
+    assert_eq!(analysis.iterations, 300);
+    let shares: f32 = analysis.moves.iter().map(|(_, share, _)| share).sum();
+    assert!((shares - 1.0).abs() < 1e-3, "{shares}");
+    assert!(analysis.moves.windows(2).all(|w| w[0].1 >= w[1].1));
+
+    // the line starts with the most visited move, and is played in turn.
+    // Few of the opening moves are tried twice, so it may end there
+    let line = &analysis.principal_variation;
+    assert!(!line.is_empty());
+    assert_eq!(line[0], (Color::Blue, analysis.moves[0].0));
+    let mut position = Position::new(VariantKind::Duo);
+    for (color, m) in line {
+      assert_eq!(position.color_to_move(), Some(*color));
+      assert!(position.check(m).is_ok());
+      position.play(*m);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_bots_play() {
+    let games = Arc::new(Games::new(Arc::new(MemoryStore::new())));
+    spawn(games.clone(), BotLimits::default());
+    let alice = User {
+      id: "alice".to_string(),
+      name: "alice".to_string(),
+    };
+    let id = games
+      .create(
+        VariantKind::Duo,
+        Setup {
+          bots: vec![None, Some(Difficulty::Easy)],
+          ..Setup::default()
+        },
+      )
+      .unwrap()
+      .id;
+    let mut updates = games.subscribe(&id);
+    games.join(&id, alice).unwrap();
+    let m = games.get(&id).unwrap().position.legal_moves()[0];
+    games.play_as(&id, "alice", Color::Blue, m).unwrap();
+    // the bot answers on its own
+    let game = loop {
+      updates.recv().await.unwrap();
+      let game = games.get(&id).unwrap();
+      if game.moves.len() == 2 {
+        break game;
+      }
+    };
+    assert_eq!(game.moves[1].color, Color::Yellow);
+    let json = serde_json::to_value(game.to_json()).unwrap();
+    assert_eq!(json["players"][1]["bot"], "EASY");
+    assert_eq!(json["players"][1]["user"]["name"], "Bot (easy)");
+    assert!(json["players"][0].get("bot").is_none());
+  }
+
+  #[tokio::test]
+  async fn test_finish() {
+    let games = Arc::new(Games::new(Arc::new(MemoryStore::new())));
+    let limits = BotLimits {
+      workers: 1,
+      max_time: Duration::from_millis(1),
+    };
+    assert!(matches!(
+      Difficulty::Hard.config().limited(&limits).budget,
+      Budget::Time(time) if time == limits.max_time
+    ));
+    let bots = spawn(games.clone(), limits);
+    let setup = Setup {
+      bots: vec![Some(Difficulty::Easy); 2],
+      ..Setup::default()
+    };
+    let id = games.create(VariantKind::Duo, setup).unwrap().id;
+    let mut updates = games.subscribe(&id);
+    updates.recv().await.unwrap();
+    bots.finish().await;
+    // the move being searched was played, and no other is
+    let played = games.get(&id).unwrap().moves.len();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(games.get(&id).unwrap().moves.len(), played);
+    assert_eq!(games.get(&id).unwrap().status, Status::Ongoing);
+  }
+
+  #[tokio::test]
+  async fn test_bots_only() {
+    let games = Arc::new(Games::new(Arc::new(MemoryStore::new())));
+    spawn(games.clone(), BotLimits::default());
+    let setup = Setup {
+      bots: vec![Some(Difficulty::Easy); 2],
+      ..Setup::default()
+    };
+    let id = games.create(VariantKind::Duo, setup).unwrap().id;
+    let mut updates = games.subscribe(&id);
+    // a game between bots starts at once, and plays out to the end
+    tokio::time::timeout(Duration::from_secs(60), async {
+      while games.get(&id).unwrap().status != Status::Ended {
+        // a lagging receiver only missed updates, the game is read again
+        let _ = updates.recv().await;
+      }
+    })
+    .await
+    .unwrap();
+  }
+}
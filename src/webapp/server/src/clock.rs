@@ -0,0 +1,353 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use blokus::{Color, Move};
+use tokio::task::JoinHandle;
+
+use crate::{
+  game,
+  games::{GameError, GameRecord, Games, Played, Status},
+};
+
+// Times are kept in milliseconds since the Unix epoch, so they can be
+// stored and survive restarts
+pub fn now() -> u64 {
+  let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+  since_epoch.as_millis() as u64
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimeControl {
+  // each color has initial for the whole game, and gains increment with
+  // each of its moves
+  Fischer {
+    initial: Duration,
+    increment: Duration,
+  },
+  // each move has to be played within limit
+  PerMove {
+    limit: Duration,
+  },
+}
+
+// What happens to a color whose time runs out. Either way it passes, which
+// in Blokus means it places no more pieces. A color that forfeits also
+// ranks last, and the game ends once a single color hasn't forfeited
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OnTimeout {
+  Pass,
+  Forfeit,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Clock {
+  pub control: TimeControl,
+  pub on_timeout: OnTimeout,
+}
+
+fn millis(duration: Duration) -> u32 {
+  duration.as_millis().try_into().unwrap_or(u32::MAX)
+}
+
+impl From<&game::GameClock> for Clock {
+  fn from(clock: &game::GameClock) -> Self {
+    let ms = |ms: u32| Duration::from_millis(ms as u64);
+    Clock {
+      control: match &clock.timeControl {
+        game::GameClockTimeControl::Fischer(fischer) => TimeControl::Fischer {
+          initial: ms(fischer.initialMs),
+          increment: ms(fischer.incrementMs),
+        },
+        game::GameClockTimeControl::PerMove(per_move) => TimeControl::PerMove {
+          limit: ms(per_move.limitMs),
+        },
+      },
+      on_timeout: match clock.onTimeout {
+        game::GameClockOnTimeout::Pass => OnTimeout::Pass,
+        game::GameClockOnTimeout::Forfeit => OnTimeout::Forfeit,
+      },
+    }
+  }
+}
+
+impl From<Clock> for game::GameClock {
+  fn from(clock: Clock) -> Self {
+    game::GameClock {
+      timeControl: match clock.control {
+        TimeControl::Fischer { initial, increment } => {
+          game::GameClockTimeControl::Fischer(game::GameClockTimeControlFischer {
+            incrementMs: millis(increment),
+            initialMs: millis(initial),
+          })
+        }
+        TimeControl::PerMove { limit } => {
+          game::GameClockTimeControl::PerMove(game::GameClockTimeControlPerMove {
+            limitMs: millis(limit),
+          })
+        }
+      },
+      onTimeout: match clock.on_timeout {
+        OnTimeout::Pass => game::GameClockOnTimeout::Pass,
+        OnTimeout::Forfeit => game::GameClockOnTimeout::Forfeit,
+      },
+    }
+  }
+}
+
+impl GameRecord {
+  // When the color to move started its turn
+  fn turn_started(&self) -> Option<u64> {
+    self
+      .moves
+      .last()
+      .map(|played| played.at)
+      .or(self.started_at)
+  }
+
+  // The time color has left at now, for games with a clock that have
+  // started
+  pub fn remaining(&self, color: Color, now: u64) -> Option<Duration> {
+    let clock = self.clock?;
+    let mut turn_start = self.started_at?;
+    let thinking = |from: u64, to: u64| Duration::from_millis(to.saturating_sub(from));
+    let mut remaining = match clock.control {
+      TimeControl::Fischer { initial, increment } => {
+        let mut remaining = initial;
+        for played in &self.moves {
+          if played.color == color {
+            remaining = remaining.saturating_sub(thinking(turn_start, played.at)) + increment;
+          }
+          turn_start = played.at;
+        }
+        remaining
+      }
+      TimeControl::PerMove { limit } => limit,
+    };
+    if self.status == Status::Ongoing && self.position.color_to_move() == Some(color) {
+      remaining = remaining.saturating_sub(thinking(self.turn_started()?, now));
+    }
+    Some(remaining)
+  }
+
+  // When the time of the color to move runs out
+  pub fn deadline(&self) -> Option<u64> {
+    if self.status != Status::Ongoing {
+      return None;
+    }
+    let color = self.position.color_to_move()?;
+    let start = self.turn_started()?;
+    let remaining = self.remaining(color, start)?;
+    Some(start + remaining.as_millis() as u64)
+  }
+
+  pub fn forfeited(&self, color: Color) -> bool {
+    self
+      .clock
+      .is_some_and(|clock| clock.on_timeout == OnTimeout::Forfeit)
+      && self
+        .moves
+        .iter()
+        .any(|played| played.timeout && played.color == color)
+  }
+
+  // Makes the color to move pass if its time has run out by now, telling
+  // whether it had
+  pub fn time_out(&mut self, now: u64) -> Result<bool, GameError> {
+    let (Some(deadline), Some(color)) = (self.deadline(), self.position.color_to_move()) else {
+      return Ok(false);
+    };
+    if now < deadline {
+      return Ok(false);
+    }
+    self.position.play(Move::Pass);
+    self.moves.push(Played {
+      color,
+      m: Move::Pass,
+      at: now,
+      timeout: true,
+    });
+    let standing = (0..self.seats.len())
+      .filter(|seat| !self.forfeited(Color::ALL[*seat]))
+      .count();
+    if self.position.is_over() || standing <= 1 {
+      self.end();
+    }
+    Ok(true)
+  }
+}
+
+// How often games are checked for a color out of time
+const TICK: Duration = Duration::from_millis(200);
+
+// The deadlines of the games with a clock running, so the clocks can be
+// checked without loading every game
+#[derive(Default)]
+pub struct Deadlines(Mutex<HashMap<String, u64>>);
+
+impl Deadlines {
+  pub fn update(&self, game: &GameRecord) {
+    let mut deadlines = self.0.lock().unwrap();
+    match game.deadline() {
+      Some(deadline) => deadlines.insert(game.id.clone(), deadline),
+      None => deadlines.remove(&game.id),
+    };
+  }
+
+  // The games whose deadline has passed
+  fn due(&self, now: u64) -> Vec<String> {
+    let deadlines = self.0.lock().unwrap();
+    deadlines
+      .iter()
+      .filter(|(_, deadline)| **deadline <= now)
+      .map(|(id, _)| id.clone())
+      .collect()
+  }
+}
+
+// Passes for the colors whose time runs out, as players would, so it is
+// stored and sent to subscribers the same way
+pub fn spawn(games: Arc<Games>) -> JoinHandle<()> {
+  // clocks left running when the server last stopped
+  match games.ongoing() {
+    Ok(list) => list.iter().for_each(|game| games.deadlines.update(game)),
+    Err(error) => eprintln!("Clocks of stored games not resumed: {error:?}"),
+  }
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+      interval.tick().await;
+      let now = now();
+      for id in games.deadlines.due(now) {
+        if let Err(error) = games.update(&id, |game| game.time_out(now)) {
+          eprintln!("The clock of game {id} wasn't checked: {error:?}");
+        }
+      }
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{account::User, games::Setup, position::VariantKind, store::MemoryStore};
+
+  fn game(clock: Clock) -> GameRecord {
+    let mut game = GameRecord::new("g".to_string(), VariantKind::Duo);
+    game.clock = Some(clock);
+    for id in ["a", "b"] {
+      let user = User {
+        id: id.to_string(),
+        name: id.to_string(),
+      };
+      game.join(user).unwrap();
+    }
+    game
+  }
+
+  // Plays the first legal move at the time given
+  fn play_at(game: &mut GameRecord, now: u64) {
+    let color = game.position.color_to_move().unwrap();
+    let m = game.position.legal_moves()[0];
+    game.play_at(color, m, now).unwrap();
+  }
+
+  #[test]
+  fn test_fischer() {
+    let mut game = game(Clock {
+      control: TimeControl::Fischer {
+        initial: Duration::from_secs(10),
+        increment: Duration::from_secs(2),
+      },
+      on_timeout: OnTimeout::Pass,
+    });
+    game.started_at = Some(0);
+    let remaining = |game: &GameRecord, color, now| game.remaining(color, now).unwrap().as_millis();
+    assert_eq!(remaining(&game, Color::Blue, 3_000), 7_000);
+    assert_eq!(remaining(&game, Color::Yellow, 3_000), 10_000);
+    play_at(&mut game, 3_000);
+    assert_eq!(remaining(&game, Color::Blue, 5_000), 9_000);
+    assert_eq!(remaining(&game, Color::Yellow, 5_000), 8_000);
+    assert_eq!(game.deadline(), Some(13_000));
+
+    assert!(!game.time_out(12_999).unwrap());
+    assert!(game.time_out(13_000).unwrap());
+    let played = game.moves.last().unwrap();
+    assert_eq!(
+      (played.color, played.m, played.timeout),
+      (Color::Yellow, Move::Pass, true)
+    );
+    // passing is for good, so blue plays on alone
+    assert_eq!(game.status, Status::Ongoing);
+    assert_eq!(game.position.color_to_move(), Some(Color::Blue));
+    assert!(!game.forfeited(Color::Yellow));
+    assert_eq!(game.deadline(), Some(13_000 + 9_000));
+  }
+
+  #[test]
+  fn test_forfeit() {
+    let mut game = game(Clock {
+      control: TimeControl::PerMove {
+        limit: Duration::from_secs(5),
+      },
+      on_timeout: OnTimeout::Forfeit,
+    });
+    game.started_at = Some(1_000);
+    play_at(&mut game, 2_000);
+    assert_eq!(game.deadline(), Some(7_000));
+    assert_eq!(
+      game.remaining(Color::Yellow, 3_000),
+      Some(Duration::from_secs(4))
+    );
+    assert_eq!(
+      game.remaining(Color::Blue, 3_000),
+      Some(Duration::from_secs(5))
+    );
+    assert!(game.time_out(7_000).unwrap());
+    assert!(game.forfeited(Color::Yellow));
+    assert_eq!(game.status, Status::Ended);
+    assert_eq!(game.deadline(), None);
+
+    let json = serde_json::to_value(game.to_json()).unwrap();
+    assert_eq!(json["players"][1]["forfeited"], true);
+    assert!(json["players"][0].get("forfeited").is_none());
+    assert_eq!(json["clock"]["time_control"]["kind"], "PER_MOVE");
+    assert_eq!(json["clock"]["time_control"]["limit_ms"], 5_000);
+    assert_eq!(json["clock"]["on_timeout"], "FORFEIT");
+  }
+
+  #[tokio::test]
+  async fn test_clocks_run_out() {
+    let games = Arc::new(Games::new(Arc::new(MemoryStore::new())));
+    let clock = Clock {
+      control: TimeControl::PerMove {
+        limit: Duration::from_millis(100),
+      },
+      on_timeout: OnTimeout::Pass,
+    };
+    let players = ["a", "b"].map(|id| User {
+      id: id.to_string(),
+      name: id.to_string(),
+    });
+    let setup = Setup {
+      players: players.to_vec(),
+      clock: Some(clock),
+      ..Setup::default()
+    };
+    let id = games.create(VariantKind::Duo, setup).unwrap().id;
+    let mut updates = games.subscribe(&id);
+    spawn(games.clone());
+    // both colors pass in turn, which ends the game
+    let game = loop {
+      updates.recv().await.unwrap();
+      let game = games.get(&id).unwrap();
+      if game.status == Status::Ended {
+        break game;
+      }
+    };
+    assert_eq!(game.moves.len(), 2);
+    assert!(game.moves.iter().all(|played| played.timeout));
+  }
+}
@@ -0,0 +1,214 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use axum::http::HeaderValue;
+use serde::Deserialize;
+
+use crate::bots::BotLimits;
+
+// How the server is set up. It's read from a TOML file, blokus.toml unless
+// BLOKUS_CONFIG names another, and environment variables override it:
+//
+//   bind = "0.0.0.0:3000"              BLOKUS_BIND
+//   storage = "blokus.sqlite3"         BLOKUS_DB
+//   static_dir = "../blokus-ui/build"  BLOKUS_STATIC, empty to serve none
+//   cors_origins = []                  BLOKUS_CORS_ORIGINS, comma separated
+//   [bots]
+//   workers = 4                        BLOKUS_BOT_WORKERS
+//   max_time_ms = 10000                BLOKUS_BOT_MAX_TIME_MS
+#[derive(Debug, PartialEq)]
+pub struct Config {
+  pub bind: SocketAddr,
+  // the SQLite database
+  pub storage: PathBuf,
+  // the build of blokus-ui, whose index.html is served for paths no route
+  // takes, so the app can route them itself
+  pub static_dir: Option<PathBuf>,
+  // origins other than the server's that may call the API
+  pub cors_origins: Vec<HeaderValue>,
+  pub bots: BotLimits,
+}
+
+const DEFAULT_FILE: &str = "blokus.toml";
+
+// The file as written, anything left out taking its default
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+  bind: Option<String>,
+  storage: Option<PathBuf>,
+  static_dir: Option<PathBuf>,
+  cors_origins: Option<Vec<String>>,
+  bots: BotsFile,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct BotsFile {
+  workers: Option<usize>,
+  max_time_ms: Option<u64>,
+}
+
+impl Config {
+  // Reads the file and the environment of the process. The default file may
+  // be missing, one named in BLOKUS_CONFIG may not
+  pub fn load() -> Result<Config, String> {
+    let (path, required) = match std::env::var("BLOKUS_CONFIG") {
+      Ok(path) => (path, true),
+      Err(_) => (DEFAULT_FILE.to_string(), false),
+    };
+    let file = match std::fs::read_to_string(&path) {
+      Ok(file) => Some(file),
+      Err(error) if required || error.kind() != std::io::ErrorKind::NotFound => {
+        return Err(format!("{path}: {error}"))
+      }
+      Err(_) => None,
+    };
+    Config::from_sources(file.as_deref(), |name| std::env::var(name).ok())
+      .map_err(|error| format!("{path}: {error}"))
+  }
+
+  // The config of the file, if any, overridden by the variables env gives
+  pub fn from_sources(
+    file: Option<&str>,
+    env: impl Fn(&str) -> Option<String>,
+  ) -> Result<Config, String> {
+    let file: File = match file {
+      Some(file) => toml::from_str(file).map_err(|error| error.to_string())?,
+      None => File::default(),
+    };
+    let bind = env("BLOKUS_BIND")
+      .or(file.bind)
+      .unwrap_or_else(|| "0.0.0.0:3000".to_string());
+    let bind = bind
+      .parse()
+      .map_err(|_| format!("Bad bind address {bind}"))?;
+    let storage = env("BLOKUS_DB")
+      .map(PathBuf::from)
+      .or(file.storage)
+      .unwrap_or_else(|| "blokus.sqlite3".into());
+    let static_dir = env("BLOKUS_STATIC")
+      .map(PathBuf::from)
+      .or(file.static_dir)
+      .unwrap_or_else(|| "../blokus-ui/build".into());
+    let static_dir = (!static_dir.as_os_str().is_empty()).then_some(static_dir);
+    let cors_origins = match env("BLOKUS_CORS_ORIGINS") {
+      Some(origins) => origins
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(str::to_string)
+        .collect(),
+      None => file.cors_origins.unwrap_or_default(),
+    };
+    let cors_origins = cors_origins
+      .into_iter()
+      .map(|origin: String| {
+        HeaderValue::from_str(&origin).map_err(|_| format!("Bad CORS origin {origin:?}"))
+      })
+      .collect::<Result<_, _>>()?;
+    let defaults = BotLimits::default();
+    let workers = match env("BLOKUS_BOT_WORKERS") {
+      Some(workers) => Some(number("BLOKUS_BOT_WORKERS", &workers)?),
+      None => file.bots.workers,
+    };
+    let workers = workers.unwrap_or(defaults.workers);
+    if workers == 0 {
+      return Err("Bots need at least one worker".to_string());
+    }
+    let max_time = match env("BLOKUS_BOT_MAX_TIME_MS") {
+      Some(ms) => Some(number("BLOKUS_BOT_MAX_TIME_MS", &ms)?),
+      None => file.bots.max_time_ms,
+    };
+    let max_time = max_time.map_or(defaults.max_time, Duration::from_millis);
+    Ok(Config {
+      bind,
+      storage,
+      static_dir,
+      cors_origins,
+      bots: BotLimits { workers, max_time },
+    })
+  }
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+  value
+    .trim()
+    .parse()
+    .map_err(|_| format!("{name} isn't a number: {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn no_env(_: &str) -> Option<String> {
+    None
+  }
+
+  #[test]
+  fn test_defaults() {
+    let config = Config::from_sources(None, no_env).unwrap();
+    assert_eq!(config.bind, "0.0.0.0:3000".parse().unwrap());
+    assert_eq!(config.storage, PathBuf::from("blokus.sqlite3"));
+    assert_eq!(config.static_dir, Some("../blokus-ui/build".into()));
+    assert!(config.cors_origins.is_empty());
+    assert_eq!(config.bots, BotLimits::default());
+  }
+
+  #[test]
+  fn test_file_and_env() {
+    let file = r#"
+      bind = "127.0.0.1:8080"
+      storage = "/var/lib/blokus.sqlite3"
+      cors_origins = ["http://localhost:3001"]
+
+      [bots]
+      workers = 2
+      max_time_ms = 1500
+    "#;
+    let config = Config::from_sources(Some(file), no_env).unwrap();
+    assert_eq!(config.bind, "127.0.0.1:8080".parse().unwrap());
+    assert_eq!(config.storage, PathBuf::from("/var/lib/blokus.sqlite3"));
+    assert_eq!(config.cors_origins, ["http://localhost:3001"]);
+    assert_eq!(
+      config.bots,
+      BotLimits {
+        workers: 2,
+        max_time: Duration::from_millis(1500)
+      }
+    );
+
+    // the environment wins
+    let env = |name: &str| match name {
+      "BLOKUS_BIND" => Some("[::1]:9000".to_string()),
+      "BLOKUS_STATIC" => Some(String::new()),
+      "BLOKUS_CORS_ORIGINS" => Some("https://a.example, https://b.example".to_string()),
+      "BLOKUS_BOT_WORKERS" => Some("8".to_string()),
+      _ => None,
+    };
+    let config = Config::from_sources(Some(file), env).unwrap();
+    assert_eq!(config.bind, "[::1]:9000".parse().unwrap());
+    assert_eq!(config.storage, PathBuf::from("/var/lib/blokus.sqlite3"));
+    assert_eq!(config.static_dir, None);
+    assert_eq!(
+      config.cors_origins,
+      ["https://a.example", "https://b.example"]
+    );
+    assert_eq!(config.bots.workers, 8);
+    assert_eq!(config.bots.max_time, Duration::from_millis(1500));
+  }
+
+  #[test]
+  fn test_errors() {
+    assert!(Config::from_sources(Some("port = 3000"), no_env).is_err());
+    assert!(Config::from_sources(Some("bind = \"nowhere\""), no_env).is_err());
+    assert!(Config::from_sources(Some("[bots]\nworkers = 0"), no_env).is_err());
+    let env = |name: &str| (name == "BLOKUS_BOT_MAX_TIME_MS").then(|| "soon".to_string());
+    assert!(Config::from_sources(None, env).is_err());
+    // origins end up in a header, which can't hold control characters
+    let error = Config::from_sources(Some("cors_origins = [\"http://a\\u0007\"]"), no_env);
+    assert_eq!(error, Err("Bad CORS origin \"http://a\\u{7}\"".to_string()));
+    let env = |name: &str| (name == "BLOKUS_CORS_ORIGINS").then(|| "http://a\nb".to_string());
+    assert!(Config::from_sources(None, env).is_err());
+  }
+}
@@ -2,6 +2,61 @@
 
 use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
+pub enum GameClockOnTimeout {
+    #[serde(rename = "FORFEIT")]
+    Forfeit,
+
+    #[serde(rename = "PASS")]
+    Pass,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum GameClockTimeControl {
+    #[serde(rename = "FISCHER")]
+    Fischer(GameClockTimeControlFischer),
+
+    #[serde(rename = "PER_MOVE")]
+    PerMove(GameClockTimeControlPerMove),
+}
+
+/// Each color has initial_ms for the game, and gains increment_ms with each of its moves
+#[derive(Serialize, Deserialize)]
+pub struct GameClockTimeControlFischer {
+    #[serde(rename = "increment_ms")]
+    pub incrementMs: u32,
+
+    #[serde(rename = "initial_ms")]
+    pub initialMs: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GameClockTimeControlPerMove {
+    #[serde(rename = "limit_ms")]
+    pub limitMs: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GameClock {
+    /// What happens to a color out of time: it passes for the rest of the game, or also forfeits
+    #[serde(rename = "on_timeout")]
+    pub onTimeout: GameClockOnTimeout,
+
+    #[serde(rename = "time_control")]
+    pub timeControl: GameClockTimeControl,
+}
+
+/// What the game did to the rating of the player, once it ended. Bots keep theirs
+#[derive(Serialize, Deserialize)]
+pub struct GamePlayerRatingChange {
+    #[serde(rename = "delta")]
+    pub delta: i32,
+
+    #[serde(rename = "rating")]
+    pub rating: i32,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GamePlayer {
     #[serde(rename = "color")]
@@ -12,6 +67,26 @@ pub struct GamePlayer {
 
     #[serde(rename = "user")]
     pub user: User,
+
+    /// The difficulty of the search playing this color, for bots
+    #[serde(rename = "bot")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bot: Option<Box<Difficulty>>,
+
+    /// Set when the color ran out of time in a game whose clock forfeits
+    #[serde(rename = "forfeited")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forfeited: Option<Box<bool>>,
+
+    /// The time the color has left, in games with a clock that have started
+    #[serde(rename = "remaining_ms")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remainingMs: Option<Box<u32>>,
+
+    /// What the game did to the rating of the player, once it ended. Bots keep theirs
+    #[serde(rename = "rating_change")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ratingChange: Option<Box<GamePlayerRatingChange>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -26,51 +101,58 @@ pub enum GameStatus {
     WaitingForPlayers,
 }
 
+#[derive(Serialize, Deserialize)]
+pub enum GameVariant {
+    #[serde(rename = "CLASSIC")]
+    Classic,
+
+    #[serde(rename = "DUO")]
+    Duo,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Game {
     #[serde(rename = "id")]
     pub id: String,
 
+    #[serde(rename = "moves")]
+    pub moves: Vec<Move>,
+
     #[serde(rename = "players")]
     pub players: Vec<GamePlayer>,
 
+    /// Bumped whenever the schema changes in a way older clients can't read
+    #[serde(rename = "schema_version")]
+    pub schemaVersion: u32,
+
     #[serde(rename = "status")]
     pub status: GameStatus,
 
+    #[serde(rename = "variant")]
+    pub variant: GameVariant,
+
     #[serde(rename = "board")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub board: Option<Box<Board>>,
-}
 
-#[derive(Serialize, Deserialize)]
-pub struct BoardRemainingTiles {
-    #[serde(rename = "color")]
-    pub color: Color,
-
-    #[serde(rename = "tiles")]
-    pub tiles: Vec<Tile>,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct BoardScore {
-    #[serde(rename = "color")]
-    pub color: Color,
+    #[serde(rename = "clock")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock: Option<Box<GameClock>>,
 
-    #[serde(rename = "score")]
-    pub score: i8,
+    /// The code to join a private game with, only sent to its creator
+    #[serde(rename = "invite_code")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inviteCode: Option<Box<String>>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Board {
+    #[serde(rename = "colors")]
+    pub colors: Vec<ColorState>,
+
     #[serde(rename = "over")]
     pub over: bool,
 
-    #[serde(rename = "remaining_tiles")]
-    pub remainingTiles: BoardRemainingTiles,
-
-    #[serde(rename = "score")]
-    pub score: BoardScore,
-
     #[serde(rename = "table")]
     pub table: Vec<Vec<Option<Box<Color>>>>,
 
@@ -95,15 +177,133 @@ pub enum Color {
 }
 
 #[derive(Serialize, Deserialize)]
-pub enum Tile {
-    #[serde(rename = "T1")]
-    T1,
+pub struct ColorState {
+    #[serde(rename = "color")]
+    pub color: Color,
+
+    #[serde(rename = "remaining_pieces")]
+    pub remainingPieces: Vec<Piece>,
+
+    #[serde(rename = "score")]
+    pub score: i8,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Difficulty {
+    #[serde(rename = "EASY")]
+    Easy,
+
+    #[serde(rename = "HARD")]
+    Hard,
+
+    #[serde(rename = "MEDIUM")]
+    Medium,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Move {
+    #[serde(rename = "PASS")]
+    Pass(MovePass),
+
+    #[serde(rename = "PLACE")]
+    Place(MovePlace),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MovePass {
+    #[serde(rename = "color")]
+    pub color: Color,
+}
+
+/// The top left corner of the bounding box of the placed piece
+#[derive(Serialize, Deserialize)]
+pub struct MovePlaceAnchor {
+    #[serde(rename = "col")]
+    pub col: u8,
+
+    #[serde(rename = "row")]
+    pub row: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MovePlace {
+    /// The top left corner of the bounding box of the placed piece
+    #[serde(rename = "anchor")]
+    pub anchor: MovePlaceAnchor,
+
+    #[serde(rename = "color")]
+    pub color: Color,
+
+    #[serde(rename = "orientation")]
+    pub orientation: Orientation,
+
+    #[serde(rename = "piece")]
+    pub piece: Piece,
+}
+
+/// R0..R3 rotate the piece clockwise by 90 degrees, F0..F3 mirror its columns before rotating
+#[derive(Serialize, Deserialize)]
+pub enum Orientation {
+    #[serde(rename = "F0")]
+    F0,
+
+    #[serde(rename = "F1")]
+    F1,
+
+    #[serde(rename = "F2")]
+    F2,
+
+    #[serde(rename = "F3")]
+    F3,
+
+    #[serde(rename = "R0")]
+    R0,
+
+    #[serde(rename = "R1")]
+    R1,
+
+    #[serde(rename = "R2")]
+    R2,
+
+    #[serde(rename = "R3")]
+    R3,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Piece {
+    #[serde(rename = "F5")]
+    F5,
+
+    #[serde(rename = "I1")]
+    I1,
 
-    #[serde(rename = "T2")]
-    T2,
+    #[serde(rename = "I2")]
+    I2,
 
-    #[serde(rename = "T3")]
-    T3,
+    #[serde(rename = "I3")]
+    I3,
+
+    #[serde(rename = "I4")]
+    I4,
+
+    #[serde(rename = "I5")]
+    I5,
+
+    #[serde(rename = "L4")]
+    L4,
+
+    #[serde(rename = "L5")]
+    L5,
+
+    #[serde(rename = "N5")]
+    N5,
+
+    #[serde(rename = "O4")]
+    O4,
+
+    #[serde(rename = "P5")]
+    P5,
 
     #[serde(rename = "T4")]
     T4,
@@ -111,8 +311,29 @@ pub enum Tile {
     #[serde(rename = "T5")]
     T5,
 
-    #[serde(rename = "T6")]
-    T6,
+    #[serde(rename = "U5")]
+    U5,
+
+    #[serde(rename = "V3")]
+    V3,
+
+    #[serde(rename = "V5")]
+    V5,
+
+    #[serde(rename = "W5")]
+    W5,
+
+    #[serde(rename = "X5")]
+    X5,
+
+    #[serde(rename = "Y5")]
+    Y5,
+
+    #[serde(rename = "Z4")]
+    Z4,
+
+    #[serde(rename = "Z5")]
+    Z5,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -0,0 +1,661 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use blokus::{Color, IllegalMove, Move};
+use rand::seq::SliceRandom;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::{
+  account::{Account, User},
+  board,
+  bots::Difficulty,
+  clock::{self, Clock, Deadlines},
+  game, live,
+  position::{Position, VariantKind},
+  ratings::{self, Rating, RatingChange},
+  store::{Store, StoreError},
+};
+
+// Version of game.jtd.json the server speaks
+pub const SCHEMA_VERSION: u32 = 1;
+
+// A game can only move forward through these: players join while it's
+// waiting, play while it's ongoing, and nothing changes once it has ended
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Status {
+  WaitingForPlayers,
+  Ongoing,
+  Ended,
+}
+
+#[derive(Clone)]
+pub struct GameRecord {
+  pub id: String,
+  pub variant: VariantKind,
+  pub status: Status,
+  // the player of each color, in turn order
+  pub seats: Vec<Option<User>>,
+  // every move played, passes included
+  pub moves: Vec<Played>,
+  // the position after the moves
+  pub position: Position,
+  // the code to join the game with, for private games
+  pub invite: Option<String>,
+  pub clock: Option<Clock>,
+  // when the game got all its players, see clock::now
+  pub started_at: Option<u64>,
+  // what the game did to the rating of the player of each color, once it
+  // ended, see ratings.rs
+  pub rating_changes: Vec<Option<RatingChange>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Played {
+  // engine passes don't say who passed
+  pub color: Color,
+  pub m: Move,
+  // see clock::now
+  pub at: u64,
+  // a pass forced by the color running out of time, which needn't be legal
+  pub timeout: bool,
+}
+
+// What a new game starts with: bots at the colors they are given for, then
+// players at the first free colors. Private games get an invite code, and
+// can only be joined with it
+#[derive(Default)]
+pub struct Setup {
+  pub bots: Vec<Option<Difficulty>>,
+  pub players: Vec<User>,
+  pub private: bool,
+  pub clock: Option<Clock>,
+}
+
+const INVITE_LEN: usize = 6;
+
+#[derive(Debug)]
+pub enum GameError {
+  NotFound,
+  // the game is past the status the request needs
+  WrongStatus(Status),
+  AlreadySeated,
+  // the user has no seat in the game
+  NotSeated,
+  // the move is for a color the user doesn't play
+  NotYourColor,
+  // the game takes an invite code to join
+  Private,
+  // the color's time ran out before it moved
+  OutOfTime,
+  Illegal(IllegalMove),
+  Store(StoreError),
+}
+
+impl From<StoreError> for GameError {
+  fn from(error: StoreError) -> Self {
+    GameError::Store(error)
+  }
+}
+
+impl GameRecord {
+  pub fn new(id: String, variant: VariantKind) -> Self {
+    GameRecord {
+      id,
+      variant,
+      status: Status::WaitingForPlayers,
+      seats: vec![None; variant.colors()],
+      moves: vec![],
+      position: Position::new(variant),
+      invite: None,
+      clock: None,
+      started_at: None,
+      rating_changes: vec![None; variant.colors()],
+    }
+  }
+
+  // A stored game, with its position rebuilt by playing its moves again
+  pub fn replay(
+    id: String,
+    variant: VariantKind,
+    status: Status,
+    seats: Vec<Option<User>>,
+    moves: Vec<Played>,
+  ) -> Result<Self, GameError> {
+    let mut position = Position::new(variant);
+    for played in &moves {
+      if position.color_to_move() != Some(played.color) {
+        return Err(GameError::Illegal(IllegalMove::WrongTurn));
+      }
+      if !played.timeout {
+        position.check(&played.m).map_err(GameError::Illegal)?;
+      }
+      position.play(played.m);
+    }
+    Ok(GameRecord {
+      id,
+      variant,
+      status,
+      seats,
+      moves,
+      position,
+      invite: None,
+      clock: None,
+      started_at: None,
+      rating_changes: vec![None; variant.colors()],
+    })
+  }
+
+  fn set_status(&mut self, status: Status) {
+    let allowed = matches!(
+      (self.status, status),
+      (Status::WaitingForPlayers, Status::Ongoing) | (Status::Ongoing, Status::Ended)
+    );
+    assert!(allowed, "{:?} can't follow {:?}", status, self.status);
+    self.status = status;
+    if status == Status::Ongoing {
+      self.started_at = Some(clock::now());
+    }
+  }
+
+  // Ends the game before every color is stuck, as clocks do
+  pub fn end(&mut self) {
+    self.set_status(Status::Ended);
+  }
+
+  // Seats the user at the first free color, and starts the game once every
+  // color has a player
+  pub fn join(&mut self, user: User) -> Result<Color, GameError> {
+    if self.status != Status::WaitingForPlayers {
+      return Err(GameError::WrongStatus(self.status));
+    }
+    if self
+      .seats
+      .iter()
+      .flatten()
+      .any(|seated| seated.id == user.id)
+    {
+      return Err(GameError::AlreadySeated);
+    }
+    let seat = self.seats.iter().position(Option::is_none).unwrap();
+    self.seat(seat, user);
+    Ok(Color::ALL[seat])
+  }
+
+  fn seat(&mut self, seat: usize, user: User) {
+    self.seats[seat] = Some(user);
+    if self.seats.iter().all(Option::is_some) {
+      self.set_status(Status::Ongoing);
+    }
+  }
+
+  // The color the user plays, if any
+  pub fn seat_of(&self, user_id: &str) -> Option<Color> {
+    self
+      .seats
+      .iter()
+      .position(|seat| seat.as_ref().is_some_and(|user| user.id == user_id))
+      .map(|seat| Color::ALL[seat])
+  }
+
+  // Plays the move for color, if it's legal, and ends the game once no
+  // color can move
+  pub fn play(&mut self, color: Color, m: Move) -> Result<(), GameError> {
+    self.play_at(color, m, clock::now())
+  }
+
+  pub fn play_at(&mut self, color: Color, m: Move, now: u64) -> Result<(), GameError> {
+    if self.status != Status::Ongoing {
+      return Err(GameError::WrongStatus(self.status));
+    }
+    // engine passes don't say who passed, so the turn is checked here
+    if self.position.color_to_move() != Some(color) {
+      return Err(GameError::Illegal(IllegalMove::WrongTurn));
+    }
+    // the clock task passes for the color soon
+    if self.deadline().is_some_and(|deadline| now >= deadline) {
+      return Err(GameError::OutOfTime);
+    }
+    self.position.check(&m).map_err(GameError::Illegal)?;
+    self.position.play(m);
+    self.moves.push(Played {
+      color,
+      m,
+      at: now,
+      timeout: false,
+    });
+    if self.position.is_over() {
+      self.set_status(Status::Ended);
+    }
+    Ok(())
+  }
+
+  // Plays the move for the user, who has to play its color. Bots may play
+  // several colors of a game
+  pub fn play_as(&mut self, user_id: &str, color: Color, m: Move) -> Result<(), GameError> {
+    let plays = |seat: &Option<User>| seat.as_ref().is_some_and(|user| user.id == user_id);
+    // colors the variant doesn't have are no one's
+    if !self.seats.get(color as usize).is_some_and(plays) {
+      return Err(match self.seat_of(user_id) {
+        None => GameError::NotSeated,
+        Some(_) => GameError::NotYourColor,
+      });
+    }
+    self.play(color, m)
+  }
+
+  // The position after the first ply moves, if that many were played
+  pub fn position_at(&self, ply: usize) -> Option<Position> {
+    let mut position = Position::new(self.variant);
+    for played in self.moves.get(..ply)? {
+      position.play(played.m);
+    }
+    Some(position)
+  }
+
+  pub fn to_json(&self) -> game::Game {
+    let now = clock::now();
+    let players = self
+      .seats
+      .iter()
+      .zip(Color::ALL)
+      .filter_map(|(seat, color)| {
+        seat.as_ref().map(|user| game::GamePlayer {
+          color: color.into(),
+          score: self.position.score(color) as i8,
+          user: game::User {
+            id: user.id.clone(),
+            name: user.name.clone(),
+          },
+          bot: Difficulty::of(&user.id).map(|d| Box::new(d.into())),
+          forfeited: self.forfeited(color).then_some(Box::new(true)),
+          remainingMs: self
+            .remaining(color, now)
+            .map(|remaining| Box::new(remaining.as_millis().try_into().unwrap_or(u32::MAX))),
+          ratingChange: self.rating_changes[color as usize]
+            .map(|change| Box::new(change.to_json())),
+        })
+      })
+      .collect();
+    game::Game {
+      id: self.id.clone(),
+      moves: self
+        .moves
+        .iter()
+        .map(|played| board::to_move(played.color, &played.m))
+        .collect(),
+      players,
+      schemaVersion: SCHEMA_VERSION,
+      status: match self.status {
+        Status::WaitingForPlayers => game::GameStatus::WaitingForPlayers,
+        Status::Ongoing => game::GameStatus::Ongoing,
+        Status::Ended => game::GameStatus::Ended,
+      },
+      variant: self.variant.into(),
+      // the board is only shown once the game is under way
+      board: match self.status {
+        Status::WaitingForPlayers => None,
+        _ => Some(Box::new(self.position.board())),
+      },
+      clock: self.clock.map(|clock| Box::new(clock.into())),
+      inviteCode: None,
+    }
+  }
+}
+
+// Live update messages kept for a subscriber that is slow to read them
+const UPDATE_BACKLOG: usize = 64;
+
+// All the games of the server. Changes to a game are made one at a time,
+// so a game read from the store is never overwritten by a stale copy, and
+// are sent to the game's subscribers in the order they were made. Changes
+// to different games are made at once, except for rating the players of
+// games that end
+pub struct Games {
+  store: Arc<dyn Store>,
+  // held while creating games and bot accounts, which mustn't take the
+  // same invite code or account twice
+  creating: Mutex<()>,
+  // the locks of the games being changed, see GameLock
+  locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+  // held while rating the players of a game, who may be ending others
+  rating: Mutex<()>,
+  subscribers: Mutex<HashMap<String, broadcast::Sender<String>>>,
+  // where to send the ids of games a bot is to move in, see bots.rs
+  bot_turns: Mutex<Option<mpsc::UnboundedSender<String>>>,
+  pub deadlines: Deadlines,
+}
+
+impl Games {
+  pub fn new(store: Arc<dyn Store>) -> Self {
+    Games {
+      store,
+      creating: Mutex::new(()),
+      locks: Mutex::new(HashMap::new()),
+      rating: Mutex::new(()),
+      subscribers: Mutex::new(HashMap::new()),
+      bot_turns: Mutex::new(None),
+      deadlines: Deadlines::default(),
+    }
+  }
+
+  // The ids of games as a bot becomes due to move in them. Without a
+  // receiver, bots never move
+  pub fn bot_turns(&self) -> mpsc::UnboundedReceiver<String> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    *lock(&self.bot_turns) = Some(sender);
+    receiver
+  }
+
+  pub fn notify_bot(&self, game: &GameRecord) {
+    if game.bot_to_move().is_none() {
+      return;
+    }
+    if let Some(sender) = lock(&self.bot_turns).as_ref() {
+      // fails only when the bots have stopped
+      let _ = sender.send(game.id.clone());
+    }
+  }
+
+  // The live update messages of the game from now on, see live.rs
+  pub fn subscribe(&self, id: &str) -> broadcast::Receiver<String> {
+    let mut subscribers = lock(&self.subscribers);
+    subscribers
+      .entry(id.to_string())
+      .or_insert_with(|| broadcast::channel(UPDATE_BACKLOG).0)
+      .subscribe()
+  }
+
+  fn publish(&self, before: &GameRecord, after: &GameRecord) {
+    let mut subscribers = lock(&self.subscribers);
+    let Some(sender) = subscribers.get(&after.id) else {
+      return;
+    };
+    if sender.receiver_count() == 0 {
+      subscribers.remove(&after.id);
+      return;
+    }
+    for message in live::updates(before, after) {
+      // fails only when every subscriber has left since
+      let _ = sender.send(message);
+    }
+  }
+
+  pub fn create(&self, variant: VariantKind, setup: Setup) -> Result<GameRecord, GameError> {
+    let mut game = GameRecord::new(uuid::Uuid::new_v4().to_string(), variant);
+    for (seat, difficulty) in setup.bots.iter().enumerate().take(variant.colors()) {
+      if let Some(difficulty) = difficulty {
+        game.seat(seat, self.bot_user(*difficulty)?);
+      }
+    }
+    for user in setup.players {
+      game.join(user)?;
+    }
+    game.clock = setup.clock;
+    let _guard = lock(&self.creating);
+    if setup.private {
+      game.invite = Some(self.new_invite()?);
+    }
+    self.store.insert_game(&game)?;
+    self.deadlines.update(&game);
+    self.notify_bot(&game);
+    Ok(game)
+  }
+
+  // An invite code no game has. Codes leave out letters and digits that
+  // are easily mistaken for one another
+  fn new_invite(&self) -> Result<String, GameError> {
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    loop {
+      let mut rng = rand::thread_rng();
+      let code: String = (0..INVITE_LEN)
+        .map(|_| *ALPHABET.choose(&mut rng).unwrap() as char)
+        .collect();
+      if self.store.game_by_invite(&code)?.is_none() {
+        return Ok(code);
+      }
+    }
+  }
+
+  // The user of the bot, whose account is made the first time it plays
+  fn bot_user(&self, difficulty: Difficulty) -> Result<User, GameError> {
+    let _guard = lock(&self.creating);
+    let user = difficulty.user();
+    if self.store.account(&user.id)?.is_none() {
+      self.store.insert_account(&Account {
+        user: user.clone(),
+        password: None,
+      })?;
+    }
+    Ok(user)
+  }
+
+  pub fn get(&self, id: &str) -> Result<GameRecord, GameError> {
+    self.store.game(id)?.ok_or(GameError::NotFound)
+  }
+
+  pub fn list(&self) -> Result<Vec<GameRecord>, GameError> {
+    Ok(self.store.games()?)
+  }
+
+  // The games waiting for players that anyone may join
+  pub fn open(&self) -> Result<Vec<GameRecord>, GameError> {
+    Ok(self.store.open_games()?)
+  }
+
+  pub fn ongoing(&self) -> Result<Vec<GameRecord>, GameError> {
+    Ok(self.store.ongoing_games()?)
+  }
+
+  // Applies f to the game and saves the result, unless f fails
+  pub fn update<T>(
+    &self,
+    id: &str,
+    f: impl FnOnce(&mut GameRecord) -> Result<T, GameError>,
+  ) -> Result<(GameRecord, T), GameError> {
+    let game_lock = GameLock::new(self, id);
+    let _guard = lock(&game_lock.lock);
+    let before = self.get(id)?;
+    let mut game = before.clone();
+    let result = f(&mut game)?;
+    if before.status != Status::Ended && game.status == Status::Ended {
+      let _rating = lock(&self.rating);
+      let ratings = ratings::rate(self.store.as_ref(), &mut game)?;
+      self.store.end_game(&game, &ratings)?;
+    } else {
+      self.store.update_game(&game)?;
+    }
+    self.publish(&before, &game);
+    self.deadlines.update(&game);
+    self.notify_bot(&game);
+    Ok((game, result))
+  }
+
+  pub fn leaderboard(
+    &self,
+    variant: VariantKind,
+    limit: usize,
+  ) -> Result<Vec<(User, Rating)>, GameError> {
+    Ok(self.store.leaderboard(variant, limit)?)
+  }
+
+  // Private games can only be joined with their invite code
+  pub fn join(&self, id: &str, user: User) -> Result<GameRecord, GameError> {
+    let join = |game: &mut GameRecord| match game.invite {
+      Some(_) => Err(GameError::Private),
+      None => game.join(user),
+    };
+    Ok(self.update(id, join)?.0)
+  }
+
+  pub fn join_invite(&self, code: &str, user: User) -> Result<GameRecord, GameError> {
+    let game = self
+      .store
+      .game_by_invite(code)?
+      .ok_or(GameError::NotFound)?;
+    Ok(self.update(&game.id, |game| game.join(user))?.0)
+  }
+
+  pub fn play(&self, id: &str, user_id: &str, m: &game::Move) -> Result<GameRecord, GameError> {
+    let (color, m) = board::from_move(m);
+    self.play_as(id, user_id, color, m)
+  }
+
+  pub fn play_as(
+    &self,
+    id: &str,
+    user_id: &str,
+    color: Color,
+    m: Move,
+  ) -> Result<GameRecord, GameError> {
+    Ok(self.update(id, |game| game.play_as(user_id, color, m))?.0)
+  }
+}
+
+// Locks the mutex, even if a thread panicked holding it: what the games and
+// stores guard is left whole by every change made under them, a database
+// transaction rolling back when a panic drops it
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// The lock of a game, shared by those changing it, and dropped from the
+// locks of Games once none is
+struct GameLock<'a> {
+  games: &'a Games,
+  id: &'a str,
+  lock: Arc<Mutex<()>>,
+}
+
+impl<'a> GameLock<'a> {
+  fn new(games: &'a Games, id: &'a str) -> Self {
+    let lock = lock(&games.locks)
+      .entry(id.to_string())
+      .or_default()
+      .clone();
+    GameLock { games, id, lock }
+  }
+}
+
+impl Drop for GameLock<'_> {
+  fn drop(&mut self) {
+    let mut locks = lock(&self.games.locks);
+    // the map holds one reference and this another
+    if Arc::strong_count(&self.lock) == 2 {
+      locks.remove(self.id);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::position::play_out;
+
+  fn user(id: &str) -> User {
+    User {
+      id: id.to_string(),
+      name: format!("Player {id}"),
+    }
+  }
+
+  #[test]
+  fn test_join() {
+    let mut game = GameRecord::new("g".to_string(), VariantKind::Duo);
+    assert_eq!(game.join(user("a")).unwrap(), Color::Blue);
+    assert!(matches!(
+      game.join(user("a")),
+      Err(GameError::AlreadySeated)
+    ));
+    assert_eq!(game.status, Status::WaitingForPlayers);
+    assert!(game.to_json().board.is_none());
+
+    assert_eq!(game.join(user("b")).unwrap(), Color::Yellow);
+    assert_eq!(game.status, Status::Ongoing);
+    assert!(matches!(
+      game.join(user("c")),
+      Err(GameError::WrongStatus(Status::Ongoing))
+    ));
+    let json = serde_json::to_value(game.to_json()).unwrap();
+    assert_eq!(json["players"][1]["user"]["id"], "b");
+    assert_eq!(json["players"][1]["color"], "C2");
+    assert_eq!(json["board"]["color_to_move"], "C1");
+  }
+
+  fn place(color: Color, piece: blokus::Piece, row: u8, col: u8) -> Move {
+    Move::Place {
+      color,
+      piece,
+      orientation: 0,
+      row,
+      col,
+    }
+  }
+
+  #[test]
+  fn test_play() {
+    let mut game = GameRecord::new("g".to_string(), VariantKind::Duo);
+    game.join(user("a")).unwrap();
+    let m = place(Color::Blue, blokus::Piece::I1, 4, 4);
+    assert!(matches!(
+      game.play(Color::Blue, m),
+      Err(GameError::WrongStatus(Status::WaitingForPlayers))
+    ));
+    game.join(user("b")).unwrap();
+    assert!(matches!(
+      game.play_as("b", Color::Blue, m),
+      Err(GameError::NotYourColor)
+    ));
+    assert!(matches!(
+      game.play_as("c", Color::Blue, m),
+      Err(GameError::NotSeated)
+    ));
+    assert!(matches!(
+      game.play(Color::Yellow, Move::Pass),
+      Err(GameError::Illegal(IllegalMove::WrongTurn))
+    ));
+    assert!(matches!(
+      game.play(Color::Blue, Move::Pass),
+      Err(GameError::Illegal(IllegalMove::MustPlace))
+    ));
+    game.play_as("a", Color::Blue, m).unwrap();
+    assert_eq!(game.moves.len(), 1);
+    assert_eq!((game.moves[0].color, game.moves[0].m), (Color::Blue, m));
+    assert_eq!(game.position.color_to_move(), Some(Color::Yellow));
+
+    // play on until no color can move
+    play_out(&mut game.position.clone(), |_, color, m| {
+      game.play(color, m).unwrap()
+    });
+    assert_eq!(game.status, Status::Ended);
+    assert!(matches!(
+      game.play(Color::Blue, Move::Pass),
+      Err(GameError::WrongStatus(Status::Ended))
+    ));
+    let json = serde_json::to_value(game.to_json()).unwrap();
+    assert_eq!(json["status"], "ENDED");
+    assert_eq!(json["board"]["over"], true);
+    assert_eq!(json["moves"].as_array().unwrap().len(), game.moves.len());
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_no_going_back() {
+    let mut game = GameRecord::new("g".to_string(), VariantKind::Duo);
+    game.set_status(Status::Ended);
+  }
+
+  #[test]
+  fn test_panic_in_update() {
+    let games = Games::new(Arc::new(crate::store::MemoryStore::new()));
+    let id = games.create(VariantKind::Duo, Setup::default()).unwrap().id;
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+      games.update(&id, |_| -> Result<(), GameError> { panic!("a bug") })
+    }));
+    assert!(panicked.is_err());
+    // the game can still be changed, and its lock is gone once it isn't
+    assert!(games.join(&id, user("a")).is_ok());
+    assert!(lock(&games.locks).is_empty());
+  }
+}
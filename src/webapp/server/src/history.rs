@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{FromRef, Path, State},
+  http::{header::CONTENT_TYPE, StatusCode},
+  response::IntoResponse,
+  routing::get,
+  Json, Router,
+};
+use blokus::{Classic, Color, Duo, Record};
+use serde_json::{json, Value};
+
+use crate::{
+  api::ApiError,
+  board, game,
+  games::{GameRecord, Games, Status},
+  position::{Position, VariantKind},
+};
+
+impl GameRecord {
+  // The game in the record format of the engine, passes forced by clocks
+  // marked as timeouts
+  pub fn to_record(&self) -> Record {
+    let mut record = match self.variant {
+      VariantKind::Classic => Record::new::<Classic, 4>(),
+      VariantKind::Duo => Record::new::<Duo, 2>(),
+    };
+    record.players = self
+      .seats
+      .iter()
+      .zip(Color::ALL)
+      .filter_map(|(seat, color)| seat.as_ref().map(|user| (color, user.name.clone())))
+      .collect();
+    if self.status == Status::Ended {
+      record.scores = self.scores(&self.position);
+    }
+    record.moves = self.moves.iter().map(|played| played.m).collect();
+    record.timeouts = (0..self.moves.len())
+      .filter(|ix| self.moves[*ix].timeout)
+      .collect();
+    record
+  }
+
+  fn scores(&self, position: &Position) -> Vec<(Color, i32)> {
+    Color::ALL[..self.seats.len()]
+      .iter()
+      .map(|color| (*color, position.score(*color)))
+      .collect()
+  }
+
+  // Every move with when it was played and the scores it left
+  pub fn history(&self) -> Vec<Value> {
+    let mut position = Position::new(self.variant);
+    self
+      .moves
+      .iter()
+      .zip(1..)
+      .map(|(played, ply)| {
+        position.play(played.m);
+        let scores: Vec<Value> = self
+          .scores(&position)
+          .into_iter()
+          .map(|(color, score)| json!({ "color": game::Color::from(color), "score": score }))
+          .collect();
+        json!({
+          "ply": ply,
+          "move": board::to_move(played.color, &played.m),
+          // moves stored before times were kept have none
+          "played_at": (played.at > 0).then_some(played.at),
+          "timeout": played.timeout,
+          "scores": scores,
+        })
+      })
+      .collect()
+  }
+}
+
+pub fn no_such_ply(game: &GameRecord) -> ApiError {
+  ApiError {
+    status: StatusCode::NOT_FOUND,
+    code: "NO_SUCH_PLY",
+    message: format!("The game has {} moves", game.moves.len()),
+  }
+}
+
+pub fn router<S>() -> Router<S>
+where
+  Arc<Games>: FromRef<S>,
+  S: Send + Sync + Clone + 'static,
+{
+  Router::new()
+    .route("/games/:id/history", get(history))
+    .route("/games/:id/at/:ply", get(board_at))
+    .route("/games/:id/record", get(record))
+}
+
+// Times are in milliseconds since the Unix epoch
+async fn history(
+  State(games): State<Arc<Games>>,
+  Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+  let game = games.get(&id)?;
+  Ok(Json(json!({
+    "game_id": game.id,
+    "variant": game::GameVariant::from(game.variant),
+    "started_at": game.started_at,
+    "moves": game.history(),
+  })))
+}
+
+// The board after the first ply moves, 0 being the empty board
+async fn board_at(
+  State(games): State<Arc<Games>>,
+  Path((id, ply)): Path<(String, usize)>,
+) -> Result<Json<game::Board>, ApiError> {
+  let game = games.get(&id)?;
+  let position = game.position_at(ply).ok_or_else(|| no_such_ply(&game))?;
+  Ok(Json(position.board()))
+}
+
+async fn record(
+  State(games): State<Arc<Games>>,
+  Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+  let record = games.get(&id)?.to_record();
+  Ok((
+    [(CONTENT_TYPE, "text/plain; charset=utf-8")],
+    record.to_string(),
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::{
+    account::User,
+    clock::{Clock, OnTimeout, TimeControl},
+  };
+
+  #[test]
+  fn test_record() {
+    let mut game = GameRecord::new("g".to_string(), VariantKind::Duo);
+    for name in ["alice", "bob"] {
+      let user = User {
+        id: name.to_string(),
+        name: name.to_string(),
+      };
+      game.join(user).unwrap();
+    }
+    while game.status == Status::Ongoing {
+      let color = game.position.color_to_move().unwrap();
+      let m = *game.position.legal_moves().last().unwrap();
+      game.play(color, m).unwrap();
+    }
+    let record = game.to_record();
+    assert_eq!(
+      record.players,
+      [
+        (Color::Blue, "alice".to_string()),
+        (Color::Yellow, "bob".to_string())
+      ]
+    );
+    assert_eq!(record.scores.len(), 2);
+    // the engine reads it back and agrees on the scores
+    let parsed: Record = record.to_string().parse().unwrap();
+    assert_eq!(parsed, record);
+    let state = parsed.replay::<Duo, 2>().unwrap();
+    assert!(state.is_over());
+
+    let history = game.history();
+    assert_eq!(history.len(), game.moves.len());
+    let last = history.last().unwrap();
+    assert_eq!(last["ply"], game.moves.len());
+    assert_eq!(last["scores"][1]["score"], record.scores[1].1);
+    assert!(last["played_at"].as_u64().unwrap() > 0);
+  }
+
+  #[test]
+  fn test_record_with_timeouts() {
+    let mut game = GameRecord::new("g".to_string(), VariantKind::Duo);
+    game.clock = Some(Clock {
+      control: TimeControl::PerMove {
+        limit: Duration::from_secs(5),
+      },
+      on_timeout: OnTimeout::Pass,
+    });
+    for name in ["alice", "bob"] {
+      let user = User {
+        id: name.to_string(),
+        name: name.to_string(),
+      };
+      game.join(user).unwrap();
+    }
+    game.started_at = Some(0);
+    let m = game.position.legal_moves()[0];
+    game.play_at(Color::Blue, m, 1_000).unwrap();
+    // bob runs out of time with moves left, and alice plays on alone
+    assert!(game.time_out(6_000).unwrap());
+    while game.status == Status::Ongoing {
+      let m = game.position.legal_moves()[0];
+      game.play_at(Color::Blue, m, 7_000).unwrap();
+    }
+    assert_eq!(game.moves.iter().filter(|played| played.timeout).count(), 1);
+
+    let record = game.to_record();
+    assert_eq!(record.timeouts, [1]);
+    let parsed: Record = record.to_string().parse().unwrap();
+    assert_eq!(parsed, record);
+    let state = parsed.replay::<Duo, 2>().unwrap();
+    assert!(state.is_over());
+    assert_eq!(record.scores, game.scores(&game.position));
+  }
+}
@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{
+    ws::{Message, WebSocket, WebSocketUpgrade},
+    Path, State,
+  },
+  http::StatusCode,
+  response::Response,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+  account::User,
+  api::ApiError,
+  board, game,
+  games::{GameRecord, Games},
+};
+
+// Live updates of a game over a WebSocket.
+//
+// The server sends JSON messages with a "type":
+//   SNAPSHOT {game}: the whole game, sent first, and again if the client
+//     fell too far behind to be sent every update
+//   PLAYERS {game}: a player joined
+//   MOVE {move, game}: a move was played
+//   STATUS {status, game}: the game started or ended
+//   ERROR {error, message}: a move sent by this client was refused, with
+//     the same codes as the REST API
+// where game is the game after the change.
+//
+// Players connect with their session, and can send {"type": "MOVE",
+// "move": move} to play. Clients connecting without a session are
+// spectators, and can only watch
+
+// The messages for the changes from before to after
+pub fn updates(before: &GameRecord, after: &GameRecord) -> Vec<String> {
+  let game = serde_json::to_value(after.to_json()).unwrap();
+  let mut messages = vec![];
+  if before.seats != after.seats {
+    messages.push(json!({ "type": "PLAYERS", "game": game }));
+  }
+  for played in after.moves.iter().skip(before.moves.len()) {
+    messages.push(json!({
+      "type": "MOVE",
+      "move": board::to_move(played.color, &played.m),
+      "game": game,
+    }));
+  }
+  if before.status != after.status {
+    messages.push(json!({ "type": "STATUS", "status": game["status"], "game": game }));
+  }
+  messages.iter().map(Value::to_string).collect()
+}
+
+fn snapshot(game: &GameRecord) -> String {
+  json!({ "type": "SNAPSHOT", "game": game.to_json() }).to_string()
+}
+
+fn error(error: ApiError) -> String {
+  json!({ "type": "ERROR", "error": error.code, "message": error.message }).to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum ClientMessage {
+  #[serde(rename = "MOVE")]
+  Move {
+    #[serde(rename = "move")]
+    m: game::Move,
+  },
+}
+
+pub async fn game_socket(
+  ws: WebSocketUpgrade,
+  State(games): State<Arc<Games>>,
+  Path(id): Path<String>,
+  user: Option<User>,
+) -> Result<Response, ApiError> {
+  // unknown games are refused before upgrading
+  games.get(&id)?;
+  let user = user.map(|user| user.id);
+  Ok(ws.on_upgrade(move |socket| session(socket, games, id, user)))
+}
+
+async fn session(mut socket: WebSocket, games: Arc<Games>, id: String, user: Option<String>) {
+  // subscribing before taking the snapshot, so no update is missed
+  let mut updates = games.subscribe(&id);
+  let Ok(game) = games.get(&id) else {
+    return;
+  };
+  if socket.send(Message::Text(snapshot(&game))).await.is_err() {
+    return;
+  }
+  loop {
+    tokio::select! {
+      update = updates.recv() => {
+        let text = match update {
+          Ok(text) => text,
+          Err(RecvError::Lagged(_)) => match games.get(&id) {
+            Ok(game) => snapshot(&game),
+            Err(_) => break,
+          },
+          Err(RecvError::Closed) => break,
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+          break;
+        }
+      }
+      message = socket.recv() => {
+        let text = match message {
+          Some(Ok(Message::Text(text))) => text,
+          Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+          Some(Ok(_)) => continue,
+        };
+        // a successful move reaches this client as an update
+        if let Err(reply) = handle(&games, &id, user.as_deref(), &text) {
+          if socket.send(Message::Text(error(reply))).await.is_err() {
+            break;
+          }
+        }
+      }
+    }
+  }
+}
+
+fn handle(games: &Games, id: &str, user: Option<&str>, text: &str) -> Result<(), ApiError> {
+  let message: ClientMessage = serde_json::from_str(text).map_err(|e| ApiError {
+    status: StatusCode::BAD_REQUEST,
+    code: "BAD_REQUEST",
+    message: e.to_string(),
+  })?;
+  let Some(user) = user else {
+    return Err(ApiError {
+      status: StatusCode::FORBIDDEN,
+      code: "SPECTATOR",
+      message: "Spectators can't play moves".to_string(),
+    });
+  };
+  match message {
+    ClientMessage::Move { m } => games.play(id, user, &m)?,
+  };
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::{SocketAddr, TcpListener};
+
+  use futures::{SinkExt, StreamExt};
+  use tokio::net::TcpStream;
+  use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{self, client::IntoClientRequest},
+    MaybeTlsStream, WebSocketStream,
+  };
+
+  use super::*;
+  use crate::{
+    api::{router, AppState},
+    games::Setup,
+    position::VariantKind,
+    store::MemoryStore,
+  };
+
+  type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+  fn serve(state: AppState) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum::Server::from_tcp(listener)
+      .unwrap()
+      .serve(router(state).into_make_service());
+    tokio::spawn(server);
+    addr
+  }
+
+  // Connects with the session cookie, as browsers do, or as a spectator
+  async fn connect(addr: SocketAddr, id: &str, token: Option<&str>) -> Client {
+    let mut request = format!("ws://{addr}/games/{id}/ws")
+      .into_client_request()
+      .unwrap();
+    if let Some(token) = token {
+      let cookie = format!("session={token}").parse().unwrap();
+      request.headers_mut().insert("cookie", cookie);
+    }
+    connect_async(request).await.unwrap().0
+  }
+
+  async fn receive(client: &mut Client) -> Value {
+    loop {
+      match client.next().await.unwrap().unwrap() {
+        tungstenite::Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+        _ => continue,
+      }
+    }
+  }
+
+  async fn send(client: &mut Client, message: Value) {
+    let text = message.to_string();
+    client.send(tungstenite::Message::Text(text)).await.unwrap();
+  }
+
+  fn place(color: &str, row: u8, col: u8) -> Value {
+    json!({ "type": "MOVE", "move": {
+      "kind": "PLACE",
+      "color": color,
+      "piece": "I1",
+      "orientation": "R0",
+      "anchor": { "row": row, "col": col },
+    }})
+  }
+
+  #[tokio::test]
+  async fn test_live_game() {
+    let state = AppState::new(Arc::new(MemoryStore::new()));
+    let (games, accounts) = (state.games.clone(), state.accounts.clone());
+    let alice = accounts.register("alice", "password").await.unwrap();
+    let bob = accounts.register("bob", "password").await.unwrap();
+    let id = games.create(VariantKind::Duo, Setup::default()).unwrap().id;
+    let addr = serve(state);
+
+    let token = accounts.start_session(&alice).unwrap();
+    let mut a = connect(addr, &id, Some(&token)).await;
+    let message = receive(&mut a).await;
+    assert_eq!(message["type"], "SNAPSHOT");
+    assert_eq!(message["game"]["status"], "WAITING_FOR_PLAYERS");
+
+    games.join(&id, alice).unwrap();
+    assert_eq!(receive(&mut a).await["type"], "PLAYERS");
+    games.join(&id, bob.clone()).unwrap();
+    assert_eq!(receive(&mut a).await["type"], "PLAYERS");
+    let message = receive(&mut a).await;
+    assert_eq!(message["type"], "STATUS");
+    assert_eq!(message["status"], "ONGOING");
+
+    let token = accounts.start_session(&bob).unwrap();
+    let mut b = connect(addr, &id, Some(&token)).await;
+    let message = receive(&mut b).await;
+    assert_eq!(message["type"], "SNAPSHOT");
+    assert_eq!(message["game"]["status"], "ONGOING");
+    let mut spectator = connect(addr, &id, None).await;
+    assert_eq!(receive(&mut spectator).await["type"], "SNAPSHOT");
+
+    // a move sent by a player reaches everyone
+    send(&mut a, place("C1", 4, 4)).await;
+    for client in [&mut a, &mut b, &mut spectator] {
+      let message = receive(client).await;
+      assert_eq!(message["type"], "MOVE");
+      assert_eq!(message["move"]["anchor"]["row"], 4);
+      assert_eq!(message["game"]["board"]["table"][4][4], "C1");
+    }
+
+    // refused moves are only answered to their sender
+    send(&mut b, place("C1", 5, 5)).await;
+    let message = receive(&mut b).await;
+    assert_eq!(message["type"], "ERROR");
+    assert_eq!(message["error"], "NOT_YOUR_COLOR");
+    send(&mut spectator, place("C2", 9, 9)).await;
+    let message = receive(&mut spectator).await;
+    assert_eq!(message["error"], "SPECTATOR");
+    send(&mut b, json!({ "type": "RESIGN" })).await;
+    assert_eq!(receive(&mut b).await["error"], "BAD_REQUEST");
+
+    send(&mut b, place("C2", 9, 9)).await;
+    for client in [&mut a, &mut b, &mut spectator] {
+      let message = receive(client).await;
+      assert_eq!(message["type"], "MOVE");
+      assert_eq!(message["game"]["moves"].as_array().unwrap().len(), 2);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_unknown_game() {
+    let addr = serve(AppState::new(Arc::new(MemoryStore::new())));
+    assert!(connect_async(format!("ws://{addr}/games/nope/ws"))
+      .await
+      .is_err());
+  }
+}
@@ -0,0 +1,361 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use axum::{
+  extract::{rejection::JsonRejection, FromRef, Path, State},
+  http::StatusCode,
+  routing::{get, post},
+  Json, Router,
+};
+use blokus::Color;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+
+use crate::{
+  account::{Accounts, User},
+  api::ApiError,
+  bots::Difficulty,
+  game,
+  games::{GameError, GameRecord, Games, Setup},
+  position::VariantKind,
+};
+
+pub struct LobbyConfig {
+  // how long players wait in the queue before bots take the seats left,
+  // or None to wait for players only
+  pub bot_fill_after: Option<Duration>,
+  pub bot_difficulty: Difficulty,
+}
+
+impl Default for LobbyConfig {
+  fn default() -> Self {
+    LobbyConfig {
+      bot_fill_after: Some(Duration::from_secs(30)),
+      bot_difficulty: Difficulty::Medium,
+    }
+  }
+}
+
+struct Waiting {
+  user: User,
+  variant: VariantKind,
+  since: Instant,
+}
+
+// Where a player stands in quick matching
+#[derive(Debug, PartialEq)]
+pub enum Ticket {
+  NotQueued,
+  Queued {
+    variant: VariantKind,
+    waited: Duration,
+    // players in the queue for the variant, this one included
+    waiting: usize,
+  },
+  Matched {
+    game_id: String,
+  },
+}
+
+#[derive(Default)]
+struct Queue {
+  // oldest first
+  waiting: Vec<Waiting>,
+  // the game each matched player was put in, by user id
+  matched: HashMap<String, String>,
+}
+
+// Quick matching: players queue for a variant, and are put in a new game
+// together once there are enough of them for every color, or with bots
+// once the first of them has waited long enough
+pub struct Matchmaker {
+  games: Arc<Games>,
+  config: LobbyConfig,
+  queue: Mutex<Queue>,
+}
+
+impl Matchmaker {
+  pub fn new(games: Arc<Games>, config: LobbyConfig) -> Self {
+    Matchmaker {
+      games,
+      config,
+      queue: Mutex::new(Queue::default()),
+    }
+  }
+
+  // Queues the user for the variant, in place of any earlier queueing
+  pub fn enqueue(&self, user: User, variant: VariantKind) -> Result<Ticket, GameError> {
+    let user_id = user.id.clone();
+    {
+      let mut queue = self.queue.lock().unwrap();
+      queue.waiting.retain(|w| w.user.id != user.id);
+      queue.matched.remove(&user.id);
+      queue.waiting.push(Waiting {
+        user,
+        variant,
+        since: Instant::now(),
+      });
+    }
+    self.match_players(variant, false)?;
+    Ok(self.ticket(&user_id))
+  }
+
+  pub fn leave(&self, user_id: &str) {
+    let mut queue = self.queue.lock().unwrap();
+    queue.waiting.retain(|w| w.user.id != user_id);
+    queue.matched.remove(user_id);
+  }
+
+  pub fn ticket(&self, user_id: &str) -> Ticket {
+    let queue = self.queue.lock().unwrap();
+    if let Some(game_id) = queue.matched.get(user_id) {
+      return Ticket::Matched {
+        game_id: game_id.clone(),
+      };
+    }
+    let Some(entry) = queue.waiting.iter().find(|w| w.user.id == user_id) else {
+      return Ticket::NotQueued;
+    };
+    Ticket::Queued {
+      variant: entry.variant,
+      waited: entry.since.elapsed(),
+      waiting: queue
+        .waiting
+        .iter()
+        .filter(|w| w.variant == entry.variant)
+        .count(),
+    }
+  }
+
+  // Starts a game with the first players queued for the variant, if there
+  // are enough of them, or if fill and the first has waited long enough
+  fn match_players(&self, variant: VariantKind, fill: bool) -> Result<(), GameError> {
+    let mut queue = self.queue.lock().unwrap();
+    let colors = variant.colors();
+    let queued: Vec<usize> = (0..queue.waiting.len())
+      .filter(|ix| queue.waiting[*ix].variant == variant)
+      .take(colors)
+      .collect();
+    let due = match (fill, self.config.bot_fill_after, queued.first()) {
+      (true, Some(wait), Some(first)) => queue.waiting[*first].since.elapsed() >= wait,
+      _ => false,
+    };
+    if queued.len() < colors && !due {
+      return Ok(());
+    }
+    let players: Vec<User> = queued
+      .iter()
+      .map(|ix| queue.waiting[*ix].user.clone())
+      .collect();
+    // bots take the last colors
+    let bots = (0..colors)
+      .map(|seat| (seat >= players.len()).then_some(self.config.bot_difficulty))
+      .collect();
+    let setup = Setup {
+      bots,
+      players: players.clone(),
+      ..Setup::default()
+    };
+    let game = self.games.create(variant, setup)?;
+    queue
+      .waiting
+      .retain(|w| !players.iter().any(|user| user.id == w.user.id));
+    for user in players {
+      queue.matched.insert(user.id, game.id.clone());
+    }
+    Ok(())
+  }
+
+  // Fills the games of players who have waited long enough with bots
+  pub fn fill_with_bots(&self) -> Result<(), GameError> {
+    for variant in [VariantKind::Classic, VariantKind::Duo] {
+      self.match_players(variant, true)?;
+    }
+    Ok(())
+  }
+}
+
+// How often the queue is checked for players waiting long enough for bots
+const FILL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn spawn(matchmaker: Arc<Matchmaker>) -> JoinHandle<()> {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(FILL_INTERVAL);
+    loop {
+      interval.tick().await;
+      if let Err(error) = matchmaker.fill_with_bots() {
+        eprintln!("Queued players not matched with bots: {error:?}");
+      }
+    }
+  })
+}
+
+pub fn router<S>() -> Router<S>
+where
+  Arc<Accounts>: FromRef<S>,
+  Arc<Games>: FromRef<S>,
+  Arc<Matchmaker>: FromRef<S>,
+  S: Send + Sync + Clone + 'static,
+{
+  Router::new()
+    .route("/lobby", get(lobby))
+    .route("/matchmaking", get(ticket).post(enqueue).delete(leave))
+    .route("/invites/:code/join", post(join_invite))
+}
+
+// A game waiting for players, with the colors still free
+fn open_game(game: &GameRecord) -> Value {
+  let open_seats: Vec<game::Color> = game
+    .seats
+    .iter()
+    .zip(Color::ALL)
+    .filter(|(seat, _)| seat.is_none())
+    .map(|(_, color)| color.into())
+    .collect();
+  json!({ "game": game.to_json(), "open_seats": open_seats })
+}
+
+// The public games waiting for players, oldest first
+async fn lobby(State(games): State<Arc<Games>>) -> Result<Json<Vec<Value>>, ApiError> {
+  let open = games.open()?.iter().map(open_game).collect();
+  Ok(Json(open))
+}
+
+fn ticket_json(ticket: Ticket) -> Value {
+  match ticket {
+    Ticket::NotQueued => json!({ "status": "NOT_QUEUED" }),
+    Ticket::Queued {
+      variant,
+      waited,
+      waiting,
+    } => json!({
+      "status": "QUEUED",
+      "variant": game::GameVariant::from(variant),
+      "waited_seconds": waited.as_secs(),
+      "waiting": waiting,
+    }),
+    Ticket::Matched { game_id } => json!({ "status": "MATCHED", "game_id": game_id }),
+  }
+}
+
+#[derive(Deserialize)]
+pub struct Enqueue {
+  pub variant: game::GameVariant,
+}
+
+async fn enqueue(
+  State(matchmaker): State<Arc<Matchmaker>>,
+  user: User,
+  request: Result<Json<Enqueue>, JsonRejection>,
+) -> Result<Json<Value>, ApiError> {
+  let Json(request) = request?;
+  let ticket = matchmaker.enqueue(user, (&request.variant).into())?;
+  Ok(Json(ticket_json(ticket)))
+}
+
+async fn ticket(State(matchmaker): State<Arc<Matchmaker>>, user: User) -> Json<Value> {
+  Json(ticket_json(matchmaker.ticket(&user.id)))
+}
+
+async fn leave(State(matchmaker): State<Arc<Matchmaker>>, user: User) -> StatusCode {
+  matchmaker.leave(&user.id);
+  StatusCode::NO_CONTENT
+}
+
+async fn join_invite(
+  State(games): State<Arc<Games>>,
+  Path(code): Path<String>,
+  user: User,
+) -> Result<Json<game::Game>, ApiError> {
+  let code = code.to_ascii_uppercase();
+  Ok(Json(games.join_invite(&code, user)?.to_json()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{games::Status, store::MemoryStore};
+
+  fn user(id: &str) -> User {
+    User {
+      id: id.to_string(),
+      name: id.to_string(),
+    }
+  }
+
+  fn matchmaker(bot_fill_after: Option<Duration>) -> Matchmaker {
+    let games = Arc::new(Games::new(Arc::new(MemoryStore::new())));
+    let config = LobbyConfig {
+      bot_fill_after,
+      bot_difficulty: Difficulty::Easy,
+    };
+    Matchmaker::new(games, config)
+  }
+
+  #[test]
+  fn test_quick_match() {
+    let matchmaker = matchmaker(None);
+    let ticket = matchmaker.enqueue(user("a"), VariantKind::Classic).unwrap();
+    assert!(matches!(ticket, Ticket::Queued { waiting: 1, .. }));
+    matchmaker.enqueue(user("b"), VariantKind::Duo).unwrap();
+    matchmaker.enqueue(user("c"), VariantKind::Classic).unwrap();
+    // queueing again only moves the player to the back
+    matchmaker.enqueue(user("a"), VariantKind::Classic).unwrap();
+    matchmaker.enqueue(user("d"), VariantKind::Classic).unwrap();
+    assert!(matches!(
+      matchmaker.ticket("d"),
+      Ticket::Queued {
+        variant: VariantKind::Classic,
+        waiting: 3,
+        ..
+      }
+    ));
+    matchmaker.fill_with_bots().unwrap();
+    assert!(matches!(matchmaker.ticket("a"), Ticket::Queued { .. }));
+
+    let ticket = matchmaker.enqueue(user("e"), VariantKind::Classic).unwrap();
+    let Ticket::Matched { game_id } = ticket else {
+      panic!("{ticket:?}");
+    };
+    for id in ["a", "c", "d"] {
+      assert_eq!(
+        matchmaker.ticket(id),
+        Ticket::Matched {
+          game_id: game_id.clone()
+        }
+      );
+    }
+    let game = matchmaker.games.get(&game_id).unwrap();
+    assert_eq!(game.status, Status::Ongoing);
+    assert_eq!(game.seat_of("c"), Some(Color::Blue));
+    assert_eq!(game.seat_of("e"), Some(Color::Green));
+    assert!(matches!(matchmaker.ticket("b"), Ticket::Queued { .. }));
+
+    matchmaker.leave("b");
+    matchmaker.leave("c");
+    assert_eq!(matchmaker.ticket("b"), Ticket::NotQueued);
+    assert_eq!(matchmaker.ticket("c"), Ticket::NotQueued);
+  }
+
+  #[test]
+  fn test_bot_fill() {
+    let matchmaker = matchmaker(Some(Duration::ZERO));
+    matchmaker.enqueue(user("a"), VariantKind::Classic).unwrap();
+    matchmaker.enqueue(user("b"), VariantKind::Classic).unwrap();
+    matchmaker.fill_with_bots().unwrap();
+    let Ticket::Matched { game_id } = matchmaker.ticket("b") else {
+      panic!("not matched");
+    };
+    let game = matchmaker.games.get(&game_id).unwrap();
+    assert_eq!(game.status, Status::Ongoing);
+    assert_eq!(game.seat_of("a"), Some(Color::Blue));
+    assert_eq!(game.seat_of("b"), Some(Color::Yellow));
+    let bot = Difficulty::Easy.user();
+    assert_eq!(game.seats[2].as_ref(), Some(&bot));
+    assert_eq!(game.seats[3].as_ref(), Some(&bot));
+  }
+}
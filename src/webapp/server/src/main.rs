@@ -1,25 +1,166 @@
-mod game;
 mod account;
+mod analysis;
+mod api;
+mod board;
+mod bots;
+mod clock;
+mod config;
+// generated by jtd-codegen, which keeps the names of the schema
+#[allow(non_snake_case, clippy::box_collection)]
+mod game;
+mod games;
+mod history;
+mod live;
+mod lobby;
+mod position;
+mod ratings;
+mod store;
+
+use std::sync::Arc;
 
+use api::AppState;
 use axum::{
-  http::{StatusCode, Uri},
+  http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Method, StatusCode, Uri,
+  },
   response::IntoResponse,
-  routing::get,
   Router, Server,
 };
+use config::Config;
+use store::{SqliteStore, Store};
+use tower_http::{
+  cors::CorsLayer,
+  services::{ServeDir, ServeFile},
+};
 
 pub async fn fallback(uri: Uri) -> impl IntoResponse {
   (StatusCode::NOT_FOUND, format!("No route {}", uri))
 }
 
+// The API, with the UI build for whatever else is asked for, and index.html
+// for paths of the app itself
+fn app(config: &Config, state: AppState) -> Router {
+  let mut app = api::router(state);
+  app = match &config.static_dir {
+    Some(dir) if dir.is_dir() => {
+      let index = ServeFile::new(dir.join("index.html"));
+      app.fallback_service(ServeDir::new(dir).fallback(index))
+    }
+    Some(dir) => {
+      eprintln!("{}: no UI build, serving the API only", dir.display());
+      app.fallback(fallback)
+    }
+    None => app.fallback(fallback),
+  };
+  if !config.cors_origins.is_empty() {
+    let cors = CorsLayer::new()
+      .allow_origin(config.cors_origins.clone())
+      .allow_methods([Method::GET, Method::POST, Method::DELETE])
+      .allow_headers([AUTHORIZATION, CONTENT_TYPE])
+      .allow_credentials(true);
+    app = app.layer(cors);
+  }
+  app
+}
+
+// Prints why the server can't start, and exits
+fn fail(message: &str) -> ! {
+  eprintln!("{message}");
+  std::process::exit(1)
+}
+
+async fn shutdown_signal() {
+  let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    .expect("SIGTERM can't be handled");
+  tokio::select! {
+    _ = terminate.recv() => {}
+    _ = tokio::signal::ctrl_c() => {}
+  }
+}
+
 #[tokio::main]
 async fn main() {
-  let app = Router::new()
-    .fallback(fallback)
-    .route("/", get(|| async { "Hello axum!" }));
+  let config = Config::load().unwrap_or_else(|error| fail(&error));
+  let path = &config.storage;
+  let store =
+    SqliteStore::open(path).unwrap_or_else(|error| fail(&format!("{}: {error}", path.display())));
+  let store = Arc::new(store);
+  let state = AppState::new(store.clone());
+  let bots = bots::spawn(state.games.clone(), config.bots);
+  let clock = clock::spawn(state.games.clone());
+  let lobby = lobby::spawn(state.matchmaker.clone());
 
-  Server::bind(&"0.0.0.0:3000".parse().unwrap())
-    .serve(app.into_make_service())
+  Server::bind(&config.bind)
+    .serve(app(&config, state).into_make_service())
+    .with_graceful_shutdown(shutdown_signal())
     .await
     .unwrap();
+
+  // requests are done, so only bots still move games on
+  bots.finish().await;
+  clock.abort();
+  lobby.abort();
+  if let Err(error) = store.flush() {
+    eprintln!("Storage not flushed: {error:?}");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use axum::{body::Body, http::Request};
+  use tower::ServiceExt;
+
+  use super::*;
+  use crate::store::MemoryStore;
+
+  struct TempDir(PathBuf);
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.0);
+    }
+  }
+
+  async fn get(app: &Router, uri: &str) -> (StatusCode, String) {
+    let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+  }
+
+  #[tokio::test]
+  async fn test_static_files() {
+    let dir = TempDir(std::env::temp_dir().join(format!("blokus-{}", uuid::Uuid::new_v4())));
+    std::fs::create_dir_all(dir.0.join("assets")).unwrap();
+    std::fs::write(dir.0.join("index.html"), "<html>app</html>").unwrap();
+    std::fs::write(dir.0.join("assets/app.js"), "run()").unwrap();
+    let mut config = Config::from_sources(None, |_| None).unwrap();
+    config.static_dir = Some(dir.0.clone());
+    let router = app(&config, AppState::new(Arc::new(MemoryStore::new())));
+
+    assert_eq!(
+      get(&router, "/assets/app.js").await,
+      (StatusCode::OK, "run()".to_string())
+    );
+    // the app routes its own paths
+    for uri in ["/", "/play/abc", "/assets/missing.js"] {
+      assert_eq!(
+        get(&router, uri).await,
+        (StatusCode::OK, "<html>app</html>".to_string()),
+        "{uri}"
+      );
+    }
+    // the API still answers its routes
+    let (status, body) = get(&router, "/lobby").await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "[]"));
+
+    // with no build, only the API is served
+    config.static_dir = None;
+    let router = app(&config, AppState::new(Arc::new(MemoryStore::new())));
+    assert_eq!(get(&router, "/play/abc").await.0, StatusCode::NOT_FOUND);
+  }
 }
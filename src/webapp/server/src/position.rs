@@ -0,0 +1,135 @@
+use blokus::{Blokus, Classic, Color, Duo, IllegalMove, Move, Scoring, State, Variant};
+use rustyai::MaMdp;
+
+use crate::{board, game};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum VariantKind {
+  Classic,
+  Duo,
+}
+
+impl VariantKind {
+  pub fn colors(&self) -> usize {
+    match self {
+      VariantKind::Classic => 4,
+      VariantKind::Duo => 2,
+    }
+  }
+}
+
+impl From<&game::GameVariant> for VariantKind {
+  fn from(variant: &game::GameVariant) -> Self {
+    match variant {
+      game::GameVariant::Classic => VariantKind::Classic,
+      game::GameVariant::Duo => VariantKind::Duo,
+    }
+  }
+}
+
+impl From<VariantKind> for game::GameVariant {
+  fn from(variant: VariantKind) -> Self {
+    match variant {
+      VariantKind::Classic => game::GameVariant::Classic,
+      VariantKind::Duo => game::GameVariant::Duo,
+    }
+  }
+}
+
+// The engine state of a game, whatever its variant
+#[derive(Clone)]
+pub enum Position {
+  Classic(Box<State<4>>),
+  Duo(Box<State<2>>),
+}
+
+// Runs $body with $state bound to the engine state of the position
+macro_rules! with_state {
+  ($position:expr, $state:ident, $body:expr) => {
+    match $position {
+      Position::Classic($state) => $body,
+      Position::Duo($state) => $body,
+    }
+  };
+}
+
+fn play<V: Variant<N>, const N: usize>(state: &mut State<N>, m: Move) {
+  let color = state.color_to_move().expect("play on a finished game");
+  let mut joint_action = [Move::Pass; N];
+  joint_action[color as usize] = m;
+  Blokus::<V>::new(Scoring::Advanced).transition(state, &joint_action);
+}
+
+fn legal_moves<V: Variant<N>, const N: usize>(state: &State<N>) -> Vec<Move> {
+  match state.color_to_move() {
+    None => vec![],
+    Some(color) => Blokus::<V>::new(Scoring::Advanced).actions(state, color as usize),
+  }
+}
+
+impl Position {
+  pub fn new(variant: VariantKind) -> Self {
+    match variant {
+      VariantKind::Classic => Position::Classic(Box::new(State::new::<Classic>())),
+      VariantKind::Duo => Position::Duo(Box::new(State::new::<Duo>())),
+    }
+  }
+
+  // The position shown on a board of the variant
+  pub fn from_board(variant: VariantKind, board: &game::Board) -> Result<Self, String> {
+    Ok(match variant {
+      VariantKind::Classic => Position::Classic(Box::new(board::from_board::<Classic, 4>(board)?)),
+      VariantKind::Duo => Position::Duo(Box::new(board::from_board::<Duo, 2>(board)?)),
+    })
+  }
+
+  pub fn color_to_move(&self) -> Option<Color> {
+    with_state!(self, state, state.color_to_move())
+  }
+
+  pub fn is_over(&self) -> bool {
+    with_state!(self, state, state.is_over())
+  }
+
+  pub fn score(&self, color: Color) -> i32 {
+    with_state!(self, state, state.advanced_score(color))
+  }
+
+  pub fn board(&self) -> game::Board {
+    with_state!(self, state, board::to_board(state))
+  }
+
+  // The moves the color to move can play
+  pub fn legal_moves(&self) -> Vec<Move> {
+    match self {
+      Position::Classic(state) => legal_moves::<Classic, 4>(state),
+      Position::Duo(state) => legal_moves::<Duo, 2>(state),
+    }
+  }
+
+  pub fn check(&self, m: &Move) -> Result<(), IllegalMove> {
+    with_state!(self, state, state.check(m))
+  }
+
+  // Plays a move for the color to move, which has to be legal
+  pub fn play(&mut self, m: Move) {
+    match self {
+      Position::Classic(state) => play::<Classic, 4>(state, m),
+      Position::Duo(state) => play::<Duo, 2>(state, m),
+    }
+  }
+}
+
+// Plays position out, spreading the moves over the legal ones so tests play
+// the same game every run, and calls f with each position before its move
+#[cfg(test)]
+pub fn play_out(position: &mut Position, mut f: impl FnMut(&Position, Color, Move)) {
+  let mut ply = 0;
+  while let Some(color) = position.color_to_move() {
+    let moves = position.legal_moves();
+    let m = moves[(ply * 7919) % moves.len()];
+    f(position, color, m);
+    position.play(m);
+    ply += 1;
+  }
+}
@@ -0,0 +1,368 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{rejection::QueryRejection, FromRef, Query, State},
+  routing::get,
+  Json, Router,
+};
+use blokus::Color;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+  account::User,
+  api::ApiError,
+  bots::Difficulty,
+  game,
+  games::{GameRecord, Games},
+  position::VariantKind,
+  store::{Store, StoreError},
+};
+
+// Elo ratings, one per user and variant. A game of several colors counts
+// as a game between each pair of them, see deltas
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rating {
+  pub rating: f64,
+  // the rated games played
+  pub games: u32,
+}
+
+const INITIAL: f64 = 1500.0;
+// how far a single game moves a rating
+const K: f64 = 32.0;
+
+impl Default for Rating {
+  fn default() -> Self {
+    Rating {
+      rating: INITIAL,
+      games: 0,
+    }
+  }
+}
+
+// What a game did to the rating of a player
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RatingChange {
+  pub before: f64,
+  pub delta: f64,
+}
+
+impl RatingChange {
+  pub fn to_json(self) -> game::GamePlayerRatingChange {
+    game::GamePlayerRatingChange {
+      delta: self.delta.round() as i32,
+      rating: (self.before + self.delta).round() as i32,
+    }
+  }
+}
+
+impl Difficulty {
+  // Bots don't learn, so their ratings are fixed, and players are rated
+  // against them as if they had played them for long
+  pub fn rating(self) -> f64 {
+    match self {
+      Difficulty::Easy => 1100.0,
+      Difficulty::Medium => 1500.0,
+      Difficulty::Hard => 1900.0,
+    }
+  }
+}
+
+// The expected score of a player rated rating against one rated other
+fn expected(rating: f64, other: f64) -> f64 {
+  1.0 / (1.0 + 10f64.powf((other - rating) / 400.0))
+}
+
+// The changes of the ratings of players finishing in places, 0 being the
+// best. Each player gains or loses against every other as in a game of two,
+// and the sum is scaled so a game counts as one game whatever the players
+pub fn deltas(ratings: &[f64], places: &[usize]) -> Vec<f64> {
+  let opponents = ratings.len().saturating_sub(1).max(1) as f64;
+  (0..ratings.len())
+    .map(|i| {
+      let surprise: f64 = (0..ratings.len())
+        .filter(|j| *j != i)
+        .map(|j| {
+          let score = match places[i].cmp(&places[j]) {
+            std::cmp::Ordering::Less => 1.0,
+            std::cmp::Ordering::Equal => 0.5,
+            std::cmp::Ordering::Greater => 0.0,
+          };
+          score - expected(ratings[i], ratings[j])
+        })
+        .sum();
+      K * surprise / opponents
+    })
+    .collect()
+}
+
+impl GameRecord {
+  // Where each color finished, 0 being the best: colors that forfeited
+  // come last, then by score, with equal colors sharing their place
+  pub fn places(&self) -> Vec<usize> {
+    let key = |color: Color| (self.forfeited(color), -self.position.score(color));
+    let colors = &Color::ALL[..self.seats.len()];
+    colors
+      .iter()
+      .map(|color| colors.iter().filter(|c| key(**c) < key(*color)).count())
+      .collect()
+  }
+}
+
+// Rates the players of the game that just ended, giving it their rating
+// changes, and returns the new ratings of those who aren't bots
+pub fn rate(store: &dyn Store, game: &mut GameRecord) -> Result<Vec<(String, Rating)>, StoreError> {
+  let users: Vec<&User> = game.seats.iter().flatten().collect();
+  if users.len() < game.seats.len() {
+    return Ok(vec![]);
+  }
+  // the ratings of players, None for bots, and the ratings they play at
+  let mut ratings = vec![];
+  let mut current = vec![];
+  for user in &users {
+    match Difficulty::of(&user.id) {
+      Some(difficulty) => {
+        ratings.push(None);
+        current.push(difficulty.rating());
+      }
+      None => {
+        let rating = store.rating(&user.id, game.variant)?.unwrap_or_default();
+        ratings.push(Some(rating));
+        current.push(rating.rating);
+      }
+    }
+  }
+  let deltas = deltas(&current, &game.places());
+  let mut changes = vec![None; users.len()];
+  let mut updated = vec![];
+  for (seat, (rating, delta)) in ratings.into_iter().zip(deltas).enumerate() {
+    let Some(rating) = rating else {
+      continue;
+    };
+    changes[seat] = Some(RatingChange {
+      before: rating.rating,
+      delta,
+    });
+    let rating = Rating {
+      rating: rating.rating + delta,
+      games: rating.games + 1,
+    };
+    updated.push((users[seat].id.clone(), rating));
+  }
+  game.rating_changes = changes;
+  Ok(updated)
+}
+
+// The most players a leaderboard lists
+const MAX_LIMIT: usize = 100;
+
+pub fn router<S>() -> Router<S>
+where
+  Arc<Games>: FromRef<S>,
+  S: Send + Sync + Clone + 'static,
+{
+  Router::new().route("/leaderboard", get(leaderboard))
+}
+
+#[derive(Deserialize)]
+pub struct LeaderboardQuery {
+  pub variant: Option<game::GameVariant>,
+  pub limit: Option<usize>,
+}
+
+// The best rated players of a variant, classic unless asked otherwise, with
+// the bots among them to compare with. Guests aren't listed
+async fn leaderboard(
+  State(games): State<Arc<Games>>,
+  query: Result<Query<LeaderboardQuery>, QueryRejection>,
+) -> Result<Json<Vec<Value>>, ApiError> {
+  let Query(query) = query?;
+  let variant = query
+    .variant
+    .as_ref()
+    .map_or(VariantKind::Classic, VariantKind::from);
+  let limit = query.limit.unwrap_or(MAX_LIMIT).min(MAX_LIMIT);
+  let players = games.leaderboard(variant, limit)?;
+  let mut entries: Vec<(f64, Value)> = players
+    .into_iter()
+    .map(|(user, rating)| {
+      let entry = json!({
+        "user": game::User { id: user.id, name: user.name },
+        "rating": rating.rating.round() as i32,
+        "games": rating.games,
+      });
+      (rating.rating, entry)
+    })
+    .collect();
+  for difficulty in Difficulty::ALL {
+    let user = difficulty.user();
+    let entry = json!({
+      "user": game::User { id: user.id, name: user.name },
+      "rating": difficulty.rating().round() as i32,
+      "bot": game::Difficulty::from(difficulty),
+    });
+    entries.push((difficulty.rating(), entry));
+  }
+  entries.sort_by(|a, b| b.0.total_cmp(&a.0));
+  let board = entries
+    .into_iter()
+    .take(limit)
+    .zip(1..)
+    .map(|((_, mut entry), rank)| {
+      entry["rank"] = rank.into();
+      entry
+    })
+    .collect();
+  Ok(Json(board))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    account::Account,
+    games::{Played, Status},
+    store::MemoryStore,
+  };
+
+  #[test]
+  fn test_deltas() {
+    // two players as in plain Elo
+    let two = deltas(&[1500.0, 1500.0], &[0, 1]);
+    assert_eq!(two, vec![16.0, -16.0]);
+    let upset = deltas(&[1300.0, 1700.0], &[0, 1]);
+    assert!((upset[0] - 29.09).abs() < 0.01, "{upset:?}");
+    assert!((upset[0] + upset[1]).abs() < 1e-9);
+    // a draw between equals changes nothing
+    assert_eq!(deltas(&[1500.0, 1500.0], &[0, 0]), vec![0.0, 0.0]);
+
+    let four = deltas(&[1500.0; 4], &[1, 0, 3, 1]);
+    assert!((four[1] - 16.0).abs() < 1e-9);
+    assert!((four[2] + 16.0).abs() < 1e-9);
+    assert_eq!(four[0], four[3]);
+    assert!(four.iter().sum::<f64>().abs() < 1e-9);
+  }
+
+  fn user(id: &str) -> User {
+    User {
+      id: id.to_string(),
+      name: id.to_string(),
+    }
+  }
+
+  // A duo game in which blue placed a piece and yellow passed, which ends
+  // it with blue ahead
+  fn ended(seats: [User; 2]) -> GameRecord {
+    let mut game = GameRecord::new("g".to_string(), VariantKind::Duo);
+    for user in seats {
+      game.join(user).unwrap();
+    }
+    let m = game.position.legal_moves()[0];
+    game.play(Color::Blue, m).unwrap();
+    game.position.play(blokus::Move::Pass);
+    game.moves.push(Played {
+      color: Color::Yellow,
+      m: blokus::Move::Pass,
+      at: 0,
+      timeout: true,
+    });
+    game.end();
+    game
+  }
+
+  #[test]
+  fn test_rate() {
+    let store = MemoryStore::new();
+    let mut game = ended([user("a"), user("b")]);
+    assert_eq!(game.places(), vec![0, 1]);
+    let updated = rate(&store, &mut game).unwrap();
+    let expected = [
+      (
+        "a".to_string(),
+        Rating {
+          rating: 1516.0,
+          games: 1,
+        },
+      ),
+      (
+        "b".to_string(),
+        Rating {
+          rating: 1484.0,
+          games: 1,
+        },
+      ),
+    ];
+    assert_eq!(updated, expected);
+    assert_eq!(
+      game.rating_changes,
+      vec![
+        Some(RatingChange {
+          before: 1500.0,
+          delta: 16.0
+        }),
+        Some(RatingChange {
+          before: 1500.0,
+          delta: -16.0
+        }),
+      ]
+    );
+
+    // bots keep their rating, and beating a hard one gains more
+    let mut game = ended([user("a"), Difficulty::Hard.user()]);
+    let updated = rate(&store, &mut game).unwrap();
+    assert_eq!(updated.len(), 1);
+    assert!(updated[0].1.rating > 1500.0 + 16.0);
+    assert_eq!(game.rating_changes[1], None);
+    let json = serde_json::to_value(game.to_json()).unwrap();
+    assert_eq!(json["players"][0]["rating_change"]["rating"], 1529);
+    assert_eq!(json["players"][0]["rating_change"]["delta"], 29);
+    assert!(json["players"][1].get("rating_change").is_none());
+  }
+
+  #[test]
+  fn test_games_rate_once() {
+    let store = Arc::new(MemoryStore::new());
+    for (id, password) in [("a", Some("hash")), ("b", None)] {
+      let account = Account {
+        user: user(id),
+        password: password.map(str::to_string),
+      };
+      store.insert_account(&account).unwrap();
+    }
+    let games = Games::new(store);
+    let setup = crate::games::Setup {
+      players: vec![user("a"), user("b")],
+      ..Default::default()
+    };
+    let id = games.create(VariantKind::Duo, setup).unwrap().id;
+    games
+      .update(&id, |game| {
+        *game = GameRecord {
+          id: id.clone(),
+          ..ended([user("a"), user("b")])
+        };
+        Ok(())
+      })
+      .unwrap();
+    let game = games.get(&id).unwrap();
+    assert_eq!(game.status, Status::Ended);
+    assert!(game.rating_changes[0].is_some());
+    // b is a guest
+    let board = games.leaderboard(VariantKind::Duo, 10).unwrap();
+    assert_eq!(
+      board,
+      vec![(
+        user("a"),
+        Rating {
+          rating: 1516.0,
+          games: 1
+        }
+      )]
+    );
+    assert!(games
+      .leaderboard(VariantKind::Classic, 10)
+      .unwrap()
+      .is_empty());
+  }
+}
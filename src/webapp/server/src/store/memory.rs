@@ -0,0 +1,164 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use super::{Store, StoreError};
+use crate::{
+  account::{Account, User},
+  games::{lock, GameRecord, Status},
+  position::VariantKind,
+  ratings::Rating,
+};
+
+// Keeps everything for as long as the server runs
+#[derive(Default)]
+pub struct MemoryStore {
+  games: Mutex<Vec<GameRecord>>,
+  accounts: Mutex<Vec<Account>>,
+  sessions: Mutex<HashMap<String, String>>,
+  ratings: Mutex<HashMap<(String, VariantKind), Rating>>,
+}
+
+impl MemoryStore {
+  pub fn new() -> Self {
+    MemoryStore::default()
+  }
+}
+
+impl Store for MemoryStore {
+  fn insert_game(&self, game: &GameRecord) -> Result<(), StoreError> {
+    let mut games = lock(&self.games);
+    if games.iter().any(|g| g.id == game.id) {
+      return Err(StoreError(format!("Game {} exists already", game.id)));
+    }
+    games.push(game.clone());
+    Ok(())
+  }
+
+  fn update_game(&self, game: &GameRecord) -> Result<(), StoreError> {
+    let mut games = lock(&self.games);
+    let stored = games
+      .iter_mut()
+      .find(|g| g.id == game.id)
+      .ok_or_else(|| StoreError(format!("No game {}", game.id)))?;
+    *stored = game.clone();
+    Ok(())
+  }
+
+  fn game(&self, id: &str) -> Result<Option<GameRecord>, StoreError> {
+    let games = lock(&self.games);
+    Ok(games.iter().find(|g| g.id == id).cloned())
+  }
+
+  fn game_by_invite(&self, code: &str) -> Result<Option<GameRecord>, StoreError> {
+    let games = lock(&self.games);
+    Ok(
+      games
+        .iter()
+        .find(|g| g.invite.as_deref() == Some(code))
+        .cloned(),
+    )
+  }
+
+  fn games(&self) -> Result<Vec<GameRecord>, StoreError> {
+    Ok(lock(&self.games).clone())
+  }
+
+  fn open_games(&self) -> Result<Vec<GameRecord>, StoreError> {
+    let games = lock(&self.games);
+    Ok(
+      games
+        .iter()
+        .filter(|g| g.status == Status::WaitingForPlayers && g.invite.is_none())
+        .cloned()
+        .collect(),
+    )
+  }
+
+  fn ongoing_games(&self) -> Result<Vec<GameRecord>, StoreError> {
+    let games = lock(&self.games);
+    Ok(
+      games
+        .iter()
+        .filter(|g| g.status == Status::Ongoing)
+        .cloned()
+        .collect(),
+    )
+  }
+
+  fn end_game(&self, game: &GameRecord, ratings: &[(String, Rating)]) -> Result<(), StoreError> {
+    self.update_game(game)?;
+    let mut stored = lock(&self.ratings);
+    for (user_id, rating) in ratings {
+      stored.insert((user_id.clone(), game.variant), *rating);
+    }
+    Ok(())
+  }
+
+  fn rating(&self, user_id: &str, variant: VariantKind) -> Result<Option<Rating>, StoreError> {
+    let ratings = lock(&self.ratings);
+    Ok(ratings.get(&(user_id.to_string(), variant)).copied())
+  }
+
+  fn leaderboard(
+    &self,
+    variant: VariantKind,
+    limit: usize,
+  ) -> Result<Vec<(User, Rating)>, StoreError> {
+    let ratings = lock(&self.ratings);
+    let accounts = lock(&self.accounts);
+    let mut board: Vec<(User, Rating)> = accounts
+      .iter()
+      .filter(|account| account.password.is_some())
+      .filter_map(|account| {
+        let rating = ratings.get(&(account.user.id.clone(), variant))?;
+        Some((account.user.clone(), *rating))
+      })
+      .collect();
+    board.sort_by(|a, b| b.1.rating.total_cmp(&a.1.rating));
+    board.truncate(limit);
+    Ok(board)
+  }
+
+  fn insert_account(&self, account: &Account) -> Result<(), StoreError> {
+    let mut accounts = lock(&self.accounts);
+    let clash = accounts
+      .iter()
+      .any(|a| a.user.id == account.user.id || a.user.name == account.user.name);
+    if clash {
+      return Err(StoreError(format!(
+        "Account {} exists already",
+        account.user.name
+      )));
+    }
+    accounts.push(account.clone());
+    Ok(())
+  }
+
+  fn account(&self, id: &str) -> Result<Option<Account>, StoreError> {
+    let accounts = lock(&self.accounts);
+    Ok(accounts.iter().find(|a| a.user.id == id).cloned())
+  }
+
+  fn account_by_name(&self, name: &str) -> Result<Option<Account>, StoreError> {
+    let accounts = lock(&self.accounts);
+    Ok(accounts.iter().find(|a| a.user.name == name).cloned())
+  }
+
+  fn insert_session(&self, key: &str, user_id: &str) -> Result<(), StoreError> {
+    let mut sessions = lock(&self.sessions);
+    sessions.insert(key.to_string(), user_id.to_string());
+    Ok(())
+  }
+
+  fn session(&self, key: &str) -> Result<Option<String>, StoreError> {
+    Ok(lock(&self.sessions).get(key).cloned())
+  }
+
+  fn delete_session(&self, key: &str) -> Result<(), StoreError> {
+    lock(&self.sessions).remove(key);
+    Ok(())
+  }
+
+  fn flush(&self) -> Result<(), StoreError> {
+    Ok(())
+  }
+}
@@ -0,0 +1,65 @@
+use std::fmt::Display;
+
+use crate::{
+  account::{Account, User},
+  games::GameRecord,
+  position::VariantKind,
+  ratings::Rating,
+};
+
+// the server keeps everything in SQLite, and tests in memory
+#[cfg(test)]
+mod memory;
+mod sqlite;
+#[cfg(test)]
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+#[derive(Debug)]
+pub struct StoreError(pub String);
+
+impl Display for StoreError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Storage failed: {}", self.0)
+  }
+}
+
+// Where games, accounts and sessions are kept between requests
+pub trait Store: Send + Sync {
+  fn insert_game(&self, game: &GameRecord) -> Result<(), StoreError>;
+  // Replaces the stored game with the same id
+  fn update_game(&self, game: &GameRecord) -> Result<(), StoreError>;
+  fn game(&self, id: &str) -> Result<Option<GameRecord>, StoreError>;
+  fn game_by_invite(&self, code: &str) -> Result<Option<GameRecord>, StoreError>;
+  // Every game, oldest first
+  fn games(&self) -> Result<Vec<GameRecord>, StoreError>;
+  // The games waiting for players that anyone may join, oldest first
+  fn open_games(&self) -> Result<Vec<GameRecord>, StoreError>;
+  // The games being played, oldest first
+  fn ongoing_games(&self) -> Result<Vec<GameRecord>, StoreError>;
+  // Replaces the stored game, which just ended, along with the ratings of
+  // its players after it, by user id
+  fn end_game(&self, game: &GameRecord, ratings: &[(String, Rating)]) -> Result<(), StoreError>;
+
+  // Ratings are kept per user and variant
+  fn rating(&self, user_id: &str, variant: VariantKind) -> Result<Option<Rating>, StoreError>;
+  // The best rated users of the variant who aren't guests, best first
+  fn leaderboard(
+    &self,
+    variant: VariantKind,
+    limit: usize,
+  ) -> Result<Vec<(User, Rating)>, StoreError>;
+
+  // Fails if an account has the same id or name
+  fn insert_account(&self, account: &Account) -> Result<(), StoreError>;
+  fn account(&self, id: &str) -> Result<Option<Account>, StoreError>;
+  fn account_by_name(&self, name: &str) -> Result<Option<Account>, StoreError>;
+  // Sessions are stored under a key derived from their token, and give the
+  // id of their user
+  fn insert_session(&self, key: &str, user_id: &str) -> Result<(), StoreError>;
+  fn session(&self, key: &str) -> Result<Option<String>, StoreError>;
+  fn delete_session(&self, key: &str) -> Result<(), StoreError>;
+
+  // Writes out whatever is still buffered, before the server stops
+  fn flush(&self) -> Result<(), StoreError>;
+}
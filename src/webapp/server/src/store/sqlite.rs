@@ -0,0 +1,673 @@
+use std::{path::Path, sync::Mutex};
+
+use blokus::{Color, Move};
+use rusqlite::{params, Connection, OptionalExtension, Params, Transaction};
+
+use super::{Store, StoreError};
+use crate::{
+  account::{Account, User},
+  clock::Clock,
+  game,
+  games::{lock, GameRecord, Played, Status},
+  position::VariantKind,
+  ratings::{Rating, RatingChange},
+};
+
+// The schema, one step per version. The version of a database is kept in
+// its user_version, and opening it applies the steps it hasn't seen yet, so
+// steps are only ever added at the end
+const MIGRATIONS: &[&str] = &[
+  "
+  CREATE TABLE users (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    password TEXT
+  );
+  CREATE TABLE sessions (
+    key TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id)
+  );
+  CREATE TABLE games (
+    id TEXT PRIMARY KEY,
+    variant TEXT NOT NULL,
+    status TEXT NOT NULL
+  );
+  CREATE TABLE seats (
+    game_id TEXT NOT NULL REFERENCES games(id),
+    seat INTEGER NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users(id),
+    PRIMARY KEY (game_id, seat)
+  );
+  -- moves in blokus notation, with the color that played them, as passes
+  -- don't say
+  CREATE TABLE moves (
+    game_id TEXT NOT NULL REFERENCES games(id),
+    ply INTEGER NOT NULL,
+    color INTEGER NOT NULL,
+    move TEXT NOT NULL,
+    PRIMARY KEY (game_id, ply)
+  );
+",
+  "
+  ALTER TABLE games ADD COLUMN invite TEXT;
+  CREATE UNIQUE INDEX games_invite ON games(invite);
+",
+  // clocks in the JSON of the API, and times in milliseconds since the
+  // Unix epoch. Moves stored before have no time
+  "
+  ALTER TABLE games ADD COLUMN clock TEXT;
+  ALTER TABLE games ADD COLUMN started_at INTEGER;
+  ALTER TABLE moves ADD COLUMN played_at INTEGER NOT NULL DEFAULT 0;
+  ALTER TABLE moves ADD COLUMN timeout INTEGER NOT NULL DEFAULT 0;
+",
+  "
+  CREATE TABLE ratings (
+    user_id TEXT NOT NULL REFERENCES users(id),
+    variant TEXT NOT NULL,
+    rating REAL NOT NULL,
+    games INTEGER NOT NULL,
+    PRIMARY KEY (user_id, variant)
+  );
+  -- what the game did to the rating of the player of the seat, once it
+  -- ended
+  ALTER TABLE seats ADD COLUMN rating REAL;
+  ALTER TABLE seats ADD COLUMN rating_delta REAL;
+",
+  // the lobby and the startup of clocks and bots only look at games of a
+  // status
+  "
+  CREATE INDEX games_status ON games(status);
+",
+];
+
+impl From<rusqlite::Error> for StoreError {
+  fn from(error: rusqlite::Error) -> Self {
+    StoreError(error.to_string())
+  }
+}
+
+fn variant_name(variant: VariantKind) -> &'static str {
+  match variant {
+    VariantKind::Classic => "CLASSIC",
+    VariantKind::Duo => "DUO",
+  }
+}
+
+fn parse_variant(name: &str) -> Result<VariantKind, StoreError> {
+  match name {
+    "CLASSIC" => Ok(VariantKind::Classic),
+    "DUO" => Ok(VariantKind::Duo),
+    _ => Err(StoreError(format!("Unknown variant {name:?}"))),
+  }
+}
+
+fn status_name(status: Status) -> &'static str {
+  match status {
+    Status::WaitingForPlayers => "WAITING_FOR_PLAYERS",
+    Status::Ongoing => "ONGOING",
+    Status::Ended => "ENDED",
+  }
+}
+
+fn parse_status(name: &str) -> Result<Status, StoreError> {
+  match name {
+    "WAITING_FOR_PLAYERS" => Ok(Status::WaitingForPlayers),
+    "ONGOING" => Ok(Status::Ongoing),
+    "ENDED" => Ok(Status::Ended),
+    _ => Err(StoreError(format!("Unknown status {name:?}"))),
+  }
+}
+
+// Keeps everything in a SQLite file, so it outlives the server
+pub struct SqliteStore {
+  connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+  pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+    SqliteStore::new(Connection::open(path)?)
+  }
+
+  fn new(mut connection: Connection) -> Result<Self, StoreError> {
+    connection.pragma_update(None, "foreign_keys", true)?;
+    migrate(&mut connection)?;
+    Ok(SqliteStore {
+      connection: Mutex::new(connection),
+    })
+  }
+}
+
+fn migrate(connection: &mut Connection) -> Result<(), StoreError> {
+  let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+  if version > MIGRATIONS.len() {
+    return Err(StoreError(format!(
+      "The database is at version {version}, newer than this server"
+    )));
+  }
+  for (ix, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(migration)?;
+    transaction.pragma_update(None, "user_version", ix + 1)?;
+    transaction.commit()?;
+  }
+  Ok(())
+}
+
+// Writes the seats and the moves not stored yet, moves never being taken back
+fn save_game(transaction: &Transaction, game: &GameRecord) -> Result<(), StoreError> {
+  transaction.execute("DELETE FROM seats WHERE game_id = ?1", [&game.id])?;
+  for (seat, user) in game.seats.iter().enumerate() {
+    if let Some(user) = user {
+      let change = game.rating_changes[seat];
+      transaction.execute(
+        "INSERT INTO seats (game_id, seat, user_id, rating, rating_delta)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+          game.id,
+          seat,
+          user.id,
+          change.map(|change| change.before),
+          change.map(|change| change.delta)
+        ],
+      )?;
+    }
+  }
+  let stored: usize = transaction.query_row(
+    "SELECT COUNT(*) FROM moves WHERE game_id = ?1",
+    [&game.id],
+    |row| row.get(0),
+  )?;
+  for (ply, played) in game.moves.iter().enumerate().skip(stored) {
+    transaction.execute(
+      "INSERT INTO moves (game_id, ply, color, move, played_at, timeout)
+       VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+      params![
+        game.id,
+        ply,
+        played.color as usize,
+        played.m.to_string(),
+        played.at,
+        played.timeout
+      ],
+    )?;
+  }
+  Ok(())
+}
+
+fn update_game(transaction: &Transaction, game: &GameRecord) -> Result<(), StoreError> {
+  let updated = transaction.execute(
+    "UPDATE games SET status = ?2, started_at = ?3 WHERE id = ?1",
+    params![game.id, status_name(game.status), game.started_at],
+  )?;
+  if updated == 0 {
+    return Err(StoreError(format!("No game {}", game.id)));
+  }
+  save_game(transaction, game)
+}
+
+// The games matching condition, which follows FROM in the query
+fn query_games(
+  connection: &Connection,
+  condition: &str,
+  params: impl Params,
+) -> Result<Vec<GameRecord>, StoreError> {
+  let query =
+    format!("SELECT id, variant, status, invite, clock, started_at FROM games {condition}");
+  let mut statement = connection.prepare(&query)?;
+  let mut rows = statement.query(params)?;
+  let mut games = vec![];
+  while let Some(row) = rows.next()? {
+    games.push(load_game(connection, row)?);
+  }
+  Ok(games)
+}
+
+fn load_game(connection: &Connection, row: &rusqlite::Row) -> Result<GameRecord, StoreError> {
+  let id: String = row.get(0)?;
+  let variant = parse_variant(&row.get::<_, String>(1)?)?;
+  let status = parse_status(&row.get::<_, String>(2)?)?;
+  let invite = row.get(3)?;
+  let clock = row
+    .get::<_, Option<String>>(4)?
+    .map(|clock| parse_clock(&id, &clock))
+    .transpose()?;
+  let started_at = row.get(5)?;
+  let mut seats = vec![None; variant.colors()];
+  let mut rating_changes = vec![None; variant.colors()];
+  let mut statement = connection.prepare(
+    "SELECT seat, users.id, users.name, rating, rating_delta
+     FROM seats JOIN users ON users.id = seats.user_id WHERE game_id = ?1",
+  )?;
+  let mut rows = statement.query([&id])?;
+  while let Some(row) = rows.next()? {
+    let seat: usize = row.get(0)?;
+    let user = User {
+      id: row.get(1)?,
+      name: row.get(2)?,
+    };
+    *seats
+      .get_mut(seat)
+      .ok_or_else(|| StoreError(format!("Game {id} has no seat {seat}")))? = Some(user);
+    if let (Some(before), Some(delta)) = (row.get(3)?, row.get(4)?) {
+      rating_changes[seat] = Some(RatingChange { before, delta });
+    }
+  }
+
+  let mut moves = vec![];
+  let mut statement = connection
+    .prepare("SELECT color, move, played_at, timeout FROM moves WHERE game_id = ?1 ORDER BY ply")?;
+  let mut rows = statement.query([&id])?;
+  while let Some(row) = rows.next()? {
+    let color: usize = row.get(0)?;
+    let m: String = row.get(1)?;
+    let color = *Color::ALL
+      .get(color)
+      .ok_or_else(|| StoreError(format!("Game {id} has a move for color {color}")))?;
+    let m: Move = m.parse().map_err(StoreError)?;
+    moves.push(Played {
+      color,
+      m,
+      at: row.get(2)?,
+      timeout: row.get(3)?,
+    });
+  }
+
+  let mut game = GameRecord::replay(id.clone(), variant, status, seats, moves)
+    .map_err(|error| StoreError(format!("Game {id} doesn't replay: {error:?}")))?;
+  game.invite = invite;
+  game.clock = clock;
+  game.started_at = started_at;
+  game.rating_changes = rating_changes;
+  Ok(game)
+}
+
+fn clock_json(clock: Option<Clock>) -> Option<String> {
+  clock.map(|clock| serde_json::to_string(&game::GameClock::from(clock)).unwrap())
+}
+
+fn parse_clock(id: &str, json: &str) -> Result<Clock, StoreError> {
+  let clock: game::GameClock = serde_json::from_str(json)
+    .map_err(|error| StoreError(format!("Game {id} has a bad clock: {error}")))?;
+  Ok((&clock).into())
+}
+
+fn account(row: &rusqlite::Row) -> rusqlite::Result<Account> {
+  Ok(Account {
+    user: User {
+      id: row.get(0)?,
+      name: row.get(1)?,
+    },
+    password: row.get(2)?,
+  })
+}
+
+impl Store for SqliteStore {
+  fn insert_game(&self, game: &GameRecord) -> Result<(), StoreError> {
+    let mut connection = lock(&self.connection);
+    let transaction = connection.transaction()?;
+    transaction.execute(
+      "INSERT INTO games (id, variant, status, invite, clock, started_at)
+       VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+      params![
+        game.id,
+        variant_name(game.variant),
+        status_name(game.status),
+        game.invite,
+        clock_json(game.clock),
+        game.started_at
+      ],
+    )?;
+    save_game(&transaction, game)?;
+    Ok(transaction.commit()?)
+  }
+
+  fn update_game(&self, game: &GameRecord) -> Result<(), StoreError> {
+    let mut connection = lock(&self.connection);
+    let transaction = connection.transaction()?;
+    update_game(&transaction, game)?;
+    Ok(transaction.commit()?)
+  }
+
+  fn game(&self, id: &str) -> Result<Option<GameRecord>, StoreError> {
+    let connection = lock(&self.connection);
+    Ok(query_games(&connection, "WHERE id = ?1", [id])?.pop())
+  }
+
+  fn game_by_invite(&self, code: &str) -> Result<Option<GameRecord>, StoreError> {
+    let connection = lock(&self.connection);
+    Ok(query_games(&connection, "WHERE invite = ?1", [code])?.pop())
+  }
+
+  fn games(&self) -> Result<Vec<GameRecord>, StoreError> {
+    let connection = lock(&self.connection);
+    query_games(&connection, "ORDER BY rowid", [])
+  }
+
+  fn open_games(&self) -> Result<Vec<GameRecord>, StoreError> {
+    let connection = lock(&self.connection);
+    query_games(
+      &connection,
+      "WHERE status = ?1 AND invite IS NULL ORDER BY rowid",
+      [status_name(Status::WaitingForPlayers)],
+    )
+  }
+
+  fn ongoing_games(&self) -> Result<Vec<GameRecord>, StoreError> {
+    let connection = lock(&self.connection);
+    query_games(
+      &connection,
+      "WHERE status = ?1 ORDER BY rowid",
+      [status_name(Status::Ongoing)],
+    )
+  }
+
+  fn end_game(&self, game: &GameRecord, ratings: &[(String, Rating)]) -> Result<(), StoreError> {
+    let mut connection = lock(&self.connection);
+    let transaction = connection.transaction()?;
+    update_game(&transaction, game)?;
+    for (user_id, rating) in ratings {
+      transaction.execute(
+        "INSERT OR REPLACE INTO ratings (user_id, variant, rating, games)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+          user_id,
+          variant_name(game.variant),
+          rating.rating,
+          rating.games
+        ],
+      )?;
+    }
+    Ok(transaction.commit()?)
+  }
+
+  fn rating(&self, user_id: &str, variant: VariantKind) -> Result<Option<Rating>, StoreError> {
+    let connection = lock(&self.connection);
+    let rating = connection
+      .query_row(
+        "SELECT rating, games FROM ratings WHERE user_id = ?1 AND variant = ?2",
+        params![user_id, variant_name(variant)],
+        |row| {
+          Ok(Rating {
+            rating: row.get(0)?,
+            games: row.get(1)?,
+          })
+        },
+      )
+      .optional()?;
+    Ok(rating)
+  }
+
+  fn leaderboard(
+    &self,
+    variant: VariantKind,
+    limit: usize,
+  ) -> Result<Vec<(User, Rating)>, StoreError> {
+    let connection = lock(&self.connection);
+    let mut statement = connection.prepare(
+      "SELECT users.id, users.name, rating, games FROM ratings JOIN users ON users.id = user_id
+       WHERE variant = ?1 AND password IS NOT NULL ORDER BY rating DESC LIMIT ?2",
+    )?;
+    let rows = statement.query_map(params![variant_name(variant), limit], |row| {
+      let user = User {
+        id: row.get(0)?,
+        name: row.get(1)?,
+      };
+      let rating = Rating {
+        rating: row.get(2)?,
+        games: row.get(3)?,
+      };
+      Ok((user, rating))
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+  }
+
+  fn insert_account(&self, account: &Account) -> Result<(), StoreError> {
+    let connection = lock(&self.connection);
+    connection.execute(
+      "INSERT INTO users (id, name, password) VALUES (?1, ?2, ?3)",
+      params![account.user.id, account.user.name, account.password],
+    )?;
+    Ok(())
+  }
+
+  fn account(&self, id: &str) -> Result<Option<Account>, StoreError> {
+    let connection = lock(&self.connection);
+    Ok(
+      connection
+        .query_row(
+          "SELECT id, name, password FROM users WHERE id = ?1",
+          [id],
+          account,
+        )
+        .optional()?,
+    )
+  }
+
+  fn account_by_name(&self, name: &str) -> Result<Option<Account>, StoreError> {
+    let connection = lock(&self.connection);
+    Ok(
+      connection
+        .query_row(
+          "SELECT id, name, password FROM users WHERE name = ?1",
+          [name],
+          account,
+        )
+        .optional()?,
+    )
+  }
+
+  fn insert_session(&self, key: &str, user_id: &str) -> Result<(), StoreError> {
+    let connection = lock(&self.connection);
+    connection.execute(
+      "INSERT OR REPLACE INTO sessions (key, user_id) VALUES (?1, ?2)",
+      params![key, user_id],
+    )?;
+    Ok(())
+  }
+
+  fn session(&self, key: &str) -> Result<Option<String>, StoreError> {
+    let connection = lock(&self.connection);
+    Ok(
+      connection
+        .query_row(
+          "SELECT user_id FROM sessions WHERE key = ?1",
+          [key],
+          |row| row.get(0),
+        )
+        .optional()?,
+    )
+  }
+
+  fn delete_session(&self, key: &str) -> Result<(), StoreError> {
+    let connection = lock(&self.connection);
+    connection.execute("DELETE FROM sessions WHERE key = ?1", [key])?;
+    Ok(())
+  }
+
+  // Writes are committed as they're made, so only the page cache is left
+  fn flush(&self) -> Result<(), StoreError> {
+    lock(&self.connection).cache_flush()?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{path::PathBuf, time::Duration};
+
+  use super::*;
+  use crate::clock::{OnTimeout, TimeControl};
+
+  // A database file that is removed once the test is done
+  struct TempFile(PathBuf);
+
+  impl TempFile {
+    fn new() -> Self {
+      let name = format!("blokus-{}.sqlite3", uuid::Uuid::new_v4());
+      TempFile(std::env::temp_dir().join(name))
+    }
+  }
+
+  impl Drop for TempFile {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_file(&self.0);
+    }
+  }
+
+  fn account(name: &str, password: Option<&str>) -> Account {
+    Account {
+      user: User {
+        id: format!("id-{name}"),
+        name: name.to_string(),
+      },
+      password: password.map(str::to_string),
+    }
+  }
+
+  #[test]
+  fn test_accounts() {
+    let store = SqliteStore::new(Connection::open_in_memory().unwrap()).unwrap();
+    let alice = account("alice", Some("hash"));
+    store.insert_account(&alice).unwrap();
+    store.insert_account(&account("guest", None)).unwrap();
+    assert!(store.insert_account(&account("alice", None)).is_err());
+
+    let stored = store.account_by_name("alice").unwrap().unwrap();
+    assert_eq!(stored.user, alice.user);
+    assert_eq!(stored.password.as_deref(), Some("hash"));
+    assert_eq!(store.account("id-guest").unwrap().unwrap().password, None);
+    assert!(store.account("id-carol").unwrap().is_none());
+
+    store.insert_session("key", "id-alice").unwrap();
+    assert_eq!(store.session("key").unwrap().as_deref(), Some("id-alice"));
+    store.delete_session("key").unwrap();
+    assert_eq!(store.session("key").unwrap(), None);
+    // sessions belong to existing users
+    assert!(store.insert_session("key", "id-carol").is_err());
+  }
+
+  #[test]
+  fn test_games_survive_reopening() {
+    let file = TempFile::new();
+    let store = SqliteStore::open(&file.0).unwrap();
+    let (alice, bob) = (account("alice", None), account("bob", None));
+    store.insert_account(&alice).unwrap();
+    store.insert_account(&bob).unwrap();
+
+    let mut game = GameRecord::new("g".to_string(), VariantKind::Duo);
+    store.insert_game(&game).unwrap();
+    game.join(alice.user).unwrap();
+    store.update_game(&game).unwrap();
+    game.join(bob.user).unwrap();
+    for _ in 0..6 {
+      let color = game.position.color_to_move().unwrap();
+      let m = game.position.legal_moves()[0];
+      game.play(color, m).unwrap();
+      store.update_game(&game).unwrap();
+    }
+    let mut other = GameRecord::new("h".to_string(), VariantKind::Classic);
+    other.invite = Some("ABC234".to_string());
+    other.clock = Some(Clock {
+      control: TimeControl::Fischer {
+        initial: Duration::from_secs(300),
+        increment: Duration::from_secs(5),
+      },
+      on_timeout: OnTimeout::Forfeit,
+    });
+    store.insert_game(&other).unwrap();
+    assert!(store.insert_game(&other).is_err());
+    drop(store);
+
+    let store = SqliteStore::open(&file.0).unwrap();
+    let stored = store.game("g").unwrap().unwrap();
+    assert_eq!(stored.status, Status::Ongoing);
+    assert_eq!(stored.seats, game.seats);
+    assert_eq!(stored.moves, game.moves);
+    assert_eq!(stored.started_at, game.started_at);
+    assert_eq!(
+      serde_json::to_value(stored.to_json()).unwrap(),
+      serde_json::to_value(game.to_json()).unwrap()
+    );
+    assert_eq!(
+      store
+        .games()
+        .unwrap()
+        .iter()
+        .map(|g| g.id.as_str())
+        .collect::<Vec<_>>(),
+      ["g", "h"]
+    );
+    let ids = |games: Vec<GameRecord>| games.into_iter().map(|g| g.id).collect::<Vec<_>>();
+    assert_eq!(ids(store.ongoing_games().unwrap()), ["g"]);
+    // h waits for players, but only those with its invite
+    assert!(store.open_games().unwrap().is_empty());
+    store
+      .insert_game(&GameRecord::new("i".to_string(), VariantKind::Duo))
+      .unwrap();
+    assert_eq!(ids(store.open_games().unwrap()), ["i"]);
+    assert!(store.game("nope").unwrap().is_none());
+    let invited = store.game_by_invite("ABC234").unwrap().unwrap();
+    assert_eq!(invited.id, "h");
+    assert_eq!(invited.clock, other.clock);
+    assert!(store.game_by_invite("ABC235").unwrap().is_none());
+    assert!(store
+      .update_game(&GameRecord::new("nope".to_string(), VariantKind::Duo))
+      .is_err());
+  }
+
+  #[test]
+  fn test_ratings() {
+    let store = SqliteStore::new(Connection::open_in_memory().unwrap()).unwrap();
+    let (alice, guest) = (account("alice", Some("hash")), account("guest", None));
+    store.insert_account(&alice).unwrap();
+    store.insert_account(&guest).unwrap();
+    let mut game = GameRecord::new("g".to_string(), VariantKind::Duo);
+    store.insert_game(&game).unwrap();
+    game.join(alice.user.clone()).unwrap();
+    game.join(guest.user.clone()).unwrap();
+    game.end();
+    game.rating_changes = vec![
+      Some(RatingChange {
+        before: 1500.0,
+        delta: 16.0,
+      }),
+      Some(RatingChange {
+        before: 1500.0,
+        delta: -16.0,
+      }),
+    ];
+    let rating = |rating| Rating { rating, games: 1 };
+    let ratings = [
+      (alice.user.id.clone(), rating(1516.0)),
+      (guest.user.id.clone(), rating(1484.0)),
+    ];
+    store.end_game(&game, &ratings).unwrap();
+
+    assert_eq!(
+      store.game("g").unwrap().unwrap().rating_changes,
+      game.rating_changes
+    );
+    let variant = VariantKind::Duo;
+    assert_eq!(
+      store.rating(&guest.user.id, variant).unwrap(),
+      Some(rating(1484.0))
+    );
+    assert_eq!(
+      store.rating(&alice.user.id, VariantKind::Classic).unwrap(),
+      None
+    );
+    // guests aren't listed
+    let board = store.leaderboard(variant, 10).unwrap();
+    assert_eq!(board, vec![(alice.user, rating(1516.0))]);
+    assert!(store.leaderboard(variant, 0).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_newer_database() {
+    let mut connection = Connection::open_in_memory().unwrap();
+    connection
+      .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+      .unwrap();
+    assert!(migrate(&mut connection).is_err());
+  }
+}